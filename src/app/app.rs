@@ -1,8 +1,12 @@
 use echidna_lib::config::{Config, GroupBy, TerminalApp};
 use echidna_lib::generate;
-use echidna_lib::generate::{Generator, SaveErr};
+use echidna_lib::generate::{BundleIdOptions, Generator, Rank, Role, SaveErr, Target};
+use echidna_lib::icon_composer::{Color, ShimIcon, MAX_LABEL_CHARS};
 use echidna_lib::misc::get_app_resources;
-use echidna_lib::{bail, bailf, term};
+use echidna_lib::output::MemTree;
+use echidna_lib::shim::Shim;
+use echidna_lib::type_decl::TypeDecl;
+use echidna_lib::{bail, bailf, icon, replace, term};
 
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
@@ -21,10 +25,12 @@ use icns::IconFamily;
 use lazy_static::lazy_static;
 
 // All eyeballed.
-const INNER_HEIGHT: f32 = 230.0;
+const INNER_HEIGHT: f32 = 305.0;
 const MIN_INNER_SIZE: (f32, f32) = (500.0, INNER_HEIGHT);
 const MAX_INNER_SIZE: (f32, f32) = (700.0, INNER_HEIGHT);
 const MIN_HELP_INNER_SIZE: (f32, f32) = (400.0, 180.0);
+const MIN_PREVIEW_INNER_SIZE: (f32, f32) = (500.0, 300.0);
+const MIN_TYPE_FORM_INNER_SIZE: (f32, f32) = (400.0, 200.0);
 const WINDOW_PADDING: f32 = 20.0;
 const SECTION_SPACING: f32 = 20.0;
 const THUMBNAIL_SIZE: (f32, f32) = (128.0, 128.0);
@@ -65,7 +71,7 @@ impl DocTypes {
 lazy_static! {
     pub static ref SUPPORTED_EXTS: &'static [&'static str] = &[
         "jpg", "jpeg", "avif", "avif", "bmp", "dds", "exr", "gif", "hdr", "ico", "png", "pnm",
-        "qoi", "tga", "tif", "tiff", "webp", "icns",
+        "qoi", "tga", "tif", "tiff", "webp", "icns", "svg", "svgz",
     ];
 }
 
@@ -74,6 +80,7 @@ lazy_static! {
 #[derive(Clone)]
 pub struct Image {
     path: PathBuf,
+    uri: String, // Tells egui's loaders what the buffer holds.
     buffer: Bytes,
 }
 
@@ -82,11 +89,24 @@ impl Image {
 
     pub fn new(path: PathBuf, buffer: Vec<u8>) -> Image {
         Image {
+            uri: path.display().to_string(),
             path,
             buffer: Bytes::from(buffer),
         }
     }
 
+    // SVGs are rendered by the library, as they will be in the shim, rather than by egui.
+    fn load_svg(path: PathBuf) -> Result<Image, String> {
+        let source = icon::load_source(Some(&path))?;
+        let side = (THUMBNAIL_SIZE.0.max(THUMBNAIL_SIZE.1) * 2.0).round() as u32;
+        let buffer = icon::encode_png(&source.render(side))?;
+        Ok(Image {
+            uri: format!("{}.png", path.display()),
+            path,
+            buffer: Bytes::from(buffer),
+        })
+    }
+
     fn is_png(path: &Path) -> Result<bool, String> {
         let mut file =
             File::open(path).map_err(|e| format!("Error opening '{}': {e}", path.display()))?;
@@ -136,6 +156,9 @@ impl Image {
     }
 
     pub fn load(path: PathBuf) -> Result<Image, String> {
+        if let Some("svg" | "svgz") = path.extension().and_then(|x| x.to_str()) {
+            return Self::load_svg(path);
+        }
         if path.extension() == Some(OsStr::new("icns")) && !Self::is_png(&path)? {
             return Self::load_icns(path);
         }
@@ -153,7 +176,35 @@ impl Image {
     }
 
     pub fn to_egui_image(&self) -> egui::Image<'static> {
-        egui::Image::from_bytes(self.path.display().to_string(), self.buffer.clone())
+        egui::Image::from_bytes(self.uri.clone(), self.buffer.clone())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// The "Add Type" dialog's fields.
+#[derive(Default)]
+struct TypeForm {
+    identifier: String,
+    description: String,
+    extensions: String,
+    conforms_to: String,
+    icon: Option<PathBuf>,
+    error: String,
+}
+
+impl TypeForm {
+    fn to_decl(&self) -> Result<TypeDecl, String> {
+        let split = |x: &str| x.split(',').map(|x| x.to_owned()).collect();
+        TypeDecl {
+            identifier: self.identifier.clone(),
+            mime_type: String::new(),
+            description: self.description.clone(),
+            conforms_to: split(&self.conforms_to),
+            extensions: split(&self.extensions),
+            icon: self.icon.clone(),
+        }
+        .normalized(None)
     }
 }
 
@@ -166,9 +217,16 @@ struct EchidnaApp {
     doc_type: DocTypes,
     utis: String,
     exts: String,
+    doc_role: Role,
+    doc_rank: Option<Rank>,
+
+    custom_types: Vec<TypeDecl>,
+    type_form: Option<TypeForm>, // Shown in its own window while set.
 
     group_by: GroupBy,
 
+    make_default: bool,
+
     default_file_name: String,
     previous_name: Option<OsString>, // Previous name chosen by Save As
 
@@ -176,6 +234,15 @@ struct EchidnaApp {
     generic_terminal: String,
 
     custom_shim_icon: Option<Image>,
+    icon_label: String,
+    icon_tint: Option<egui::Color32>,
+    editing_label: bool,
+    label_error: String,
+    // The icon with its label and tint, as a URI for egui and PNG data, while there are any.
+    composed_thumbnail: Option<(String, Bytes)>,
+    composed_count: usize, // Keeps URIs unique, egui caches images by URI.
+
+    preview: Option<String>, // Shown in its own window while set.
 
     show_help: Arc<AtomicBool>,
     help_cache: Arc<Mutex<CommonMarkCache>>,
@@ -206,41 +273,67 @@ impl EchidnaApp {
         app
     }
 
-    fn generate_inner(&mut self) -> Result<(), String> {
+    // Validates the form, returning the shim's config and document types.
+    fn shim_settings(&self) -> Result<(Config, generate::DocTypes), String> {
         if self.cmd.is_empty() {
             bail!("Command must not be empty");
         }
 
-        let doc_type = match self.doc_type {
-            DocTypes::TextFiles => generate::DocTypes::TextFiles,
-            DocTypes::AllDocs => generate::DocTypes::AllDocs,
+        let mut doc_type = match self.doc_type {
+            DocTypes::TextFiles => generate::DocTypes::text_files(),
+            DocTypes::AllDocs => generate::DocTypes::all_docs(),
             DocTypes::UTIs => {
                 if self.utis.is_empty() {
                     bailf!("UTIs must not be empty");
                 }
-                generate::DocTypes::UTIs(self.utis.clone())
+                generate::DocTypes::utis(&self.utis)
             }
             DocTypes::Exts => {
                 if self.exts.is_empty() {
                     bailf!("Extensions must not be empty");
                 }
-                generate::DocTypes::Exts(self.exts.clone())
+                generate::DocTypes::exts(&self.exts)
             }
         };
+        for group in &mut doc_type.0 {
+            group.role = self.doc_role;
+            group.rank = self.doc_rank;
+        }
 
         if self.terminal == GENERIC && self.generic_terminal.is_empty() {
             return Err("Generic terminal must not be empty".to_string());
         }
 
+        let terminal = if self.terminal == GENERIC {
+            TerminalApp::Generic(self.generic_terminal.clone())
+        } else {
+            TerminalApp::Supported(self.terminal.clone())
+        };
+        let config = Config {
+            command: self.cmd.clone(),
+            group_open_by: self.group_by,
+            terminal,
+            dry_run: None,
+            icon: None,
+        };
+
+        Ok((config, doc_type))
+    }
+
+    fn file_name(&self) -> String {
+        self.previous_name
+            .as_ref()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or(self.default_file_name.clone())
+    }
+
+    fn generate_inner(&mut self) -> Result<(), String> {
+        let (config, doc_type) = self.shim_settings()?;
+
         // Shame to have to use to_string_lossy(), everwhere else, the filename is
         // an OsStr(ing). At least here the user has the chance  to fix it if it
         // gets mangled.
-        let dialog = rfd::FileDialog::new().set_file_name(
-            self.previous_name
-                .as_ref()
-                .map(|x| x.to_string_lossy().to_string())
-                .unwrap_or(self.default_file_name.clone()),
-        );
+        let dialog = rfd::FileDialog::new().set_file_name(self.file_name());
 
         let Some(app_path) = dialog.save_file() else {
             return Ok(());
@@ -252,35 +345,23 @@ impl EchidnaApp {
             }
         }
 
-        let terminal = if self.terminal == GENERIC {
-            TerminalApp::Generic(self.generic_terminal.clone())
-        } else {
-            TerminalApp::Supported(self.terminal.clone())
-        };
-        let config = Config {
-            command: self.cmd.clone(),
-            group_open_by: self.group_by,
-            terminal,
-        };
         let shim_path = get_shim_path()?;
 
         let mut gen = Generator::gen(
             &config,
             &doc_type,
             &shim_path,
-            None,
-            self.custom_shim_icon.as_ref().map(|x| &*x.path),
+            &BundleIdOptions::default(),
+            &self.shim_icon(),
             app_path.clone(),
+            Target::host(),
+            &self.custom_types,
+            None,
         )?;
         let res = gen.save(false);
 
         match res {
-            Ok(()) => {
-                if let Err(e) = opener::reveal(gen.final_bundle_path()) {
-                    eprintln!("{e}"); // No modal, failure here does no real harm.
-                }
-                return Ok(());
-            }
+            Ok(()) => return self.finish_save(&mut gen),
             Err(err) => match err {
                 SaveErr::Other(msg) => {
                     bail!(msg);
@@ -290,18 +371,29 @@ impl EchidnaApp {
         }
 
         // Couldn't write app because one already exists. Give user a chance to ovewrite.
+        const REPLACE: &str = "Replace";
+        const REPLACE_WITH_BACKUP: &str = "Replace, Keep Backup";
         let result = rfd::MessageDialog::new()
             .set_level(rfd::MessageLevel::Error)
             .set_title("Error: Destination already exists")
             .set_description(format!(
-                "Destination '{}' already exists. Overwrite?",
-                app_path.display()
+                "Destination '{}' already exists. Replace it? The current one can be kept as '{}'.",
+                app_path.display(),
+                replace::backup_path(gen.final_bundle_path()).display()
+            ))
+            .set_buttons(rfd::MessageButtons::YesNoCancelCustom(
+                REPLACE.to_owned(),
+                REPLACE_WITH_BACKUP.to_owned(),
+                "Cancel".to_owned(),
             ))
-            .set_buttons(rfd::MessageButtons::YesNo)
             .show();
 
-        if result == rfd::MessageDialogResult::No {
-            return Ok(());
+        match result {
+            rfd::MessageDialogResult::Custom(x) if x == REPLACE => (),
+            rfd::MessageDialogResult::Custom(x) if x == REPLACE_WITH_BACKUP => {
+                gen.keep_backup(true)
+            }
+            _ => return Ok(()),
         }
 
         let res = gen.save(true);
@@ -314,11 +406,26 @@ impl EchidnaApp {
             }
         };
 
+        self.finish_save(&mut gen)
+    }
+
+    // Everything after a successful save.
+    fn finish_save(&self, gen: &mut Generator) -> Result<(), String> {
+        let res = if self.make_default {
+            gen.make_default()
+        } else {
+            Ok(())
+        };
+
+        for warning in gen.warnings() {
+            eprintln!("Warning: {warning}"); // No modal, nothing's gone wrong.
+        }
+
         if let Err(e) = opener::reveal(gen.final_bundle_path()) {
             eprintln!("{e}"); // No modal, failure here does no real harm.
         }
 
-        Ok(())
+        res
     }
 
     fn generate(&mut self) {
@@ -327,6 +434,37 @@ impl EchidnaApp {
         }
     }
 
+    // Generates in memory, to show what Save As would write.
+    fn preview_inner(&mut self) -> Result<(), String> {
+        let (config, doc_type) = self.shim_settings()?;
+
+        let gen = Generator::gen_into(
+            MemTree::new(),
+            &config,
+            &doc_type,
+            &get_shim_path()?,
+            &BundleIdOptions::default(),
+            &self.shim_icon(),
+            PathBuf::from(self.file_name()),
+            Target::host(),
+            &self.custom_types,
+            None,
+        )?;
+
+        let mut preview = gen.preview();
+        for warning in gen.warnings() {
+            preview += &format!("\nWarning: {warning}");
+        }
+        self.preview = Some(preview);
+        Ok(())
+    }
+
+    fn preview(&mut self) {
+        if let Err(e) = self.preview_inner() {
+            modal(e);
+        }
+    }
+
     fn update_default_file_name(&mut self) {
         let word = self.cmd.split_whitespace().next();
         let word = match word {
@@ -379,6 +517,122 @@ impl EchidnaApp {
         });
     }
 
+    fn draw_preview(&mut self, ctx: &egui::Context) {
+        let Some(preview) = &self.preview else {
+            return;
+        };
+
+        let vb = egui::viewport::ViewportBuilder::default()
+            .with_title("Preview")
+            .with_min_inner_size(MIN_PREVIEW_INNER_SIZE);
+        let vid = egui::viewport::ViewportId::from_hash_of("preview window");
+        let mut close = false;
+        ctx.show_viewport_immediate(vid, vb, |ctx, _| {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                if ctx.input(|i| i.viewport().close_requested()) {
+                    close = true;
+                    return;
+                }
+
+                egui::ScrollArea::both().show(ui, |ui| {
+                    ui.monospace(preview);
+                });
+            });
+        });
+
+        if close {
+            self.preview = None;
+        }
+    }
+
+    fn draw_type_form(&mut self, ctx: &egui::Context) {
+        let Some(form) = &mut self.type_form else {
+            return;
+        };
+
+        let vb = egui::viewport::ViewportBuilder::default()
+            .with_title("Add Type")
+            .with_min_inner_size(MIN_TYPE_FORM_INNER_SIZE);
+        let vid = egui::viewport::ViewportId::from_hash_of("type form window");
+        let mut add = None;
+        ctx.show_viewport_immediate(vid, vb, |ctx, _| {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                if ctx.input(|i| i.viewport().close_requested()) {
+                    add = Some(false);
+                    return;
+                }
+
+                Grid::new("Type Form").num_columns(2).show(ui, |ui| {
+                    ui.label("Identifier:");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut form.identifier)
+                            .hint_text("com.example.foo"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Description:");
+                    ui.add(egui::TextEdit::singleline(&mut form.description));
+                    ui.end_row();
+
+                    ui.label("Extensions:");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut form.extensions)
+                            .hint_text("Comma-delimited"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Conforms To:")
+                        .on_hover_text("UTIs the type is a kind of.");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut form.conforms_to).hint_text("public.data"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Icon:");
+                    ui.horizontal(|ui| {
+                        match form.icon.as_ref().and_then(|x| x.file_name()) {
+                            Some(name) => ui.label(name.to_string_lossy()),
+                            None => ui.weak("None"),
+                        };
+                        if ui.button("Select…").clicked() {
+                            form.icon = rfd::FileDialog::new()
+                                .add_filter("image", *SUPPORTED_EXTS)
+                                .pick_file();
+                        }
+                    });
+                    ui.end_row();
+                });
+
+                if !form.error.is_empty() {
+                    ui.colored_label(ui.visuals().error_fg_color, &form.error);
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Add").clicked() {
+                        add = Some(true);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        add = Some(false);
+                    }
+                });
+            });
+        });
+
+        match add {
+            Some(true) => match form.to_decl() {
+                Ok(decl) => {
+                    self.custom_types
+                        .retain(|x| x.identifier != decl.identifier);
+                    self.custom_types.push(decl);
+                    self.type_form = None;
+                }
+                Err(e) => form.error = e,
+            },
+            Some(false) => self.type_form = None,
+            None => (),
+        }
+    }
+
     fn draw_form(&mut self, ui: &mut egui::Ui) {
         Grid::new("Form").num_columns(2).show(ui, |ui| {
             ui.label("Command:")
@@ -429,6 +683,64 @@ impl EchidnaApp {
             });
             ui.end_row();
 
+            ui.label("Role:").on_hover_text(
+                "How the shim handles the documents, and how strongly it claims them (Automatic \
+                leaves it to macOS).",
+            );
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("Document Role Combo Box")
+                    .selected_text(self.doc_role.to_string())
+                    .show_ui(ui, |ui| {
+                        for role in [Role::Editor, Role::Viewer, Role::Shell, Role::None] {
+                            ui.selectable_value(&mut self.doc_role, role, role.to_string());
+                        }
+                    });
+
+                let rank_name = |x: Option<Rank>| x.map_or("Automatic".into(), |x| x.to_string());
+                egui::ComboBox::from_id_source("Document Rank Combo Box")
+                    .selected_text(rank_name(self.doc_rank))
+                    .show_ui(ui, |ui| {
+                        for rank in [
+                            None,
+                            Some(Rank::Owner),
+                            Some(Rank::Default),
+                            Some(Rank::Alternate),
+                            Some(Rank::None),
+                        ] {
+                            ui.selectable_value(&mut self.doc_rank, rank, rank_name(rank));
+                        }
+                    });
+            });
+            ui.end_row();
+
+            ui.label("Custom Types:")
+                .on_hover_text("Your own file types, declared by the shim, which it also opens.");
+            ui.horizontal(|ui| {
+                if self.custom_types.is_empty() {
+                    ui.weak("None");
+                } else {
+                    let identifiers: Vec<_> = self
+                        .custom_types
+                        .iter()
+                        .map(|x| x.identifier.as_str())
+                        .collect();
+                    ui.label(identifiers.join(", "));
+                }
+
+                if ui.button("Add Type…").clicked() {
+                    self.type_form = Some(TypeForm::default());
+                }
+
+                let clear = egui::Button::new("Clear");
+                if ui
+                    .add_enabled(!self.custom_types.is_empty(), clear)
+                    .clicked()
+                {
+                    self.custom_types.clear();
+                }
+            });
+            ui.end_row();
+
             ui.end_row(); // Spacer
 
             ui.label("Terminal:");
@@ -469,6 +781,14 @@ impl EchidnaApp {
                 ui.radio_value(&mut self.group_by, GroupBy::None, "Individually");
             });
             ui.end_row();
+
+            ui.label("Default App:")
+                .on_hover_text("Make the shim the default app for its documents.");
+            ui.checkbox(
+                &mut self.make_default,
+                "Open these documents with it by default",
+            );
+            ui.end_row();
         });
     }
 
@@ -493,10 +813,81 @@ impl EchidnaApp {
         };
     }
 
+    fn shim_icon(&self) -> ShimIcon {
+        let label = self.icon_label.trim();
+        ShimIcon {
+            path: self.custom_shim_icon.as_ref().map(|x| x.path.clone()),
+            label: (!label.is_empty()).then(|| label.to_owned()),
+            tint: self.icon_tint.map(|x| Color(x.to_array())),
+            badge: None,
+        }
+    }
+
+    // Re-renders the thumbnail after the icon, label or tint changes.
+    fn update_composed_thumbnail(&mut self, ctx: &egui::Context) {
+        if let Some((uri, _)) = self.composed_thumbnail.take() {
+            ctx.forget_image(&uri);
+        }
+        self.label_error.clear();
+
+        let icon = self.shim_icon();
+        if !icon.is_composed() {
+            return;
+        }
+
+        let side = (THUMBNAIL_SIZE.0.max(THUMBNAIL_SIZE.1) * 2.0).round() as u32;
+        match icon.preview(side).and_then(|x| icon::encode_png(&x)) {
+            Ok(png) => {
+                self.composed_count += 1;
+                let uri = format!("bytes://composed-icon-{}.png", self.composed_count);
+                self.composed_thumbnail = Some((uri, Bytes::from(png)));
+            }
+            Err(e) => self.label_error = e,
+        }
+    }
+
+    fn draw_label_editor(&mut self, ui: &mut egui::Ui) {
+        let mut changed = false;
+
+        let label = egui::TextEdit::singleline(&mut self.icon_label)
+            .hint_text("Label")
+            .char_limit(MAX_LABEL_CHARS)
+            .desired_width(THUMBNAIL_SIZE.0);
+        changed |= ui.add(label).changed();
+
+        ui.horizontal(|ui| {
+            let mut tinted = self.icon_tint.is_some();
+            if ui.checkbox(&mut tinted, "Tint").changed() {
+                self.icon_tint = tinted.then_some(egui::Color32::from_rgb(0x3b, 0x82, 0xf0));
+                changed = true;
+            }
+            if let Some(tint) = &mut self.icon_tint {
+                changed |= egui::color_picker::color_edit_button_srgba(
+                    ui,
+                    tint,
+                    egui::color_picker::Alpha::Opaque,
+                )
+                .changed();
+            }
+        });
+
+        if !self.label_error.is_empty() {
+            ui.colored_label(ui.visuals().error_fg_color, &self.label_error);
+        }
+
+        if changed {
+            self.update_composed_thumbnail(ui.ctx());
+        }
+    }
+
     fn draw_icon_column(&mut self, ui: &mut egui::Ui) {
         ui.vertical_centered(|ui| {
             let img_bg = ui.visuals().widgets.inactive.weak_bg_fill;
-            ui.add(if let Some(img) = &self.custom_shim_icon {
+            ui.add(if let Some((uri, png)) = &self.composed_thumbnail {
+                egui::Image::from_bytes(uri.clone(), png.clone())
+                    .fit_to_exact_size(THUMBNAIL_SIZE.into())
+                    .bg_fill(img_bg)
+            } else if let Some(img) = &self.custom_shim_icon {
                 img.to_egui_image()
                     .fit_to_exact_size(THUMBNAIL_SIZE.into())
                     .bg_fill(img_bg)
@@ -506,9 +897,19 @@ impl EchidnaApp {
                     .bg_fill(img_bg)
             });
 
-            if ui.button("Select…").clicked() {
-                self.change_shim_icon(None);
-            }
+            ui.horizontal(|ui| {
+                if ui.button("Select…").clicked() {
+                    self.change_shim_icon(None);
+                    self.update_composed_thumbnail(ui.ctx());
+                }
+                if ui
+                    .selectable_label(self.editing_label, "Label…")
+                    .on_hover_text("Draw a label or a tint over the icon.")
+                    .clicked()
+                {
+                    self.editing_label = !self.editing_label;
+                }
+            });
 
             let reset_button = egui::Button::new("Default Icon");
             if ui
@@ -516,18 +917,118 @@ impl EchidnaApp {
                 .clicked()
             {
                 self.custom_shim_icon = None;
+                self.update_composed_thumbnail(ui.ctx());
+            }
+
+            if self.editing_label {
+                self.draw_label_editor(ui);
             }
         });
     }
 
+    // Fills in the form from an existing shim, to regenerate it with changes.
+    fn open_shim(&mut self, ctx: &egui::Context, path: Option<PathBuf>) {
+        let path = path.or_else(|| {
+            rfd::FileDialog::new()
+                .add_filter("app", &["app"])
+                .pick_file()
+        });
+
+        let Some(path) = path else {
+            // Pressed cancel.
+            return;
+        };
+
+        let shim = match Shim::load(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                modal(format!("Error opening '{}': {e}", path.display()));
+                return;
+            }
+        };
+
+        self.cmd.clone_from(&shim.config.command);
+        self.update_default_file_name();
+        self.previous_name = match shim.target {
+            Target::MacOs => path.file_name().map(|x| x.to_owned()),
+            Target::Linux => Some(shim.name.clone().into()),
+        };
+
+        match &shim.config.terminal {
+            TerminalApp::Supported(name) => self.terminal.clone_from(name),
+            TerminalApp::Generic(name) => {
+                GENERIC.clone_into(&mut self.terminal);
+                self.generic_terminal.clone_from(name);
+            }
+        }
+        self.group_by = shim.config.group_open_by;
+        self.custom_types = shim.type_decls.clone();
+
+        // The form only has room for one group of either UTIs or extensions.
+        let groups = shim.doc_types.groups();
+        if let Some(first) = groups.first() {
+            self.doc_role = first.role;
+            self.doc_rank = first.rank;
+        }
+        let (utis, exts) = (shim.doc_types.all_utis(), shim.doc_types.all_exts());
+        if shim.doc_types == generate::DocTypes::text_files() {
+            self.doc_type = DocTypes::TextFiles;
+        } else if shim.doc_types == generate::DocTypes::all_docs() {
+            self.doc_type = DocTypes::AllDocs;
+        } else if !utis.is_empty() {
+            self.doc_type = DocTypes::UTIs;
+            self.utis = utis.join(", ");
+        } else {
+            self.doc_type = DocTypes::Exts;
+            self.exts = exts.join(", ");
+        }
+        let icons = groups.iter().any(|x| x.icon.is_some());
+        let mime_types = groups.iter().any(|x| !x.mime_types.is_empty());
+        if groups.len() > 1 || (!utis.is_empty() && !exts.is_empty()) || icons || mime_types {
+            modal(format!(
+                "'{}' has document types the form can't show, saving will replace them with \
+                those shown. Use echidna-cli to keep them.",
+                path.display()
+            ));
+        }
+
+        self.custom_shim_icon = None;
+        self.icon_label.clear();
+        self.icon_tint = None;
+        match shim.icon_source() {
+            // The form can't show a badge, so that icon is kept as it is.
+            Ok(source) if source.badge.is_none() => {
+                if let Some(path) = source.path {
+                    self.change_shim_icon(Some(path));
+                }
+                self.icon_label = source.label.unwrap_or_default();
+                self.icon_tint = source
+                    .tint
+                    .map(|Color([r, g, b, a])| egui::Color32::from_rgba_premultiplied(r, g, b, a));
+            }
+            // The label and tint are already part of the icon.
+            _ => {
+                if let Some(icon) = shim.icon {
+                    self.change_shim_icon(Some(icon));
+                }
+            }
+        }
+        self.update_composed_thumbnail(ctx);
+    }
+
     fn handle_dropped_files(&mut self, ctx: &egui::Context) {
-        ctx.input(|i| {
-            if !i.raw.dropped_files.is_empty() {
-                let file = &i.raw.dropped_files[0];
-                assert!(file.path.is_some());
-                self.change_shim_icon(file.path.clone());
+        // Not inside input(), which would deadlock updating the thumbnail.
+        let dropped = ctx.input(|i| i.raw.dropped_files.first().map(|x| x.path.clone()));
+        if let Some(path) = dropped {
+            assert!(path.is_some());
+            // An app (a directory) is a shim to open, anything else an icon.
+            if path.as_ref().is_some_and(|x| x.is_dir()) {
+                self.open_shim(ctx, path);
+            } else {
+                self.change_shim_icon(path);
+                self.update_composed_thumbnail(ctx);
             }
-        });
+        }
     }
 
     fn draw(&mut self, ui: &mut egui::Ui) {
@@ -551,6 +1052,16 @@ impl EchidnaApp {
                     if ui.button("Save As…").clicked() {
                         self.generate();
                     }
+                    if ui.button("Preview…").clicked() {
+                        self.preview();
+                    }
+                    if ui
+                        .button("Open…")
+                        .on_hover_text("Fill in the form from an existing shim.")
+                        .clicked()
+                    {
+                        self.open_shim(ui.ctx(), None);
+                    }
                 });
             });
         });
@@ -563,6 +1074,8 @@ impl eframe::App for EchidnaApp {
         egui::CentralPanel::default().frame(frame).show(ctx, |ui| {
             self.draw(ui);
             self.draw_help(ctx);
+            self.draw_preview(ctx);
+            self.draw_type_form(ctx);
         });
 
         self.handle_dropped_files(ctx);
@@ -1,20 +1,256 @@
+use echidna_lib::archive::{self, Format};
 use echidna_lib::config::{Config, GroupBy, TerminalApp};
-use echidna_lib::generate::{DocTypes, Generator};
-use echidna_lib::{bail, bailf, term};
+use echidna_lib::generate::{
+    self, BundleIdOptions, DocTypes, Generator, Rank, Role, Status, Target,
+};
+use echidna_lib::icon_composer::{Color, ShimIcon};
+use echidna_lib::misc::format_unix_time;
+use echidna_lib::output::MemTree;
+use echidna_lib::recipe::{self, Manifest, Outcome, Recipe};
+use echidna_lib::shim::{self, Shim, ShimEdit};
+use echidna_lib::{
+    bail, bailf, defaults, info_plist, platypus, registry, term, type_decl, upgrade,
+};
 
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
-/// Generate a shim app.
+/// Generate a shim app, or manage existing ones.
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    subcommand: Option<Command>,
+
+    #[command(flatten)]
+    generate: Option<Args>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Restore the default handlers that a shim replaced with --make-default.
+    RestoreDefaults {
+        /// The shim's path, bundle identifier or desktop file name.
+        shim: String,
+    },
+
+    /// Describe an existing shim: its command, terminal, document types, identity and icon.
+    Inspect {
+        /// The .app bundle, or for Linux, the desktop entry or data directory.
+        path: PathBuf,
+
+        /// Print it as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Check a shim for what commonly breaks one: its binary, Info.plist, icon, config, command,
+    /// UTIs and bundle identifier.
+    Verify {
+        /// The .app bundle, or for Linux, the desktop entry or data directory.
+        path: PathBuf,
+
+        /// Print the results as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List generated shims, and any others found in /Applications, ~/Applications and
+    /// $XDG_DATA_HOME/applications.
+    List {
+        /// Print them as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Remove a shim and everything installed with it, restoring the default handlers it replaced.
+    Uninstall {
+        /// The shim's name, path or bundle identifier.
+        shim: String,
+    },
+
+    /// Change an existing shim in place, keeping its binary, bundle identifier and icon unless
+    /// they're changed too.
+    Edit(EditArgs),
+
+    /// Replace the shim binary in existing shims with this version's, and bring their config up to
+    /// date.
+    Upgrade {
+        /// Shims to upgrade: .app bundles, or for Linux, desktop entries or data directories.
+        #[arg(required_unless_present = "all")]
+        paths: Vec<PathBuf>,
+
+        /// Upgrade every shim in /Applications, ~/Applications and $XDG_DATA_HOME.
+        #[arg(long, conflicts_with = "paths")]
+        all: bool,
+
+        /// Downgrade shims newer than the shim binary, instead of leaving them as they are.
+        #[arg(long, short, action)]
+        force: bool,
+
+        /// Report what would change without changing anything.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Path to the shim binary. [default: same directory as echidna-cli]
+        #[arg(long)]
+        shim_path: Option<PathBuf>,
+    },
+
+    /// Generate a shim from a recipe: a TOML (or .json) file with the same settings as the
+    /// arguments, e.g. command = "vim". Leaves the shim alone if nothing changed.
+    Build {
+        /// The recipe. The shim is named after the file unless it sets name.
+        recipe: PathBuf,
+
+        /// Where to put the app. Ignored for Linux shims. [default: ~/Applications]
+        #[arg(long)]
+        dest: Option<PathBuf>,
+
+        /// Overwrite a shim that wasn't built from this recipe, or anything else in the way.
+        #[arg(long, short, action)]
+        force: bool,
+
+        /// Path to the shim binary. [default: same directory as echidna-cli]
+        #[arg(long)]
+        shim_path: Option<PathBuf>,
+    },
+
+    /// Make the shims in a manifest, a [defaults] table and [[shims]] recipes, exist and be up
+    /// to date, and remove those an earlier sync made that it no longer lists.
+    Sync {
+        /// The manifest, TOML or .json.
+        manifest: PathBuf,
+
+        /// Where to put the apps. Ignored for Linux shims. [default: ~/Applications]
+        #[arg(long)]
+        dest: Option<PathBuf>,
+
+        /// Overwrite shims that weren't synced from this manifest, or anything else in the way.
+        #[arg(long, short, action)]
+        force: bool,
+
+        /// Report what would change without changing anything.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Path to the shim binary. [default: same directory as echidna-cli]
+        #[arg(long)]
+        shim_path: Option<PathBuf>,
+    },
+
+    /// Pack a shim into a reproducible archive, with the recipe to regenerate it.
+    Export {
+        /// The .app bundle, or for Linux, the desktop entry or data directory.
+        path: PathBuf,
+
+        /// Archive format. [default: going by --output, or zip]
+        #[arg(long)]
+        format: Option<Format>,
+
+        /// Where to write the archive. [default: <name>.<format> in the current directory]
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+
+    /// Generate a shim equivalent to a Platypus app or .platypus profile, and report the settings
+    /// that couldn't be carried over.
+    ImportPlatypus(ImportPlatypusArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct ImportPlatypusArgs {
+    /// The Platypus .app bundle, or .platypus profile.
+    path: PathBuf,
+
+    /// Where to put the app. Ignored for Linux shims. [default: ~/Applications]
+    #[arg(long)]
+    dest: Option<PathBuf>,
+
+    /// Kind of shim to generate. [default: the host platform]
+    #[arg(long, default_value_t = Target::host(), hide_default_value = true)]
+    target: Target,
+
+    /// Terminal app to open in.
+    #[arg(long)]
+    terminal: Option<String>,
+
+    /// An unsupported terminal to (attempt) to use by sending keystrokes.
+    #[arg(long, conflicts_with = "terminal")]
+    generic_terminal: Option<String>,
+
+    /// Overwrite a shim that wasn't imported from this app or profile, or anything else in the way.
+    #[arg(long, short, action)]
+    force: bool,
+
+    /// Report what would be generated without saving anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Path to the shim binary. [default: same directory as echidna-cli]
+    #[arg(long)]
+    shim_path: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct EditArgs {
+    /// The .app bundle, or for Linux, the desktop entry or data directory.
+    path: PathBuf,
+
+    /// The terminal program to execute.
+    #[arg(long)]
+    command: Option<String>,
+
+    /// all: open together. none: one per window.
+    #[arg(long)]
+    group_open_by: Option<GroupBy>,
+
+    /// Terminal app to open in.
+    #[arg(long)]
+    terminal: Option<String>,
+
+    /// An unsupported terminal to (attempt) to use by sending keystrokes.
+    #[arg(long, conflicts_with = "terminal")]
+    generic_terminal: Option<String>,
+
+    /// A new bundle identifier. macOS only.
+    #[arg(long)]
+    bundle_id: Option<String>,
+
+    /// A new icon.
+    #[arg(long)]
+    icon: Option<PathBuf>,
+
+    /// A label drawn over the icon, replacing the current label. The icon is made again from
+    /// what it was generated from, unless --icon is passed.
+    #[arg(long, value_name = "TEXT")]
+    icon_label: Option<String>,
+
+    /// A color to tint the icon with, replacing the current tint.
+    #[arg(long, value_name = "COLOR")]
+    icon_tint: Option<Color>,
+
+    /// An image drawn small in the icon's top right corner, replacing the current badge.
+    #[arg(long, value_name = "FILE")]
+    icon_badge: Option<PathBuf>,
+}
+
+/// Generate a shim app.
+#[derive(clap::Args, Debug)]
 struct Args {
     /// The terminal program to execute.
     command: String,
 
-    /// Path to new app, including app name.
+    /// Path to new app, including app name. For --target linux, only the name is used, and the
+    /// shim is installed in $XDG_DATA_HOME.
     out_path: PathBuf,
 
+    /// Kind of shim to generate. [default: the host platform]
+    #[arg(long, default_value_t = Target::host(), hide_default_value = true)]
+    target: Target,
+
     /// all: open together. none: one per window.
     #[arg(long, default_value_t = Default::default())]
     group_open_by: GroupBy,
@@ -23,19 +259,46 @@ struct Args {
     #[arg(long)]
     shim_path: Option<String>,
 
-    /// Bundle Identifier. [default: Autogenerated]
+    /// Bundle Identifier. [default: the existing shim's, unless --bundle-id-prefix or
+    /// --bundle-id-seed is passed, or <prefix>.<name>]
     #[arg(long)]
     bundle_id: Option<String>,
 
+    /// Start of a derived bundle identifier. [default: local.<hostname>]
+    #[arg(long, value_name = "PREFIX", conflicts_with = "bundle_id")]
+    bundle_id_prefix: Option<String>,
+
+    /// Used instead of the app's name to derive its bundle identifier.
+    #[arg(long, value_name = "SEED", conflicts_with = "bundle_id")]
+    bundle_id_seed: Option<String>,
+
     /// Overwrite existing.
     #[arg(long, short, action)]
     force: bool,
 
+    /// When overwriting, keep the previous shim as <Name>.app.bak.
+    #[arg(long, requires = "force")]
+    backup: bool,
+
+    /// Make the shim the default app for its document types. Undo with restore-defaults.
+    #[arg(long, action)]
+    make_default: bool,
+
+    /// Print what would be written where, without saving anything.
+    #[arg(long, action, conflicts_with = "make_default")]
+    dry_run: bool,
+
+    /// Also pack the saved shim into an archive, .zip, .tar.gz or .tgz, as export does.
+    #[arg(long, value_name = "FILE", conflicts_with = "dry_run")]
+    archive: Option<PathBuf>,
+
     /// Terminal app to open in. Mutually exclusive with --generic-terminal.
     #[arg(
         long,
-        help = String::from("Terminal app in which to open. Supported: ")
-            + term::supported_terminals_string().as_str()
+        help = String::from("Terminal app in which to open. Supported for macOS: ")
+            + term::supported_terminals_string_for(Target::MacOs).as_str()
+            + ". Supported for Linux: "
+            + term::supported_terminals_string_for(Target::Linux).as_str()
     )]
     terminal: Option<String>,
 
@@ -44,10 +307,39 @@ struct Args {
     #[arg(long)]
     generic_terminal: Option<String>,
 
+    /// Make the shim record what it would run to this file as JSON lines ('-' for stdout) instead
+    /// of opening a terminal. Can also be set when running the shim with ECH_SHIM_DRY_RUN.
+    #[arg(long)]
+    shim_dry_run: Option<PathBuf>,
+
     /// A custom icon for the shim app.
     #[arg(long)]
     icon: Option<PathBuf>,
 
+    /// A short label drawn over the icon, e.g. "vim", to tell shims apart in the Dock.
+    #[arg(long, value_name = "TEXT")]
+    icon_label: Option<String>,
+
+    /// A color to tint the icon with: #rrggbb, or red, orange, yellow, green, teal, blue, purple,
+    /// pink, gray, black or white.
+    #[arg(long, value_name = "COLOR")]
+    icon_tint: Option<Color>,
+
+    /// An image drawn small in the icon's top right corner.
+    #[arg(long, value_name = "FILE")]
+    icon_badge: Option<PathBuf>,
+
+    /// Declare your own file types for the shim to open, from a JSON array of objects with
+    /// "identifier", "extensions", and optionally "conforms_to", "description", "mime_type" and
+    /// "icon".
+    #[arg(long, value_name = "FILE")]
+    declare_types: Option<PathBuf>,
+
+    /// Keys to add to (or replace in) the shim's Info.plist, as a JSON object or a plist
+    /// dictionary. macOS only.
+    #[arg(long, value_name = "FILE")]
+    extra_info_plist: Option<PathBuf>,
+
     /// Support opening all text files (default). Only one --docs-* may be passed.
     #[arg(long, group = "document-type")]
     docs_text_files: bool,
@@ -63,11 +355,464 @@ struct Args {
     /// A comma-delimited list of extensions to support opening. Only one --docs-* may be passed.
     #[arg(long, group = "document-type")]
     docs_exts: Option<String>,
+
+    /// Document type groups, as a JSON array of {"name", "utis", "exts", "role", "rank", "icon"}
+    /// objects, each needing "utis" or "exts". Only one --docs-* may be passed.
+    #[arg(long, group = "document-type", value_name = "FILE")]
+    docs_groups: Option<PathBuf>,
+
+    /// How the shim handles the documents of the other --docs-* flags.
+    #[arg(long, value_enum, default_value_t = Role::Editor, conflicts_with = "docs_groups")]
+    docs_role: Role,
+
+    /// How strongly the shim claims the documents of the other --docs-* flags. Left to macOS if
+    /// not passed.
+    #[arg(long, value_enum, conflicts_with = "docs_groups")]
+    docs_rank: Option<Rank>,
 }
 
-fn run() -> Result<(), String> {
-    let args = Args::parse();
+fn print_warnings(warnings: &[String]) {
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+}
+
+fn restore_defaults(shim: &str) -> Result<(), String> {
+    let warnings = defaults::restore(shim)?;
+    print_warnings(&warnings);
+    Ok(())
+}
+
+fn list(json: bool) -> Result<(), String> {
+    let listed = registry::list()?;
+    if json {
+        let entries: Vec<_> = listed
+            .iter()
+            .map(|x| {
+                let mut entry = serde_json::to_value(&x.entry).unwrap_or_default();
+                entry["untracked"] = x.untracked.into();
+                entry["missing"] = x.missing.into();
+                entry
+            })
+            .collect();
+        let json = serde_json::to_string_pretty(&entries)
+            .map_err(|e| format!("Error serializing shims: {e}"))?;
+        println!("{json}");
+        return Ok(());
+    }
+
+    if listed.is_empty() {
+        println!("No shims found");
+        return Ok(());
+    }
+
+    for x in &listed {
+        let entry = &x.entry;
+        let status = match (x.untracked, x.missing) {
+            (true, _) => " (untracked)",
+            (_, true) => " (missing)",
+            _ => "",
+        };
+        println!("{} [{}]{status}", entry.name, entry.target);
+        println!("  path:     {}", entry.path.display());
+        println!("  command:  {}", entry.command);
+        if let Some(id) = &entry.bundle_id {
+            println!("  id:       {id}");
+        }
+        if !x.untracked {
+            println!("  created:  {} UTC", format_unix_time(entry.created));
+            println!("  updated:  {} UTC", format_unix_time(entry.updated));
+        }
+    }
+    Ok(())
+}
+
+fn uninstall(shim: &str) -> Result<(), String> {
+    let (removed, warnings) = registry::uninstall(shim)?;
+    for path in &removed {
+        println!("Removed {}", path.display());
+    }
+    print_warnings(&warnings);
+    Ok(())
+}
+
+fn inspect(path: &Path, json: bool) -> Result<(), String> {
+    let shim = Shim::load(path)?;
+    if json {
+        let json = serde_json::to_string_pretty(&shim)
+            .map_err(|e| format!("Error serializing shim: {e}"))?;
+        println!("{json}");
+        return Ok(());
+    }
 
+    let or_none = |x: &Option<String>| x.clone().unwrap_or_else(|| "-".to_owned());
+    let terminal = match &shim.config.terminal {
+        TerminalApp::Supported(name) => name.clone(),
+        TerminalApp::Generic(name) => format!("{name} (generic)"),
+    };
+
+    println!("Name:              {}", shim.name);
+    println!("Path:              {}", shim.path.display());
+    println!("Target:            {}", shim.target);
+    println!("Command:           {}", shim.config.command);
+    println!("Terminal:          {terminal}");
+    println!("Group open by:     {}", shim.config.group_open_by);
+    if let Some(dry_run) = &shim.config.dry_run {
+        println!("Dry run to:        {}", dry_run.display());
+    }
+    if shim.target == Target::MacOs {
+        println!("Bundle identifier: {}", or_none(&shim.bundle_id));
+        println!("Build number:      {}", or_none(&shim.version));
+    }
+    println!(
+        "Generated by:      Echidna {}",
+        or_none(&shim.generator_version)
+    );
+    match &shim.icon {
+        Some(icon) => println!("Icon:              {}", icon.display()),
+        None => println!("Icon:              default"),
+    }
+
+    println!("Document types:");
+    if shim.doc_types.groups().is_empty() {
+        println!("  none");
+    }
+    for group in shim.doc_types.groups() {
+        let mut line = format!("  {}", group.name.as_deref().unwrap_or("(unnamed)"));
+        line += &format!(", role {}", group.role);
+        if let Some(rank) = group.rank {
+            line += &format!(", rank {rank}");
+        }
+        println!("{line}");
+        if !group.utis.is_empty() {
+            println!("    types:      {}", group.utis.join(", "));
+        }
+        if !group.exts.is_empty() {
+            println!("    extensions: {}", group.exts.join(", "));
+        }
+        if !group.mime_types.is_empty() {
+            println!("    MIME types: {}", group.mime_types.join(", "));
+        }
+        if let Some(icon) = &group.icon {
+            println!("    icon:       {}", icon.display());
+        }
+    }
+
+    if !shim.type_decls.is_empty() {
+        println!("Declared types:");
+        for decl in &shim.type_decls {
+            println!(
+                "  {} ({}): {}",
+                decl.identifier,
+                decl.mime_type,
+                decl.extensions.join(", ")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn verify(path: &Path, json: bool) -> Result<(), String> {
+    let checks = generate::verify(path)?;
+    if json {
+        let json = serde_json::to_string_pretty(&checks)
+            .map_err(|e| format!("Error serializing checks: {e}"))?;
+        println!("{json}");
+    } else {
+        for check in &checks {
+            println!("{:<4}  {}: {}", check.status, check.name, check.detail);
+        }
+    }
+
+    let count = |status| checks.iter().filter(|x| x.status == status).count();
+    let failed = count(Status::Fail);
+    if !json {
+        println!(
+            "{} passed, {} warnings, {failed} failed",
+            count(Status::Pass),
+            count(Status::Warn)
+        );
+    }
+    if failed > 0 {
+        bailf!("{failed} check(s) failed");
+    }
+    Ok(())
+}
+
+// The given shim binary, or the one next to echidna-cli.
+fn shim_path(given: Option<PathBuf>) -> Result<PathBuf, String> {
+    let shim_path = match given {
+        Some(x) => x,
+        None => {
+            let mut path =
+                std::env::current_exe().map_err(|e| format!("Failed to get current exe: {e}"))?;
+            if !path.pop() {
+                bailf!(
+                    "Couldn't pop binary filename from path '{}' !?",
+                    path.display()
+                );
+            }
+            path.push("echidna-shim");
+            path
+        }
+    };
+
+    if !shim_path.exists() {
+        bailf!("Couldn't find shim executable at '{}'", shim_path.display());
+    }
+    Ok(shim_path)
+}
+
+fn upgrade(
+    paths: Vec<PathBuf>,
+    all: bool,
+    force: bool,
+    dry_run: bool,
+    shim_path: Option<PathBuf>,
+) -> Result<(), String> {
+    let shim_path = self::shim_path(shim_path)?;
+    let paths = if all { shim::find_all() } else { paths };
+    if paths.is_empty() {
+        println!("No shims found");
+        return Ok(());
+    }
+
+    let (mut upgraded, mut skipped, mut failed) = (0, 0, 0);
+    for path in &paths {
+        match upgrade::upgrade(path, &shim_path, force, dry_run) {
+            Ok(x) => {
+                println!("{x}");
+                if x.is_skipped() {
+                    skipped += 1;
+                } else if !x.is_up_to_date() {
+                    upgraded += 1;
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {e}", path.display());
+                failed += 1;
+            }
+        }
+    }
+
+    let verb = if dry_run { "to upgrade" } else { "upgraded" };
+    println!(
+        "{upgraded} {verb}, {} up to date, {skipped} newer, {failed} failed",
+        paths.len() - upgraded - skipped - failed
+    );
+    if failed > 0 {
+        bailf!("{failed} shim(s) couldn't be upgraded");
+    }
+    Ok(())
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf, String> {
+    fs::canonicalize(path).map_err(|e| format!("Error resolving '{}': {e}", path.display()))
+}
+
+// The given destination for built shims, or ~/Applications.
+fn dest(given: Option<PathBuf>) -> Result<PathBuf, String> {
+    match given {
+        // Absolute, for comparing with the registry's paths.
+        Some(x) => {
+            std::path::absolute(&x).map_err(|e| format!("Error resolving '{}': {e}", x.display()))
+        }
+        None => Ok(home::home_dir()
+            .ok_or("Couldn't find home directory")?
+            .join("Applications")),
+    }
+}
+
+fn build(
+    path: &Path,
+    dest: Option<PathBuf>,
+    force: bool,
+    shim_path: Option<PathBuf>,
+) -> Result<(), String> {
+    let recipe = Recipe::load(path)?;
+    let built = recipe::build(
+        &recipe,
+        &canonicalize(path)?,
+        &self::dest(dest)?,
+        &self::shim_path(shim_path)?,
+        force,
+        false,
+    )?;
+    println!(
+        "{}: {} ({})",
+        built.name,
+        built.outcome,
+        built.path.display()
+    );
+    print_warnings(&built.warnings);
+    Ok(())
+}
+
+fn sync(
+    path: &Path,
+    dest: Option<PathBuf>,
+    force: bool,
+    dry_run: bool,
+    shim_path: Option<PathBuf>,
+) -> Result<(), String> {
+    let manifest = Manifest::load(path)?;
+    let synced = recipe::sync(
+        &manifest,
+        &self::dest(dest)?,
+        &self::shim_path(shim_path)?,
+        force,
+        dry_run,
+    )?;
+
+    let (mut changed, mut unchanged, mut failed) = (0, 0, 0);
+    for res in &synced.built {
+        match res {
+            Ok(built) => {
+                println!(
+                    "{}: {} ({})",
+                    built.name,
+                    built.outcome,
+                    built.path.display()
+                );
+                print_warnings(&built.warnings);
+                match built.outcome {
+                    Outcome::Unchanged => unchanged += 1,
+                    _ => changed += 1,
+                }
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                failed += 1;
+            }
+        }
+    }
+    for pruned in &synced.pruned {
+        match &pruned.result {
+            Ok(warnings) => {
+                println!("{}: removed", pruned.path.display());
+                print_warnings(warnings);
+                changed += 1;
+            }
+            Err(e) => {
+                eprintln!("{}: {e}", pruned.path.display());
+                failed += 1;
+            }
+        }
+    }
+
+    let verb = if dry_run { "to change" } else { "changed" };
+    println!("{changed} {verb}, {unchanged} unchanged, {failed} failed");
+    if failed > 0 {
+        bailf!("{failed} shim(s) couldn't be synced");
+    }
+    Ok(())
+}
+
+fn export(path: &Path, format: Option<Format>, output: Option<PathBuf>) -> Result<(), String> {
+    let format = match (format, &output) {
+        (Some(x), Some(output)) => match Format::from_path(output) {
+            Ok(y) if x != y => bailf!(
+                "--format {x} doesn't match '{}', which would be {y}",
+                output.display()
+            ),
+            _ => x,
+        },
+        (Some(x), None) => x,
+        (None, Some(output)) => Format::from_path(output)?,
+        (None, None) => Format::Zip,
+    };
+    let output = match output {
+        Some(x) => x,
+        None => PathBuf::from(format!("{}.{}", Shim::load(path)?.name, format.extension())),
+    };
+
+    archive::export(path, format, &output)?;
+    println!("Exported to {}", output.display());
+    Ok(())
+}
+
+fn import_platypus(args: ImportPlatypusArgs) -> Result<(), String> {
+    let import = platypus::import(&args.path, args.target)?;
+    let recipe = Recipe {
+        terminal: args.terminal,
+        generic_terminal: args.generic_terminal,
+        ..import.recipe
+    }
+    .resolved(Path::new(""))?;
+
+    // The shim may run the script inside the app, so mustn't replace it.
+    let dest = self::dest(args.dest)?;
+    let out_path = dest.join(format!("{}.app", recipe.name()));
+    if recipe.target == Target::MacOs
+        && fs::canonicalize(&out_path).ok() == Some(fs::canonicalize(&args.path).ok()).flatten()
+    {
+        bailf!(
+            "The shim would replace '{}' itself, pass a different --dest",
+            args.path.display()
+        );
+    }
+
+    let built = recipe::build(
+        &recipe,
+        &canonicalize(&args.path)?,
+        &dest,
+        &self::shim_path(args.shim_path)?,
+        args.force,
+        args.dry_run,
+    )?;
+    println!(
+        "{}: {} ({})",
+        built.name,
+        built.outcome,
+        built.path.display()
+    );
+    println!("Command: {}", recipe.command);
+    for skipped in &import.skipped {
+        println!("Not carried over: {skipped}");
+    }
+    print_warnings(&built.warnings);
+    Ok(())
+}
+
+fn edit(args: EditArgs) -> Result<(), String> {
+    let terminal = match (args.terminal, args.generic_terminal) {
+        (_, Some(term)) => Some(TerminalApp::Generic(term)),
+        (Some(term), None) => Some(TerminalApp::Supported(term)),
+        (None, None) => None,
+    };
+
+    let composed =
+        args.icon_label.is_some() || args.icon_tint.is_some() || args.icon_badge.is_some();
+    let icon = if args.icon.is_some() || composed {
+        // A new icon starts over, otherwise what's passed replaces what it was made with.
+        let base = match args.icon {
+            Some(x) => ShimIcon::from_path(Some(&x)),
+            None => Shim::load(&args.path)?.icon_source()?,
+        };
+        Some(ShimIcon {
+            path: base.path,
+            label: args.icon_label.or(base.label),
+            tint: args.icon_tint.or(base.tint),
+            badge: args.icon_badge.or(base.badge),
+        })
+    } else {
+        None
+    };
+
+    let changes = ShimEdit {
+        command: args.command,
+        group_open_by: args.group_open_by,
+        terminal,
+        bundle_id: args.bundle_id,
+        icon,
+    };
+    let warnings = shim::edit(&args.path, &changes)?;
+    print_warnings(&warnings);
+    Ok(())
+}
+
+fn generate(args: Args) -> Result<(), String> {
     if args.terminal.is_some() && args.generic_terminal.is_some() {
         bail!("Only one of --terminal and --generic-terminal may be passed");
     }
@@ -75,68 +820,174 @@ fn run() -> Result<(), String> {
     let terminal = if let Some(term) = args.generic_terminal {
         TerminalApp::Generic(term.to_owned())
     } else if let Some(term) = &args.terminal {
-        if !term::is_supported(term) {
+        if !term::is_supported_for(args.target, term) {
             return Err(format!(
-                "Terminal {} is not supported (supported terminals: {}), but you can try it with --generic-terminal", 
+                "Terminal {} is not supported for {} (supported terminals: {}), but you can try it with --generic-terminal", 
                 term,
-                term::supported_terminals_string(),
+                args.target,
+                term::supported_terminals_string_for(args.target),
             ));
         }
         TerminalApp::Supported(term.to_owned())
     } else {
-        TerminalApp::Supported("Terminal.app".to_owned())
+        TerminalApp::Supported(term::default_terminal_for(args.target).to_owned())
     };
 
     let config = Config {
         command: args.command,
         group_open_by: args.group_open_by,
         terminal,
+        dry_run: args.shim_dry_run,
+        icon: None,
     };
 
-    let doc_types = if args.docs_text_files {
-        DocTypes::TextFiles
-    } else if args.docs_all_docs {
-        DocTypes::AllDocs
-    } else if let Some(utis) = args.docs_utis {
-        DocTypes::UTIs(utis.clone())
-    } else if let Some(exts) = args.docs_exts {
-        DocTypes::Exts(exts)
+    let doc_types = if let Some(path) = &args.docs_groups {
+        DocTypes::load(path)?
     } else {
-        DocTypes::default()
+        let mut doc_types = if args.docs_text_files {
+            DocTypes::text_files()
+        } else if args.docs_all_docs {
+            DocTypes::all_docs()
+        } else if let Some(utis) = &args.docs_utis {
+            DocTypes::utis(utis)
+        } else if let Some(exts) = &args.docs_exts {
+            DocTypes::exts(exts)
+        } else {
+            DocTypes::default()
+        };
+        for group in &mut doc_types.0 {
+            group.role = args.docs_role;
+            group.rank = args.docs_rank;
+        }
+        doc_types
     };
 
-    let shim_path = match args.shim_path {
-        Some(x) => x.into(),
-        None => {
-            let mut path =
-                std::env::current_exe().map_err(|e| format!("Failed to get current exe: {e}"))?;
-            if !path.pop() {
-                bailf!(
-                    "Couldn't pop binary filename from path '{}' !?",
-                    path.display()
-                );
-            }
-            path.push("echidna-shim");
-            path
-        }
+    let shim_path = shim_path(args.shim_path.map(PathBuf::from))?;
+
+    let archive_format = args.archive.as_deref().map(Format::from_path).transpose()?;
+
+    let type_decls = match &args.declare_types {
+        Some(path) => type_decl::load_decls(path)?,
+        None => vec![],
     };
 
-    if !shim_path.exists() {
-        bailf!("Couldn't find shim executable at '{}'", shim_path.display());
+    let extra_info_plist = args
+        .extra_info_plist
+        .as_deref()
+        .map(info_plist::load_extra)
+        .transpose()?;
+
+    let bundle_id = BundleIdOptions {
+        id: args.bundle_id.clone(),
+        prefix: args.bundle_id_prefix.clone(),
+        seed: args.bundle_id_seed.clone(),
+    };
+
+    let icon = ShimIcon {
+        path: args.icon.clone(),
+        label: args.icon_label.clone(),
+        tint: args.icon_tint,
+        badge: args.icon_badge.clone(),
+    };
+
+    if args.dry_run {
+        let gen = Generator::gen_into(
+            MemTree::new(),
+            &config,
+            &doc_types,
+            &shim_path,
+            &bundle_id,
+            &icon,
+            args.out_path,
+            args.target,
+            &type_decls,
+            extra_info_plist.as_ref(),
+        )?;
+
+        print!("{}", gen.preview());
+        print_warnings(gen.warnings());
+        return Ok(());
     }
 
     let mut gen = Generator::gen(
         &config,
         &doc_types,
         &shim_path,
-        args.bundle_id.as_deref(),
-        args.icon.as_deref(),
+        &bundle_id,
+        &icon,
         args.out_path.clone(),
+        args.target,
+        &type_decls,
+        extra_info_plist.as_ref(),
     )?;
 
-    gen.save(args.force)
-        .map(|_| ())
-        .map_err(|e| e.to_msg(&args.out_path))
+    gen.keep_backup(args.backup);
+    let mut res = gen
+        .save(args.force)
+        .map_err(|e| e.to_msg(gen.final_bundle_path()));
+
+    if res.is_ok() && args.make_default {
+        res = gen.make_default();
+    }
+
+    if let (Ok(()), Some(archive), Some(format)) = (&res, &args.archive, archive_format) {
+        res = archive::export(gen.final_bundle_path(), format, archive);
+    }
+
+    print_warnings(gen.warnings());
+    res
+}
+
+fn run() -> Result<(), String> {
+    let cli = Cli::parse();
+    match (cli.subcommand, cli.generate) {
+        (Some(Command::RestoreDefaults { shim }), _) => restore_defaults(&shim),
+        (Some(Command::Inspect { path, json }), _) => inspect(&path, json),
+        (Some(Command::Verify { path, json }), _) => verify(&path, json),
+        (Some(Command::Edit(args)), _) => edit(args),
+        (Some(Command::ImportPlatypus(args)), _) => import_platypus(args),
+        (
+            Some(Command::Export {
+                path,
+                format,
+                output,
+            }),
+            _,
+        ) => export(&path, format, output),
+        (Some(Command::List { json }), _) => list(json),
+        (Some(Command::Uninstall { shim }), _) => uninstall(&shim),
+        (
+            Some(Command::Upgrade {
+                paths,
+                all,
+                force,
+                dry_run,
+                shim_path,
+            }),
+            _,
+        ) => upgrade(paths, all, force, dry_run, shim_path),
+        (
+            Some(Command::Build {
+                recipe,
+                dest,
+                force,
+                shim_path,
+            }),
+            _,
+        ) => build(&recipe, dest, force, shim_path),
+        (
+            Some(Command::Sync {
+                manifest,
+                dest,
+                force,
+                dry_run,
+                shim_path,
+            }),
+            _,
+        ) => sync(&manifest, dest, force, dry_run, shim_path),
+        (None, Some(args)) => generate(args),
+        (None, None) => bail!("Expected a command and output path, or a subcommand (see --help)"),
+    }
 }
 
 fn main() {
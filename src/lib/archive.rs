@@ -0,0 +1,365 @@
+use crate::bailf;
+use crate::desktop;
+use crate::generate::Target;
+use crate::icon::HICOLOR_SIZES;
+use crate::misc::write_atomic;
+use crate::recipe::Recipe;
+use crate::shim::Shim;
+use crate::xdg;
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+// Packing a shim into a zip or tarball to hand to someone else, with its recipe so they can
+// regenerate it. Archives are reproducible: entries are sorted, and modes and timestamps
+// normalized, so the same shim always gives the same bytes.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Zip,
+    #[value(name = "tar.gz")]
+    TarGz,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Zip => "zip",
+            Format::TarGz => "tar.gz",
+        }
+    }
+
+    // Going by the file name: .zip, .tar.gz or .tgz.
+    pub fn from_path(path: &Path) -> Result<Format, String> {
+        let name = path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if name.ends_with(".zip") {
+            Ok(Format::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(Format::TarGz)
+        } else {
+            bailf!(
+                "Can't tell the archive format of '{}', expected .zip, .tar.gz or .tgz",
+                path.display()
+            )
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+// 1980-01-01 00:00:00 UTC, the earliest time a zip can hold.
+const TIMESTAMP: u64 = 315_532_800;
+
+enum Kind {
+    Dir,
+    File(Vec<u8>),
+    Symlink(PathBuf),
+}
+
+struct Entry {
+    // Relative, with '/' separators.
+    path: String,
+    kind: Kind,
+    executable: bool,
+}
+
+impl Entry {
+    fn mode(&self) -> u32 {
+        match self.kind {
+            Kind::Dir => 0o755,
+            Kind::File(_) if self.executable => 0o755,
+            Kind::File(_) => 0o644,
+            Kind::Symlink(_) => 0o777,
+        }
+    }
+}
+
+// Adds src as path, and if it's a directory, everything in it.
+fn collect(src: &Path, path: &Path, entries: &mut Vec<Entry>) -> Result<(), String> {
+    let err = |e: std::io::Error| format!("Error reading '{}': {e}", src.display());
+    let meta = fs::symlink_metadata(src).map_err(err)?;
+    let path_string = path.to_string_lossy().into_owned();
+
+    if meta.is_symlink() {
+        entries.push(Entry {
+            path: path_string,
+            kind: Kind::Symlink(fs::read_link(src).map_err(err)?),
+            executable: false,
+        });
+    } else if meta.is_dir() {
+        entries.push(Entry {
+            path: path_string,
+            kind: Kind::Dir,
+            executable: false,
+        });
+        for entry in fs::read_dir(src).map_err(err)? {
+            let name = entry.map_err(err)?.file_name();
+            collect(&src.join(&name), &path.join(&name), entries)?;
+        }
+    } else {
+        entries.push(Entry {
+            path: path_string,
+            kind: Kind::File(fs::read(src).map_err(err)?),
+            executable: meta.permissions().mode() & 0o111 != 0,
+        });
+    }
+    Ok(())
+}
+
+// What makes up the shim, relative to the archive's root, and the root in the filesystem: the
+// bundle's parent, or for Linux, $XDG_DATA_HOME.
+fn shim_files(shim: &Shim) -> Result<(PathBuf, Vec<PathBuf>), String> {
+    match shim.target {
+        Target::MacOs => {
+            let (Some(root), Some(name)) = (shim.path.parent(), shim.path.file_name()) else {
+                bailf!("Invalid path '{}'", shim.path.display());
+            };
+            Ok((root.to_owned(), vec![PathBuf::from(name)]))
+        }
+        Target::Linux => {
+            let root = xdg::data_home()?;
+            let name = OsStr::new(&shim.name);
+            let mut files = vec![
+                shim.path.clone(),
+                desktop::data_dir(name)?,
+                desktop::mime_dir()?
+                    .join("packages")
+                    .join(desktop::mime_package_file_name(name)),
+            ];
+            let icons_dir = desktop::icons_dir()?;
+            files.extend(
+                HICOLOR_SIZES
+                    .iter()
+                    .map(|x| icons_dir.join(desktop::hicolor_icon_path(name, *x))),
+            );
+
+            let mut relative = vec![];
+            for file in files.into_iter().filter(|x| x.exists()) {
+                let Ok(x) = file.strip_prefix(&root) else {
+                    bailf!("'{}' isn't in '{}'", file.display(), root.display());
+                };
+                relative.push(x.to_owned());
+            }
+            Ok((root, relative))
+        }
+    }
+}
+
+fn write_zip(entries: &[Entry]) -> Result<Vec<u8>, String> {
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, DateTime, ZipWriter};
+
+    let err = |e: zip::result::ZipError| format!("Error writing zip: {e}");
+    let mut zip = ZipWriter::new(std::io::Cursor::new(vec![]));
+    for entry in entries {
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(DateTime::default())
+            .unix_permissions(entry.mode());
+        match &entry.kind {
+            Kind::Dir => zip.add_directory(&entry.path, options).map_err(err)?,
+            Kind::File(contents) => {
+                zip.start_file(&entry.path, options).map_err(err)?;
+                zip.write_all(contents)
+                    .map_err(|e| format!("Error writing zip: {e}"))?;
+            }
+            Kind::Symlink(target) => zip
+                .add_symlink(&entry.path, target.to_string_lossy(), options)
+                .map_err(err)?,
+        }
+    }
+    Ok(zip.finish().map_err(err)?.into_inner())
+}
+
+fn write_tar_gz(entries: &[Entry]) -> Result<Vec<u8>, String> {
+    let err = |e: std::io::Error| format!("Error writing tarball: {e}");
+    let mut tar = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(entry.mode());
+        header.set_mtime(TIMESTAMP);
+        header.set_uid(0);
+        header.set_gid(0);
+        match &entry.kind {
+            Kind::Dir => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_size(0);
+                tar.append_data(&mut header, &entry.path, std::io::empty())
+                    .map_err(err)?;
+            }
+            Kind::File(contents) => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(contents.len() as u64);
+                tar.append_data(&mut header, &entry.path, contents.as_slice())
+                    .map_err(err)?;
+            }
+            Kind::Symlink(target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                tar.append_link(&mut header, &entry.path, target)
+                    .map_err(err)?;
+            }
+        }
+    }
+    tar.into_inner().and_then(|x| x.finish()).map_err(err)
+}
+
+// Writes the shim at shim_path (a .app bundle, or for Linux, a desktop entry) to archive_path,
+// along with <name>.recipe.toml. A macOS shim is the archive's top level .app, a Linux one's files
+// are where they go in $XDG_DATA_HOME.
+pub fn export(shim_path: &Path, format: Format, archive_path: &Path) -> Result<(), String> {
+    let shim = Shim::load(shim_path)?;
+    let (root, files) = shim_files(&shim)?;
+
+    let mut entries = vec![];
+    for file in &files {
+        collect(&root.join(file), file, &mut entries)?;
+    }
+
+    // The entry runs the shim by its absolute path here, which may not be where it's unpacked.
+    if shim.target == Target::Linux {
+        let desktop_file = shim.path.strip_prefix(&root).unwrap_or(&shim.path);
+        for entry in &mut entries {
+            if let (true, Kind::File(contents)) =
+                (Path::new(&entry.path) == desktop_file, &mut entry.kind)
+            {
+                let exec = desktop::portable_exec(OsStr::new(&shim.name));
+                let desktop =
+                    desktop::set_entry_key(&String::from_utf8_lossy(contents), "Exec", &exec);
+                *contents = desktop.into_bytes();
+            }
+        }
+    }
+
+    let mut recipe = Recipe::from_shim(&shim);
+    recipe.relative_to(&root);
+    entries.push(Entry {
+        path: format!("{}.recipe.toml", shim.name),
+        kind: Kind::File(recipe.to_toml()?.into_bytes()),
+        executable: false,
+    });
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let bytes = match format {
+        Format::Zip => write_zip(&entries)?,
+        Format::TarGz => write_tar_gz(&entries)?,
+    };
+    write_atomic(archive_path, &bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xdg::TestHome;
+
+    // Generates a Linux shim named Vim under the test home, returning its desktop entry and where
+    // its executable is in an archive.
+    fn gen_shim(home: &TestHome) -> (PathBuf, String) {
+        let shim_bin = home.path().join("echidna-shim");
+        fs::write(&shim_bin, "shim").unwrap();
+        fs::set_permissions(&shim_bin, fs::Permissions::from_mode(0o755)).unwrap();
+        let recipe_path = home.path().join("Vim.recipe.toml");
+        fs::write(
+            &recipe_path,
+            "name = \"Vim\"\ntarget = \"linux\"\ncommand = \"vim\"\ndocs_exts = [\"txt\"]\n",
+        )
+        .unwrap();
+
+        let mut gen = Recipe::load(&recipe_path)
+            .unwrap()
+            .gen(home.path(), &shim_bin)
+            .unwrap();
+        let desktop_file = gen.final_bundle_path().to_owned();
+        gen.save(false).unwrap();
+
+        let name = OsStr::new("Vim");
+        let exe = desktop::data_dir(name).unwrap().join(name);
+        let exe = exe.strip_prefix(xdg::data_home().unwrap()).unwrap();
+        (desktop_file, exe.to_string_lossy().into_owned())
+    }
+
+    // Each file's path and mode.
+    fn zip_modes(bytes: &[u8]) -> Vec<(String, u32)> {
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+        (0..zip.len())
+            .map(|i| {
+                let file = zip.by_index(i).unwrap();
+                (file.name().to_owned(), file.unix_mode().unwrap() & 0o777)
+            })
+            .collect()
+    }
+
+    fn tar_gz_modes(bytes: &[u8]) -> Vec<(String, u32)> {
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(bytes));
+        tar.entries()
+            .unwrap()
+            .map(|x| {
+                let entry = x.unwrap();
+                let path = entry.path().unwrap().to_string_lossy().into_owned();
+                (path, entry.header().mode().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn export_is_reproducible() {
+        let home = TestHome::new();
+        let (desktop_file, _) = gen_shim(&home);
+
+        for format in [Format::Zip, Format::TarGz] {
+            let first = home.path().join(format!("first.{format}"));
+            let second = home.path().join(format!("second.{format}"));
+            export(&desktop_file, format, &first).unwrap();
+            // Only the modification time changes.
+            fs::File::options()
+                .write(true)
+                .open(&desktop_file)
+                .unwrap()
+                .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+                .unwrap();
+            export(&desktop_file, format, &second).unwrap();
+            assert_eq!(
+                fs::read(&first).unwrap(),
+                fs::read(&second).unwrap(),
+                "{format}"
+            );
+        }
+    }
+
+    #[test]
+    fn export_keeps_executable_bit() {
+        let home = TestHome::new();
+        let (desktop_file, exe) = gen_shim(&home);
+        let archive = home.path().join("Vim.zip");
+        export(&desktop_file, Format::Zip, &archive).unwrap();
+        let zip = zip_modes(&fs::read(&archive).unwrap());
+        let archive = home.path().join("Vim.tar.gz");
+        export(&desktop_file, Format::TarGz, &archive).unwrap();
+        let tar_gz = tar_gz_modes(&fs::read(&archive).unwrap());
+
+        for modes in [zip, tar_gz] {
+            let mode = |path: &str| {
+                let found = modes.iter().find(|x| x.0.trim_end_matches('/') == path);
+                found.unwrap_or_else(|| panic!("no {path} in {modes:?}")).1
+            };
+            assert_eq!(mode(&exe), 0o755);
+            assert_eq!(mode("applications/echidna-Vim.desktop"), 0o644);
+            assert_eq!(mode("Vim.recipe.toml"), 0o644);
+        }
+    }
+}
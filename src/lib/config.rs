@@ -1,18 +1,22 @@
 use crate::bail;
+use crate::icon_composer::ShimIcon;
+use crate::output::OutputTarget;
 
 use std::fmt;
-use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
 pub enum GroupBy {
+    #[serde(alias = "none")]
     None,
+    #[serde(alias = "all")]
     All,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for GroupBy {
     fn default() -> Self {
         Self::All
@@ -44,12 +48,22 @@ impl TerminalApp {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Config {
     pub command: String,
     pub group_open_by: GroupBy,
 
     pub terminal: TerminalApp,
+
+    // If set, don't open a terminal, instead record what would have been run as JSON lines to
+    // this path ("-" for stdout). See term::DRY_RUN_KEY for overriding it at run time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<PathBuf>,
+
+    // What the icon was made from, see ShimIcon::recorded(). Set by the generator, not read by
+    // the shim.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<ShimIcon>,
 }
 
 fn ts<E: ToString>(e: E) -> String {
@@ -57,13 +71,32 @@ fn ts<E: ToString>(e: E) -> String {
 }
 
 impl Config {
+    // The running shim's config. Linux shims keep it next to the binary, macOS ones in the
+    // bundle's Resources directory.
+    pub fn default_path() -> Result<PathBuf, String> {
+        let exe = std::env::current_exe().map_err(|e| format!("Failed to get current exe: {e}"))?;
+        if let Some(path) = exe.parent().map(|x| x.join("config.json")) {
+            if path.is_file() {
+                return Ok(path);
+            }
+        }
+
+        Ok(crate::misc::get_app_resources()?.join("config.json"))
+    }
+
     pub fn load() -> Result<Config, String> {
-        let mut path = crate::misc::get_app_resources()?;
-        path.push("config.json");
+        Self::load_from(&Self::default_path()?)
+    }
 
-        let conf_str = std::fs::read_to_string(path).map_err(ts)?;
+    pub fn load_from(path: &Path) -> Result<Config, String> {
+        let conf_str = std::fs::read_to_string(path)
+            .map_err(|e| format!("Error reading config '{}': {e}", path.display()))?;
+        Self::parse(&conf_str)
+    }
 
-        let conf: Config = serde_json::from_str(&conf_str).map_err(ts)?;
+    // Enum values in lower case, as written by hand, are accepted too.
+    pub fn parse(conf_str: &str) -> Result<Config, String> {
+        let conf: Config = serde_json::from_str(conf_str).map_err(ts)?;
         if conf.command.is_empty() {
             bail!("Config's 'command' field may not be empty");
         }
@@ -71,19 +104,11 @@ impl Config {
         Ok(conf)
     }
 
-    pub fn write(&self, resources: &Path) -> Result<(), String> {
-        let config_dir = resources.join("config.json");
-
-        let config_json = serde_json::to_string(self)
-            .map_err(|e| format!("Error serializing config {self:?}: {e}"))?;
-
-        fs::write(&config_dir, config_json).map_err(|e| {
-            format!(
-                "Error writing config to temporary directory '{}': {e}",
-                config_dir.display()
-            )
-        })?;
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string(self).map_err(|e| format!("Error serializing config {self:?}: {e}"))
+    }
 
-        Ok(())
+    pub fn write(&self, output: &mut dyn OutputTarget, dir: &Path) -> Result<(), String> {
+        output.write_file(&dir.join("config.json"), self.to_json()?.as_bytes())
     }
 }
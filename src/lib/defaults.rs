@@ -0,0 +1,505 @@
+use crate::bailf;
+use crate::generate::Target;
+use crate::misc::{state_dir, write_atomic};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+// Making shims the default handler for their types, and undoing it. The handlers that were
+// replaced are recorded in the state directory, so they can be restored later (even if the shim
+// has since been regenerated or deleted).
+
+const RECORDS_FILE: &str = "default_handlers.json";
+
+// Catch-all types; being the default for these would mean being the default for nearly
+// everything, which is never what's wanted.
+const TOO_BROAD: &[&str] = &[
+    "public.item",
+    "public.data",
+    "public.content",
+    "application/octet-stream",
+];
+
+// What's needed to make a shim the default handler for its types.
+#[derive(Debug, Clone)]
+pub struct Handler {
+    pub target: Target,
+    // macOS: the bundle identifier. Linux: the desktop file name (which is its desktop id).
+    pub id: String,
+    // The .app or .desktop file.
+    pub path: PathBuf,
+    // macOS: UTIs. Linux: MIME types.
+    pub content_types: Vec<String>,
+    // macOS only, resolved to UTIs once the shim is registered with LaunchServices.
+    pub extensions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    pub target: Target,
+    pub path: PathBuf,
+    // Content type to the handler it had before, None if there was none.
+    pub previous: BTreeMap<String, Option<String>>,
+}
+
+// Shim id to its record.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Records(BTreeMap<String, Record>);
+
+impl Records {
+    fn path() -> Result<PathBuf, String> {
+        Ok(state_dir()?.join(RECORDS_FILE))
+    }
+
+    fn load() -> Result<Records, String> {
+        let path = Self::path()?;
+        match fs::read_to_string(&path) {
+            Ok(x) => serde_json::from_str(&x)
+                .map_err(|e| format!("Error parsing '{}': {e}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Records::default()),
+            Err(e) => Err(format!("Error reading '{}': {e}", path.display())),
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Error serializing default handler records: {e}"))?;
+        write_atomic(&Self::path()?, json.as_bytes())
+    }
+}
+
+// Makes the shim the default handler for each of its types, returning warnings for any that
+// were skipped.
+pub fn make_default(handler: &Handler) -> Result<Vec<String>, String> {
+    let mut records = Records::load()?;
+    let record = records
+        .0
+        .entry(handler.id.clone())
+        .or_insert_with(|| Record {
+            target: handler.target,
+            path: handler.path.clone(),
+            previous: BTreeMap::new(),
+        });
+    record.path = handler.path.clone();
+
+    let mut warnings = vec![];
+    let res = match handler.target {
+        Target::Linux => linux::make_default(handler, record, &mut warnings),
+        Target::MacOs => macos::make_default(handler, record, &mut warnings),
+    };
+
+    // Save even on failure, whatever was changed before it must still be restorable.
+    records.save()?;
+    res.map(|_| warnings)
+}
+
+// Restores the handlers replaced by make_default(). shim is its id or path. Returns warnings for
+// types that couldn't be restored.
+pub fn restore(shim: &str) -> Result<Vec<String>, String> {
+    let mut records = Records::load()?;
+    let Some(id) = find(&records, shim) else {
+        bailf!("No default handlers recorded for '{shim}'");
+    };
+    let record = records.0.remove(&id).unwrap();
+
+    let mut warnings = vec![];
+    match record.target {
+        Target::Linux => linux::restore(&id, &record, &mut warnings)?,
+        Target::MacOs => macos::restore(&id, &record, &mut warnings)?,
+    }
+
+    records.save()?;
+    Ok(warnings)
+}
+
+// Whether make_default() has been used on the shim (by id or path) without a restore().
+pub fn is_recorded(shim: &str) -> Result<bool, String> {
+    Ok(find(&Records::load()?, shim).is_some())
+}
+
+fn find(records: &Records, shim: &str) -> Option<String> {
+    if records.0.contains_key(shim) {
+        return Some(shim.to_owned());
+    }
+
+    records
+        .0
+        .iter()
+        .find(|(_, record)| record.path == Path::new(shim))
+        .map(|(id, _)| id.clone())
+}
+
+fn is_too_broad(content_type: &str, warnings: &mut Vec<String>) -> bool {
+    if TOO_BROAD.contains(&content_type) {
+        warnings.push(format!(
+            "Not making the shim the default for '{content_type}', it would be the default for \
+            almost everything"
+        ));
+        return true;
+    }
+    false
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// ~/.config/mimeapps.list's [Default Applications] section.
+mod linux {
+    use super::{is_too_broad, Handler, Record};
+    use crate::misc::write_atomic;
+    use crate::xdg;
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    const SECTION: &str = "[Default Applications]";
+
+    fn mimeapps_path() -> Result<PathBuf, String> {
+        Ok(xdg::config_home()?.join("mimeapps.list"))
+    }
+
+    // Just enough of an ini file to edit one section, leaving everything else as it was.
+    struct MimeApps {
+        lines: Vec<String>,
+    }
+
+    impl MimeApps {
+        fn load() -> Result<MimeApps, String> {
+            let path = mimeapps_path()?;
+            let contents = match fs::read_to_string(&path) {
+                Ok(x) => x,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(format!("Error reading '{}': {e}", path.display())),
+            };
+
+            Ok(MimeApps {
+                lines: contents.lines().map(|x| x.to_owned()).collect(),
+            })
+        }
+
+        fn save(&self) -> Result<(), String> {
+            let mut contents = self.lines.join("\n");
+            contents.push('\n');
+            write_atomic(&mimeapps_path()?, contents.as_bytes())
+        }
+
+        // Range of the section's lines, after its header.
+        fn section(&self) -> Option<(usize, usize)> {
+            let start = self.lines.iter().position(|x| x.trim() == SECTION)? + 1;
+            let len = self.lines[start..]
+                .iter()
+                .position(|x| x.trim_start().starts_with('['))
+                .unwrap_or(self.lines.len() - start);
+            Some((start, start + len))
+        }
+
+        fn find(&self, key: &str) -> Option<usize> {
+            let (start, end) = self.section()?;
+            (start..end).find(|i| {
+                self.lines[*i]
+                    .split_once('=')
+                    .is_some_and(|(k, _)| k.trim() == key)
+            })
+        }
+
+        fn get(&self, key: &str) -> Option<String> {
+            let line = &self.lines[self.find(key)?];
+            line.split_once('=').map(|(_, v)| v.trim().to_owned())
+        }
+
+        fn set(&mut self, key: &str, value: &str) {
+            let line = format!("{key}={value}");
+            if let Some(i) = self.find(key) {
+                self.lines[i] = line;
+                return;
+            }
+
+            match self.section() {
+                Some((start, end)) => {
+                    // Keep it above any blank lines separating the next section.
+                    let mut end = end;
+                    while end > start && self.lines[end - 1].trim().is_empty() {
+                        end -= 1;
+                    }
+                    self.lines.insert(end, line);
+                }
+                None => {
+                    if self.lines.last().is_some_and(|x| !x.trim().is_empty()) {
+                        self.lines.push(String::new());
+                    }
+                    self.lines.push(SECTION.to_owned());
+                    self.lines.push(line);
+                }
+            }
+        }
+
+        fn remove(&mut self, key: &str) {
+            if let Some(i) = self.find(key) {
+                self.lines.remove(i);
+            }
+        }
+    }
+
+    pub fn make_default(
+        handler: &Handler,
+        record: &mut Record,
+        warnings: &mut Vec<String>,
+    ) -> Result<(), String> {
+        let mut mimeapps = MimeApps::load()?;
+        let value = format!("{};", handler.id);
+
+        for mime_type in &handler.content_types {
+            if is_too_broad(mime_type, warnings) {
+                continue;
+            }
+
+            let previous = mimeapps.get(mime_type);
+            if previous.as_deref() == Some(&value) {
+                continue;
+            }
+
+            // If it's already recorded, the shim has been default before, keep the original.
+            record.previous.entry(mime_type.clone()).or_insert(previous);
+            mimeapps.set(mime_type, &value);
+        }
+
+        mimeapps.save()
+    }
+
+    pub fn restore(id: &str, record: &Record, warnings: &mut Vec<String>) -> Result<(), String> {
+        let mut mimeapps = MimeApps::load()?;
+        let ours = format!("{id};");
+
+        for (mime_type, previous) in &record.previous {
+            // Don't clobber something the user chose since.
+            if mimeapps.get(mime_type).as_deref() != Some(&ours) {
+                warnings.push(format!(
+                    "Default handler for '{mime_type}' has changed since, leaving it"
+                ));
+                continue;
+            }
+
+            match previous {
+                Some(previous) => mimeapps.set(mime_type, previous),
+                None => mimeapps.remove(mime_type),
+            }
+        }
+
+        mimeapps.save()
+    }
+}
+
+// LaunchServices.
+mod macos {
+    use super::{Handler, Record};
+
+    #[cfg(target_os = "macos")]
+    pub fn make_default(
+        handler: &Handler,
+        record: &mut Record,
+        warnings: &mut Vec<String>,
+    ) -> Result<(), String> {
+        use super::is_too_broad;
+        use crate::launch_services;
+
+        // Needed before LaunchServices will accept it as a handler, and for its exported types
+        // to be known.
+        launch_services::register(&handler.path)?;
+
+        let mut utis = handler.content_types.clone();
+        for ext in &handler.extensions {
+            match launch_services::uti_for_ext(ext) {
+                Some(uti) => utis.push(uti),
+                None => warnings.push(format!("Couldn't get a UTI for extension '{ext}'")),
+            }
+        }
+
+        for uti in utis {
+            if is_too_broad(&uti, warnings) {
+                continue;
+            }
+
+            let previous = launch_services::default_handler(&uti);
+            if previous.as_deref() == Some(&handler.id) {
+                continue;
+            }
+
+            launch_services::set_default_handler(&uti, &handler.id)?;
+            record.previous.entry(uti).or_insert(previous);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn restore(id: &str, record: &Record, warnings: &mut Vec<String>) -> Result<(), String> {
+        use crate::launch_services;
+
+        for (uti, previous) in &record.previous {
+            if launch_services::default_handler(uti).as_deref() != Some(id) {
+                warnings.push(format!(
+                    "Default handler for '{uti}' has changed since, leaving it"
+                ));
+                continue;
+            }
+
+            match previous {
+                Some(previous) => launch_services::set_default_handler(uti, previous)?,
+                None => warnings.push(format!(
+                    "There was no default handler for '{uti}' before, LaunchServices will pick \
+                    one once the shim is gone"
+                )),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    pub fn make_default(_: &Handler, _: &mut Record, _: &mut Vec<String>) -> Result<(), String> {
+        Err("Default handlers for macOS shims can only be set on macOS".to_owned())
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    pub fn restore(_: &str, _: &Record, _: &mut Vec<String>) -> Result<(), String> {
+        Err("Default handlers for macOS shims can only be restored on macOS".to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xdg::{self, TestHome};
+
+    const MIMEAPPS: &str = "\
+# Edited by hand
+[Added Associations]
+text/plain=gedit.desktop;
+
+[Default Applications]
+text/plain=gedit.desktop;
+image/png=eog.desktop;
+
+[Removed Associations]
+text/x-csrc=foo.desktop;
+";
+
+    fn mimeapps_path() -> PathBuf {
+        xdg::config_home().unwrap().join("mimeapps.list")
+    }
+
+    fn mimeapps() -> String {
+        fs::read_to_string(mimeapps_path()).unwrap()
+    }
+
+    fn handler(content_types: &[&str]) -> Handler {
+        Handler {
+            target: Target::Linux,
+            id: "echidna-Vim.desktop".to_owned(),
+            path: PathBuf::from("/data/applications/echidna-Vim.desktop"),
+            content_types: content_types.iter().map(|x| (*x).to_owned()).collect(),
+            extensions: vec![],
+        }
+    }
+
+    fn setup() -> TestHome {
+        let home = TestHome::new();
+        fs::create_dir_all(mimeapps_path().parent().unwrap()).unwrap();
+        fs::write(mimeapps_path(), MIMEAPPS).unwrap();
+        home
+    }
+
+    #[test]
+    fn make_default_then_restore_leaves_mimeapps_as_it_was() {
+        let _home = setup();
+        let handler = handler(&["text/plain", "text/markdown"]);
+
+        assert!(make_default(&handler).unwrap().is_empty());
+        assert_eq!(
+            mimeapps(),
+            "\
+# Edited by hand
+[Added Associations]
+text/plain=gedit.desktop;
+
+[Default Applications]
+text/plain=echidna-Vim.desktop;
+image/png=eog.desktop;
+text/markdown=echidna-Vim.desktop;
+
+[Removed Associations]
+text/x-csrc=foo.desktop;
+"
+        );
+        assert!(is_recorded("echidna-Vim.desktop").unwrap());
+        assert!(is_recorded("/data/applications/echidna-Vim.desktop").unwrap());
+
+        // Again, e.g. after regenerating it, keeps the handlers from before the first time.
+        make_default(&handler).unwrap();
+
+        assert!(restore("/data/applications/echidna-Vim.desktop")
+            .unwrap()
+            .is_empty());
+        assert_eq!(mimeapps(), MIMEAPPS);
+        assert!(!is_recorded("echidna-Vim.desktop").unwrap());
+        assert!(restore("echidna-Vim.desktop").is_err());
+    }
+
+    #[test]
+    fn restore_leaves_handlers_changed_since() {
+        let _home = setup();
+        make_default(&handler(&["text/plain", "text/markdown"])).unwrap();
+        let changed = mimeapps().replace(
+            "text/plain=echidna-Vim.desktop;",
+            "text/plain=kate.desktop;",
+        );
+        fs::write(mimeapps_path(), &changed).unwrap();
+
+        let warnings = restore("echidna-Vim.desktop").unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("'text/plain'"), "{warnings:?}");
+        assert_eq!(
+            mimeapps(),
+            MIMEAPPS.replace(
+                "text/plain=gedit.desktop;\nimage",
+                "text/plain=kate.desktop;\nimage"
+            )
+        );
+    }
+
+    #[test]
+    fn catch_all_types_are_skipped() {
+        let _home = setup();
+        let warnings = make_default(&handler(&["application/octet-stream", "text/plain"])).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            warnings[0].contains("'application/octet-stream'"),
+            "{warnings:?}"
+        );
+        assert!(!mimeapps().contains("application/octet-stream"));
+
+        let records = Records::load().unwrap();
+        let previous: Vec<_> = records.0["echidna-Vim.desktop"].previous.keys().collect();
+        assert_eq!(previous, ["text/plain"]);
+
+        for content_type in TOO_BROAD {
+            assert!(is_too_broad(content_type, &mut vec![]));
+        }
+        assert!(!is_too_broad("public.plain-text", &mut vec![]));
+    }
+
+    #[test]
+    fn make_default_creates_mimeapps() {
+        let _home = TestHome::new();
+        make_default(&handler(&["text/plain"])).unwrap();
+        assert_eq!(
+            mimeapps(),
+            "[Default Applications]\ntext/plain=echidna-Vim.desktop;\n"
+        );
+
+        restore("echidna-Vim.desktop").unwrap();
+        assert_eq!(mimeapps(), "[Default Applications]\n");
+    }
+}
@@ -0,0 +1,657 @@
+use crate::bailf;
+use crate::generate::DocTypes;
+use crate::open::bash_quote;
+use crate::type_decl::{xml_escape, TypeDecl};
+use crate::xdg;
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Freedesktop desktop entries, for shims generated for the Linux target. A Linux shim is a
+// directory under $XDG_DATA_HOME/echidna/ holding the shim binary and its config.json, a desktop
+// entry in $XDG_DATA_HOME/applications/ pointing at it, and its icon in the hicolor theme in
+// $XDG_DATA_HOME/icons/.
+
+// Catch-all UTIs, left out of MimeType rather than mapped to application/octet-stream, which
+// would offer the shim for every binary file.
+const CATCH_ALL_UTIS: &[&str] = &["public.data", "public.content", "public.item"];
+
+// Well-known UTIs, and the MIME types they correspond to.
+const UTI_MIME_TYPES: &[(&str, &str)] = &[
+    ("public.text", "text/plain"),
+    ("public.plain-text", "text/plain"),
+    ("public.utf8-plain-text", "text/plain"),
+    ("public.source-code", "text/plain"),
+    ("public.script", "text/plain"),
+    ("public.shell-script", "application/x-shellscript"),
+    ("public.python-script", "text/x-python"),
+    ("public.ruby-script", "application/x-ruby"),
+    ("public.perl-script", "application/x-perl"),
+    ("public.c-source", "text/x-csrc"),
+    ("public.c-header", "text/x-chdr"),
+    ("public.c-plus-plus-source", "text/x-c++src"),
+    ("public.c-plus-plus-header", "text/x-c++hdr"),
+    ("public.objective-c-source", "text/x-objcsrc"),
+    ("public.swift-source", "text/x-swift"),
+    ("com.sun.java-source", "text/x-java"),
+    ("com.netscape.javascript-source", "application/javascript"),
+    ("public.html", "text/html"),
+    ("public.xml", "application/xml"),
+    ("public.json", "application/json"),
+    ("public.yaml", "application/x-yaml"),
+    ("public.comma-separated-values-text", "text/csv"),
+    ("public.log", "text/x-log"),
+    ("net.daringfireball.markdown", "text/markdown"),
+    ("public.image", "image/*"),
+    ("public.png", "image/png"),
+    ("public.jpeg", "image/jpeg"),
+    ("com.adobe.pdf", "application/pdf"),
+];
+
+pub fn desktop_file_name(app_name: &OsStr) -> OsString {
+    let mut name = OsString::from("echidna-");
+    name.push(app_name);
+    name.push(".desktop");
+    name
+}
+
+// Where every shim's data directory goes.
+pub fn data_root() -> Result<PathBuf, String> {
+    Ok(xdg::data_home()?.join("echidna"))
+}
+
+// Where the shim's binary, config and icon go.
+pub fn data_dir(app_name: &OsStr) -> Result<PathBuf, String> {
+    Ok(data_root()?.join(app_name))
+}
+
+// Where the shim's desktop entry goes.
+pub fn applications_dir() -> Result<PathBuf, String> {
+    Ok(xdg::data_home()?.join("applications"))
+}
+
+// The user's icon themes.
+pub fn icons_dir() -> Result<PathBuf, String> {
+    Ok(xdg::data_home()?.join("icons"))
+}
+
+// The shim's icon's name in the hicolor theme.
+pub fn icon_name(app_name: &OsStr) -> String {
+    format!("echidna-{}", app_name.to_string_lossy())
+}
+
+// Relative to icons_dir().
+pub fn hicolor_icon_path(app_name: &OsStr, size: u32) -> PathBuf {
+    PathBuf::from(format!(
+        "hicolor/{size}x{size}/apps/{}.png",
+        icon_name(app_name)
+    ))
+}
+
+// The user's shared-mime-info database.
+pub fn mime_dir() -> Result<PathBuf, String> {
+    Ok(xdg::data_home()?.join("mime"))
+}
+
+pub fn mime_package_file_name(app_name: &OsStr) -> OsString {
+    let mut name = OsString::from("echidna-");
+    name.push(app_name);
+    name.push(".xml");
+    name
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Extension to MIME type mappings from shared-mime-info's globs2 files.
+#[derive(Debug, Default)]
+pub struct MimeGlobs {
+    // Lower-cased extension to (weight, MIME type).
+    exts: HashMap<String, (u32, String)>,
+}
+
+impl MimeGlobs {
+    // Loads the user's and system's globs. Missing or malformed files are ignored, if there's no
+    // shared-mime-info, every extension is just unknown.
+    pub fn load() -> MimeGlobs {
+        let mut globs = MimeGlobs::default();
+        let dirs = xdg::data_home().into_iter().chain(xdg::data_dirs());
+        for dir in dirs {
+            if let Ok(contents) = fs::read_to_string(dir.join("mime/globs2")) {
+                globs.parse(&contents);
+            }
+        }
+        globs
+    }
+
+    // Format is "weight:type:glob[:flags]", one per line.
+    fn parse(&mut self, contents: &str) {
+        for line in contents.lines().filter(|x| !x.starts_with('#')) {
+            let mut fields = line.split(':');
+            let (Some(weight), Some(mime), Some(glob)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+
+            let Ok(weight) = weight.parse::<u32>() else {
+                continue;
+            };
+
+            // Only plain "*.ext" globs are meaningful as extensions.
+            let Some(ext) = glob.strip_prefix("*.") else {
+                continue;
+            };
+            if ext.contains(['*', '?', '[']) {
+                continue;
+            }
+
+            let entry = self.exts.entry(ext.to_lowercase()).or_default();
+            if entry.1.is_empty() || weight > entry.0 {
+                *entry = (weight, mime.to_owned());
+            }
+        }
+    }
+
+    pub fn mime_type(&self, ext: &str) -> Option<&str> {
+        self.exts.get(&ext.to_lowercase()).map(|x| x.1.as_str())
+    }
+}
+
+// Returns the MIME types for the desktop entry's MimeType key, and warnings for anything that
+// couldn't be mapped. Extensions are looked up in the shim's own declarations if the system
+// doesn't know them.
+pub fn mime_types(
+    doc_type: &DocTypes,
+    globs: &MimeGlobs,
+    decls: &[TypeDecl],
+) -> (Vec<String>, Vec<String>) {
+    let mut types = vec![];
+    let mut warnings = vec![];
+
+    // Only what the shim opens, a group with role None is just declared.
+    let opened: Vec<_> = doc_type.groups().iter().filter(|x| x.opens()).collect();
+    let utis = opened.iter().flat_map(|x| &x.utis).map(|x| (true, x));
+    let exts = opened.iter().flat_map(|x| &x.exts).map(|x| (false, x));
+    for mime in opened.iter().flat_map(|x| &x.mime_types) {
+        if !types.contains(mime) {
+            types.push(mime.clone());
+        }
+    }
+
+    for (is_uti, value) in utis.chain(exts) {
+        if is_uti && CATCH_ALL_UTIS.contains(&value.as_str()) {
+            continue;
+        }
+        let mime = if is_uti {
+            UTI_MIME_TYPES
+                .iter()
+                .find(|(uti, _)| *uti == value)
+                .map(|(_, mime)| (*mime).to_owned())
+        } else if value == "*" {
+            None
+        } else {
+            globs.mime_type(value).map(|x| x.to_owned()).or_else(|| {
+                decls
+                    .iter()
+                    .find(|x| x.extensions.contains(value))
+                    .map(|x| x.mime_type.clone())
+            })
+        };
+
+        match mime {
+            Some(mime) => {
+                if !types.contains(&mime) {
+                    types.push(mime);
+                }
+            }
+            None => {
+                let kind = if is_uti { "UTI" } else { "extension" };
+                warnings.push(format!(
+                    "No MIME type is known for {kind} '{value}', it won't be associated with the shim"
+                ));
+            }
+        }
+    }
+
+    (types, warnings)
+}
+
+// A shared-mime-info package declaring the types.
+pub fn render_mime_package(decls: &[TypeDecl]) -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<mime-info xmlns=\"http://www.freedesktop.org/standards/shared-mime-info\">\n",
+    ));
+
+    for decl in decls {
+        xml += &format!("    <mime-type type=\"{}\">\n", xml_escape(&decl.mime_type));
+        xml += &format!(
+            "        <comment>{}</comment>\n",
+            xml_escape(&decl.description)
+        );
+        for ext in &decl.extensions {
+            xml += &format!("        <glob pattern=\"*.{}\"/>\n", xml_escape(ext));
+        }
+        xml += "    </mime-type>\n";
+    }
+
+    xml += "</mime-info>\n";
+    xml
+}
+
+// Reads back a package from render_mime_package(). Packages don't record UTIs, so each type's
+// identifier is made up from its MIME type, the way TypeDecl::normalized() makes MIME types up.
+pub fn parse_mime_package(xml: &str) -> Vec<TypeDecl> {
+    let attr = |line: &str, name: &str| {
+        let start = line.find(&format!("{name}=\""))? + name.len() + 2;
+        let len = line[start..].find('"')?;
+        Some(xml_unescape(&line[start..start + len]))
+    };
+
+    let mut decls: Vec<TypeDecl> = vec![];
+    for line in xml.lines().map(str::trim) {
+        if line.starts_with("<mime-type ") {
+            let Some(mime_type) = attr(line, "type") else {
+                continue;
+            };
+            let identifier = mime_type
+                .strip_prefix("application/x-")
+                .filter(|x| {
+                    x.chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                })
+                .map(|x| x.to_owned())
+                .unwrap_or_else(|| {
+                    crate::generate::normalize_bundle_id(&mime_type.replace('/', "."))
+                });
+            decls.push(TypeDecl {
+                identifier,
+                mime_type,
+                description: String::new(),
+                conforms_to: vec!["public.data".to_owned()],
+                extensions: vec![],
+                icon: None,
+            });
+        } else if let Some(decl) = decls.last_mut() {
+            if let Some(comment) = line
+                .strip_prefix("<comment>")
+                .and_then(|x| x.strip_suffix("</comment>"))
+            {
+                decl.description = xml_unescape(comment);
+            } else if line.starts_with("<glob ") {
+                if let Some(ext) =
+                    attr(line, "pattern").and_then(|x| x.strip_prefix("*.").map(|x| x.to_owned()))
+                {
+                    decl.extensions.push(ext);
+                }
+            }
+        }
+    }
+    decls
+}
+
+fn xml_unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// Rebuilds the MIME database from its packages/ directory, so new types are recognized.
+pub fn update_mime_database(mime_dir: &Path) -> Result<(), String> {
+    let output = match Command::new("update-mime-database").arg(mime_dir).output() {
+        Ok(x) => x,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => bailf!(
+            "update-mime-database isn't installed, new file types won't be recognized until it's \
+            run on '{}'",
+            mime_dir.display()
+        ),
+        Err(e) => bailf!("Couldn't run update-mime-database: {e}"),
+    };
+
+    if !output.status.success() {
+        bailf!(
+            "update-mime-database failed, new file types won't be recognized until it's run on \
+            '{}': {}",
+            mime_dir.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Escapes a value of type string or localestring.
+fn escape_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some('s') => unescaped.push(' '),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+// Quotes an argument of the Exec key. Done before escape_value().
+fn quote_exec_arg(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' | '`' | '$' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '%' => quoted.push_str("%%"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// An Exec value that runs the shim from wherever $XDG_DATA_HOME is, for an entry that's moved to
+// another machine, where render()'s absolute path may not exist.
+pub fn portable_exec(app_name: &OsStr) -> String {
+    let relative = Path::new("echidna").join(app_name).join(app_name);
+    let script = format!(
+        "exec \"${{XDG_DATA_HOME:-$HOME/.local/share}}\"/{} \"$@\"",
+        bash_quote(relative).to_string_lossy()
+    );
+    format!("bash -c {} bash %F", quote_exec_arg(&script))
+}
+
+pub fn entry_comment(command: &str) -> String {
+    format!("Open files with {command}")
+}
+
+// Replaces the value of key in the [Desktop Entry] group, or adds it, leaving everything else as
+// it was.
+pub fn set_entry_key(contents: &str, key: &str, value: &str) -> String {
+    let line = format!("{key}={}", escape_value(value));
+    let mut lines: Vec<String> = vec![];
+    let (mut in_group, mut group_end, mut found) = (false, None, false);
+    for current in contents.lines() {
+        let trimmed = current.trim();
+        if trimmed.starts_with('[') {
+            if in_group && group_end.is_none() {
+                group_end = Some(lines.len());
+            }
+            in_group = trimmed == "[Desktop Entry]";
+        } else if in_group && trimmed.split_once('=').is_some_and(|x| x.0.trim() == key) {
+            lines.push(line.clone());
+            found = true;
+            continue;
+        }
+        lines.push(current.to_owned());
+    }
+
+    if !found {
+        lines.insert(group_end.unwrap_or(lines.len()), line);
+    }
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    contents
+}
+
+pub struct DesktopEntry<'a> {
+    pub name: &'a str,
+    pub comment: &'a str,
+    pub exec: &'a Path,
+    pub icon: &'a str,
+    pub mime_types: &'a [String],
+}
+
+impl DesktopEntry<'_> {
+    pub fn render(&self) -> Result<String, String> {
+        let Some(exec) = self.exec.to_str() else {
+            bailf!(
+                "Shim path '{}' must be valid UTF-8 for a desktop entry",
+                self.exec.display()
+            );
+        };
+
+        let mut entry = String::from("[Desktop Entry]\n");
+        let mut push = |key: &str, value: &str| {
+            entry.push_str(key);
+            entry.push('=');
+            entry.push_str(value);
+            entry.push('\n');
+        };
+
+        push("Type", "Application");
+        push("Version", "1.5");
+        push("Name", &escape_value(self.name));
+        push("Comment", &escape_value(self.comment));
+        push(
+            "Exec",
+            &escape_value(&format!("{} %F", quote_exec_arg(exec))),
+        );
+        push("Icon", &escape_value(self.icon));
+        push("Terminal", "false");
+        push("NoDisplay", "false");
+        push("Categories", "Utility;");
+        if !self.mime_types.is_empty() {
+            let mut types = self.mime_types.join(";");
+            types.push(';');
+            push("MimeType", &escape_value(&types));
+        }
+        push("X-Echidna-Version", env!("CARGO_PKG_VERSION"));
+
+        Ok(entry)
+    }
+}
+
+// The keys of a desktop entry's [Desktop Entry] group, with their values unescaped. Localized
+// keys are left out.
+pub fn parse_entry(contents: &str) -> HashMap<String, String> {
+    let mut keys = HashMap::new();
+    let mut in_group = false;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_group = line == "[Desktop Entry]";
+            continue;
+        }
+        if !in_group || line.starts_with('#') {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            let key = key.trim();
+            if !key.contains('[') {
+                keys.insert(key.to_owned(), unescape_value(value.trim()));
+            }
+        }
+    }
+    keys
+}
+
+// Refreshes the MIME type cache for the applications directory, so the new entry shows up in
+// "Open With". Not all systems have update-desktop-database, and they'll work without it.
+pub fn update_desktop_database(applications: &Path) -> Result<(), String> {
+    let output = match Command::new("update-desktop-database")
+        .arg(applications)
+        .output()
+    {
+        Ok(x) => x,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => bailf!("Couldn't run update-desktop-database: {e}"),
+    };
+
+    if !output.status.success() {
+        bailf!(
+            "update-desktop-database failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::DocTypeGroup;
+
+    #[test]
+    fn unescape_value_reverses_escape_value() {
+        for value in ["plain", "a\\b", "two\nlines", "tab\there", "cr\r", ""] {
+            assert_eq!(unescape_value(&escape_value(value)), value);
+        }
+        assert_eq!(unescape_value("a\\sb"), "a b");
+        assert_eq!(unescape_value(r"\;"), ";");
+        assert_eq!(unescape_value("trailing\\"), "trailing\\");
+    }
+
+    #[test]
+    fn parse_entry_reads_desktop_entry_group() {
+        let entry = "\
+# A comment
+[Desktop Entry]
+Name = Vim\\sShim
+Comment=Open files with vim\\n-p
+Name[de]=Vim auf Deutsch
+# Exec=commented out
+Exec=\"/x/Vim\" %F
+
+[Desktop Action New]
+Name=New Window
+Exec=other
+";
+        let keys = parse_entry(entry);
+        assert_eq!(keys["Name"], "Vim Shim");
+        assert_eq!(keys["Comment"], "Open files with vim\n-p");
+        assert_eq!(keys["Exec"], "\"/x/Vim\" %F");
+        assert_eq!(keys.len(), 3);
+    }
+
+    #[test]
+    fn parse_entry_reads_rendered_entry() {
+        let mime_types = ["text/plain".to_owned(), "application/x-foo".to_owned()];
+        let rendered = DesktopEntry {
+            name: "Vim",
+            comment: &entry_comment("vim -p"),
+            exec: Path::new("/data/echidna/Vim/Vim"),
+            icon: "echidna-Vim",
+            mime_types: &mime_types,
+        }
+        .render()
+        .unwrap();
+
+        let keys = parse_entry(&rendered);
+        assert_eq!(keys["Name"], "Vim");
+        assert_eq!(keys["Comment"], "Open files with vim -p");
+        assert_eq!(keys["Exec"], "\"/data/echidna/Vim/Vim\" %F");
+        assert_eq!(keys["MimeType"], "text/plain;application/x-foo;");
+    }
+
+    #[test]
+    fn mime_types_leaves_out_catch_all_utis() {
+        let globs = MimeGlobs::default();
+        let (types, warnings) = mime_types(&DocTypes::text_files(), &globs, &[]);
+        assert_eq!(types, ["text/plain"]);
+        assert!(warnings.is_empty());
+
+        let (types, warnings) = mime_types(&DocTypes::all_docs(), &globs, &[]);
+        assert!(types.is_empty());
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn mime_types_takes_mime_types_only_from_their_field() {
+        let doc_types = DocTypes(vec![DocTypeGroup {
+            utis: vec!["text/markdown".to_owned()],
+            mime_types: vec!["text/x-log".to_owned()],
+            ..Default::default()
+        }]);
+        let (types, warnings) = mime_types(&doc_types, &MimeGlobs::default(), &[]);
+        assert_eq!(types, ["text/x-log"]);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn portable_exec_runs_shim_from_data_home() {
+        let entry = set_entry_key(
+            "[Desktop Entry]\nExec=\"/x/Vim\" %F\n",
+            "Exec",
+            &portable_exec(OsStr::new("My Vim")),
+        );
+        assert_eq!(
+            parse_entry(&entry)["Exec"],
+            r#"bash -c "exec \"\${XDG_DATA_HOME:-\$HOME/.local/share}\"/\$'echidna/My Vim/My Vim' \"\$@\"" bash %F"#
+        );
+    }
+
+    #[test]
+    fn parse_mime_package_reads_rendered_package() {
+        let decls = [
+            TypeDecl {
+                identifier: "echidna-tfvars".to_owned(),
+                mime_type: "application/x-echidna-tfvars".to_owned(),
+                description: "Terraform <vars> & more".to_owned(),
+                conforms_to: vec!["public.data".to_owned()],
+                extensions: vec!["tfvars".to_owned(), "tfvars.json".to_owned()],
+                icon: None,
+            },
+            TypeDecl {
+                identifier: "text.vnd.example".to_owned(),
+                mime_type: "text/vnd.example".to_owned(),
+                description: String::new(),
+                conforms_to: vec!["public.data".to_owned()],
+                extensions: vec!["ex".to_owned()],
+                icon: None,
+            },
+        ];
+        assert_eq!(parse_mime_package(&render_mime_package(&decls)), decls);
+    }
+
+    #[test]
+    fn parse_mime_package_skips_what_it_doesnt_know() {
+        let xml = "\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<mime-info xmlns=\"http://www.freedesktop.org/standards/shared-mime-info\">
+    <glob pattern=\"*.orphan\"/>
+    <mime-type>
+    <mime-type type=\"application/x-foo\">
+        <glob pattern=\"foo-*\"/>
+        <glob pattern=\"*.foo\"/>
+        <magic priority=\"50\"/>
+    </mime-type>
+</mime-info>
+";
+        let decls = parse_mime_package(xml);
+        assert_eq!(decls.len(), 1);
+        assert_eq!(decls[0].identifier, "foo");
+        assert_eq!(decls[0].mime_type, "application/x-foo");
+        assert_eq!(decls[0].extensions, ["foo"]);
+    }
+}
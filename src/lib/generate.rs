@@ -1,115 +1,300 @@
+use crate::bailf;
 use crate::bundle_tmp_dir::BundleTmpDir;
 use crate::config::Config;
+use crate::defaults::{self, Handler};
+use crate::desktop::{self, DesktopEntry, MimeGlobs};
+use crate::icon;
+use crate::icon_composer::ShimIcon;
+use crate::info_plist::{self, InfoPlist};
+use crate::output::{MemTree, OutputTarget, TempDirOutput};
+use crate::registry;
+use crate::replace::{self, Move};
+use crate::shim::{self, embedded_version};
+use crate::type_decl::{self, TypeDecl};
 
+use std::collections::BTreeSet;
+use std::env;
 use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::fs;
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 
-use rand::Rng;
+use clap::ValueEnum;
+use plist::Dictionary;
+use serde::{Deserialize, Serialize};
 
-const INFO_PLIST_TEMPLATE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
-<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
-<plist version="1.0">
-<dict>
-    <key>CFBundleDevelopmentRegion</key>
-    <string>en</string>
+// The kind of shim to generate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Target {
+    // An .app bundle.
+    #[value(name = "macos")]
+    #[serde(alias = "macos")]
+    MacOs,
+    // A freedesktop desktop entry and a data directory.
+    #[serde(alias = "linux")]
+    Linux,
+}
+
+impl Target {
+    pub fn host() -> Target {
+        if cfg!(target_os = "macos") {
+            Target::MacOs
+        } else {
+            Target::Linux
+        }
+    }
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self::host()
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // For use by clap, lower case since actual cli arguments would be lower case
+        match self {
+            Target::MacOs => write!(f, "macos"),
+            Target::Linux => write!(f, "linux"),
+        }
+    }
+}
 
-    <key>CFBundleDocumentTypes</key>
-    <array>
-        <dict>
+// CFBundleTypeRole: what the shim does with the documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+pub enum Role {
+    #[default]
+    #[serde(alias = "editor")]
+    Editor,
+    #[serde(alias = "viewer")]
+    Viewer,
+    #[serde(alias = "shell")]
+    Shell,
+    // Declares the type without opening it.
+    #[serde(alias = "none")]
+    None,
+}
 
-            {{#if utis}}
-            <key>LSItemContentTypes</key>
-            <array>
-                {{#each utis}}
-                <string>{{this}}</string>
-                {{/each}}
-            </array>
-            {{else if exts}}
-            <key>CFBundleTypeExtensions</key>
-            <array>
-                {{#each exts}}
-                <string>{{this}}</string>
-                {{/each}}
-            </array>
-            {{/if}}
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Also the Info.plist values.
+        match self {
+            Role::Editor => write!(f, "Editor"),
+            Role::Viewer => write!(f, "Viewer"),
+            Role::Shell => write!(f, "Shell"),
+            Role::None => write!(f, "None"),
+        }
+    }
+}
 
-            <key>CFBundleTypeRole</key>
-            <string>Editor</string>
+// LSHandlerRank: how strongly the shim claims the documents. None means never use the shim for
+// them, leaving it out altogether (Option::None) lets macOS decide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Rank {
+    #[serde(alias = "owner")]
+    Owner,
+    #[serde(alias = "default")]
+    Default,
+    #[serde(alias = "alternate")]
+    Alternate,
+    #[serde(alias = "none")]
+    None,
+}
 
-        </dict>
-    </array>
+impl fmt::Display for Rank {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Also the Info.plist values.
+        match self {
+            Rank::Owner => write!(f, "Owner"),
+            Rank::Default => write!(f, "Default"),
+            Rank::Alternate => write!(f, "Alternate"),
+            Rank::None => write!(f, "None"),
+        }
+    }
+}
 
+// One CFBundleDocumentTypes entry: documents, by UTI and/or extension, that the shim handles the
+// same way.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct DocTypeGroup {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub utis: Vec<String>,
+    // Without the leading '.', "*" is no extension.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exts: Vec<String>,
+    // Linux only, e.g. read back from a Linux shim's MimeType, where they can't be told apart
+    // from those its UTIs and extensions were mapped to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mime_types: Vec<String>,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rank: Option<Rank>,
+    // Any image icon::load_image() accepts. macOS only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<PathBuf>,
+}
 
-    <key>CFBundleExecutable</key>
-    <string>{{app_display_name}}</string>
+// Splits a comma-delimited list.
+fn split_list(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
 
-    <key>CFBundleIconFile</key>
-    <string>AppIcon.icns</string>
+impl DocTypeGroup {
+    pub fn with_utis(utis: &[&str]) -> DocTypeGroup {
+        DocTypeGroup {
+            utis: utis.iter().map(|x| (*x).to_owned()).collect(),
+            ..Default::default()
+        }
+    }
 
-    <key>CFBundleIdentifier</key>
-    <string>{{bundle_id}}</string>
+    // Validates the group and tidies up what the user typed. A relative icon path is relative
+    // to base_dir.
+    pub fn normalized(mut self, base_dir: Option<&Path>) -> Result<DocTypeGroup, String> {
+        self.utis = self
+            .utis
+            .iter()
+            .map(|x| x.trim().to_owned())
+            .filter(|x| !x.is_empty())
+            .collect();
+        self.exts = self
+            .exts
+            .iter()
+            .map(|x| x.trim().trim_start_matches('.').to_owned())
+            .filter(|x| !x.is_empty())
+            .collect();
+        self.mime_types = self
+            .mime_types
+            .iter()
+            .map(|x| x.trim().to_owned())
+            .filter(|x| !x.is_empty())
+            .collect();
 
-    <key>CFBundleInfoDictionaryVersion</key>
-    <string>6.0</string>
+        if self.utis.is_empty() && self.exts.is_empty() && self.mime_types.is_empty() {
+            let name = self.name.as_deref().unwrap_or("unnamed");
+            return Err(format!(
+                "Document type group '{name}' needs at least one UTI, extension or MIME type"
+            ));
+        }
 
-    <key>CFBundleName</key>
-    <string>{{app_display_name}}</string>
+        if let (Some(icon), Some(base_dir)) = (&self.icon, base_dir) {
+            self.icon = Some(base_dir.join(icon));
+        }
 
-    <key>CFBundlePackageType</key>
-    <string>APPL</string>
+        Ok(self)
+    }
 
-    <key>CFBundleShortVersionString</key>
-    <string>0.2.0</string>
-</dict>
-</plist>
-"#;
+    // Whether the shim actually opens these documents.
+    pub fn opens(&self) -> bool {
+        self.role != Role::None && self.rank != Some(Rank::None)
+    }
+}
 
-const SHIM_APP_ICON: &[u8] = include_bytes!("../../app_files/ShimAppIcon.icns");
+// The documents a shim can open, as groups. The constructors are for the common cases, a single
+// Editor group.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocTypes(pub Vec<DocTypeGroup>);
 
-// UTIs and Exts are comma-delimited lists of Uniform Type Identifiers and Extensions,
-// respectively.
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
-pub enum DocTypes {
-    #[default]
-    TextFiles,
-    AllDocs,
-    UTIs(String),
-    Exts(String),
+impl Default for DocTypes {
+    fn default() -> Self {
+        Self::text_files()
+    }
 }
 
 impl DocTypes {
-    pub fn to_info_kv(&self) -> (String, Vec<String>) {
-        use DocTypes::*;
-        match self {
-            TextFiles => (
-                "utis".to_owned(),
-                vec!["public.text".to_owned(), "public.data".to_owned()],
-            ),
-            AllDocs => (
-                "utis".to_owned(),
-                vec!["public.content".to_owned(), "public.data".to_owned()],
-            ),
-            UTIs(s) => {
-                let v = s
-                    .split(',')
-                    .map(|x| x.trim().to_owned())
-                    .filter(|x| !x.is_empty())
-                    .collect::<Vec<_>>();
-                ("utis".to_owned(), v)
+    pub fn text_files() -> DocTypes {
+        DocTypes(vec![DocTypeGroup::with_utis(&[
+            "public.text",
+            "public.data",
+        ])])
+    }
+
+    pub fn all_docs() -> DocTypes {
+        DocTypes(vec![DocTypeGroup::with_utis(&[
+            "public.content",
+            "public.data",
+        ])])
+    }
+
+    // A comma-delimited list of Uniform Type Identifiers.
+    pub fn utis(s: &str) -> DocTypes {
+        DocTypes(vec![DocTypeGroup {
+            utis: split_list(s),
+            ..Default::default()
+        }])
+    }
+
+    // A comma-delimited list of extensions, with or without leading '.'s.
+    pub fn exts(s: &str) -> DocTypes {
+        DocTypes(vec![DocTypeGroup {
+            exts: split_list(s)
+                .into_iter()
+                .map(|x| x.trim_start_matches('.').to_owned())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            ..Default::default()
+        }])
+    }
+
+    // Reads a JSON array of groups, e.g.
+    // [{"name": "PDF", "utis": ["com.adobe.pdf"], "role": "Viewer", "rank": "Alternate"}]
+    pub fn load(path: &Path) -> Result<DocTypes, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Error reading document types '{}': {e}", path.display()))?;
+        let groups: Vec<DocTypeGroup> = serde_json::from_str(&contents)
+            .map_err(|e| format!("Error parsing document types '{}': {e}", path.display()))?;
+
+        if groups.is_empty() {
+            bailf!("No document types in '{}'", path.display());
+        }
+
+        let groups = groups
+            .into_iter()
+            .map(|x| x.normalized(path.parent()))
+            .collect::<Result<_, _>>()?;
+        Ok(DocTypes(groups))
+    }
+
+    pub fn groups(&self) -> &[DocTypeGroup] {
+        &self.0
+    }
+
+    // Every UTI, in order, without duplicates.
+    pub fn all_utis(&self) -> Vec<String> {
+        let mut utis: Vec<String> = vec![];
+        for uti in self.0.iter().flat_map(|x| &x.utis) {
+            if !utis.contains(uti) {
+                utis.push(uti.clone());
             }
-            Exts(s) => {
-                let v = s
-                    .split(',')
-                    .map(|x| x.trim().trim_start_matches('.').to_owned())
-                    .filter(|x| !x.is_empty())
-                    .collect::<Vec<_>>();
-                ("exts".to_owned(), v)
+        }
+        utis
+    }
+
+    // Every extension, in order, without duplicates.
+    pub fn all_exts(&self) -> Vec<String> {
+        let mut exts: Vec<String> = vec![];
+        for ext in self.0.iter().flat_map(|x| &x.exts) {
+            if !exts.contains(ext) {
+                exts.push(ext.clone());
             }
         }
+        exts
+    }
+
+    // The icon's name in the bundle's Resources, for the group at index.
+    pub fn icon_file_name(index: usize) -> String {
+        format!("DocType{index}.icns")
     }
 }
 
+#[derive(Debug)]
 pub enum SaveErr {
     // AppAlreadyExists is separated out to give the user an opportunity to ovewrite.
     AppAlreadyExists,
@@ -130,54 +315,62 @@ impl SaveErr {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[cfg(target_os = "macos")]
-fn write_shim_bin(mac_os: &Path, app_name: &OsStr, shim_bin: &Path) -> Result<(), String> {
-    let bin_path = mac_os.join(app_name);
-    fs::copy(shim_bin, bin_path).map_err(|e| {
-        format!(
-            "Error copying shim binary '{}' to temporary directory '{}': {e}",
-            shim_bin.display(),
-            mac_os.display()
-        )
-    })?;
-
-    Ok(())
+fn write_shim_bin(
+    output: &mut dyn OutputTarget,
+    mac_os: &Path,
+    app_name: &OsStr,
+    shim_bin: &Path,
+) -> Result<(), String> {
+    output.copy_executable(shim_bin, &mac_os.join(app_name))
 }
 
 fn write_info_plist(
+    output: &mut dyn OutputTarget,
     contents: &Path,
-    app_name: &str,
-    doc_type: &DocTypes,
-    bundle_id: &str,
+    info_plist: &Dictionary,
 ) -> Result<(), String> {
-    let (file_selectors_key, file_selectors) = doc_type.to_info_kv();
-
-    let reg = handlebars::Handlebars::new();
-    let rendered = reg
-        .render_template(
-            INFO_PLIST_TEMPLATE,
-            &serde_json::json!({
-                "app_display_name": app_name,
-                file_selectors_key: file_selectors,
-                "bundle_id": bundle_id,
-            }),
-        )
-        .map_err(|e| format!("Error rendering Info.plist template: {e}"))?;
+    output.write_file(
+        &contents.join("Info.plist"),
+        &info_plist::to_xml(info_plist)?,
+    )
+}
 
-    let plist_dir = contents.join("Info.plist");
+// Longest file name most filesystems allow, in bytes.
+const MAX_NAME_LEN: usize = 255;
 
-    fs::write(&plist_dir, rendered).map_err(|e| {
-        format!(
-            "Error writing Info.plist to temporary directory '{}': {e}",
-            plist_dir.display()
-        )
-    })?;
+// Checks that name can name a bundle (without its extension), and trims surrounding whitespace.
+pub fn normalize_app_name(name: &OsStr) -> Result<OsString, String> {
+    let Some(name) = name.to_str() else {
+        bailf!("App name '{}' isn't valid UTF-8", name.to_string_lossy());
+    };
+    let invalid = |why: &str| Err(format!("Invalid app name '{name}': {why}"));
 
-    Ok(())
+    let name = name.trim();
+    if name.is_empty() {
+        return invalid("it's empty");
+    }
+    if name.starts_with('.') {
+        return invalid("it starts with '.', which would hide it");
+    }
+    // Finder shows '/' as ':' and vice versa, so neither does what the user expects.
+    if let Some(c) = name
+        .chars()
+        .find(|c| matches!(c, '/' | ':') || c.is_control())
+    {
+        return invalid(&format!("{c:?} isn't allowed"));
+    }
+    // Room for the extension.
+    if name.len() + ".desktop".len() > MAX_NAME_LEN {
+        return invalid(&format!(
+            "it's longer than {} bytes",
+            MAX_NAME_LEN - ".desktop".len()
+        ));
+    }
+    Ok(name.into())
 }
 
-// Returns (app_name, bundle_name); app_name is without .app, bundle_* has it
-fn get_names(mut app_path: PathBuf) -> Result<(OsString, OsString, PathBuf), String> {
+// Returns the file name of app_path, without its extension if it's ext, normalized.
+fn get_app_name(app_path: &Path, ext: &str) -> Result<OsString, String> {
     let file_name = || {
         app_path
             .file_name()
@@ -185,21 +378,19 @@ fn get_names(mut app_path: PathBuf) -> Result<(OsString, OsString, PathBuf), Str
             .ok_or_else(|| format!("Couldn't get file name from {}", app_path.display()))
     };
 
-    let app_name = match app_path.extension() {
-        Some(ext) => {
-            if ext == "app" {
-                app_path
-                    .file_stem()
-                    .ok_or_else(|| {
-                        format!("Couldn't get app name from path '{}'", app_path.display())
-                    })?
-                    .to_owned()
-            } else {
-                file_name()?
-            }
-        }
-        None => file_name()?,
+    let name = match app_path.extension() {
+        Some(x) if x == ext => app_path
+            .file_stem()
+            .ok_or_else(|| format!("Couldn't get app name from path '{}'", app_path.display()))?
+            .to_owned(),
+        Some(_) | None => file_name()?,
     };
+    normalize_app_name(&name)
+}
+
+// Returns (app_name, bundle_name); app_name is without .app, bundle_* has it
+fn get_names(mut app_path: PathBuf) -> Result<(OsString, OsString, PathBuf), String> {
+    let app_name = get_app_name(&app_path, "app")?;
 
     let mut bundle_name = app_name.clone();
     bundle_name.push(".app");
@@ -209,149 +400,1269 @@ fn get_names(mut app_path: PathBuf) -> Result<(OsString, OsString, PathBuf), Str
     Ok((app_name, bundle_name, app_path))
 }
 
-fn write_icon(icon_path: Option<&Path>, resources: &Path) -> Result<(), String> {
-    let mut shim_icon = resources.to_owned();
-    shim_icon.push("AppIcon.icns");
-    let icon_path = match icon_path {
-        Some(x) => x,
-        None => {
-            return fs::write(&shim_icon, SHIM_APP_ICON).map_err(|e| {
-                format!(
-                    "Error write shim's icon to temorary '{}': {e}",
-                    shim_icon.display()
-                )
-            })
-        }
-    };
+fn write_icon(
+    output: &mut dyn OutputTarget,
+    warnings: &mut Vec<String>,
+    icon: &ShimIcon,
+    resources: &Path,
+    file_name: &str,
+) -> Result<(), String> {
+    let (icns, warning) = icon::icns_for(icon)?;
+    warnings.extend(warning);
+    output.write_file(&resources.join(file_name), &icns)
+}
 
-    let ext = icon_path.extension();
-    if ext == Some(OsStr::new("png")) || ext == Some(OsStr::new("icns")) {
-        return fs::copy(icon_path, &shim_icon).map(|_| ()).map_err(|e| {
-            format!(
-                "Error copying custom shim from '{}' to temorary '{}': {e}",
-                icon_path.display(),
-                shim_icon.display()
-            )
-        });
+// Stages the icon at every hicolor size, returning the installs for them.
+fn write_hicolor_icons(
+    output: &mut dyn OutputTarget,
+    warnings: &mut Vec<String>,
+    icon: &ShimIcon,
+    app_name: &OsStr,
+) -> Result<Vec<Install>, String> {
+    let (source, warning) = icon.load()?;
+    warnings.extend(warning);
+    let icons_dir = desktop::icons_dir()?;
+
+    let mut installs = vec![];
+    for (size, png) in icon::hicolor_pngs(&source)? {
+        let relative = desktop::hicolor_icon_path(app_name, size);
+
+        // Flattened, the staged name just needs to be unique.
+        let tmp_icon = PathBuf::from(format!("icon_{size}.png"));
+        output.write_file(&tmp_icon, &png)?;
+
+        installs.push(Install::new(tmp_icon, icons_dir.join(relative), true));
     }
 
-    let image = image::open(icon_path)
-        .map_err(|e| format!("Error loading icon from '{}': {e}", icon_path.display()))?;
-    image
-        .save_with_format(&shim_icon, image::ImageFormat::Png)
-        .map_err(|e| {
-            format!(
-                "Error writing icon to temporary '{}': {e}",
-                shim_icon.display()
-            )
-        })?;
+    Ok(installs)
+}
 
-    Ok(())
+// Whether the system already has a type for the extension. Only answerable on macOS itself,
+// elsewhere assume it does, so nothing gets declared needlessly.
+fn macos_knows_ext(ext: &str) -> bool {
+    #[cfg(target_os = "macos")]
+    {
+        crate::launch_services::is_known_ext(ext)
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = ext;
+        true
+    }
 }
 
-fn generate_bundle_id(app_name: &str) -> String {
-    let hostname = gethostname::gethostname();
-    let num = rand::thread_rng().gen_range(0..=999999);
-    format!("local.{}.{app_name}{num}", hostname.to_string_lossy())
+// How to choose a macOS shim's CFBundleIdentifier. An existing bundle's is reused, so macOS still
+// treats it as the same app, unless any of these are given.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleIdOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    // [default: local.<hostname>]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    // Used instead of the app's name for the last component.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<String>,
 }
 
-fn save_bundle<S: AsRef<Path>, D: AsRef<Path>>(
-    tmp_bundle: S,
-    dst_bundle: D,
-    overwrite: bool,
-) -> Result<(), SaveErr> {
-    // Unfortunately not atomic, but an effort is made the protect existing data (unfortunately,
-    // this effort isn't atomic either), but it should be ease to recreate shim apps.
+// Apple's limit for CFBundleIdentifier.
+const MAX_BUNDLE_ID_LEN: usize = 255;
 
-    let (tmp_bundle, dst_bundle) = (tmp_bundle.as_ref(), dst_bundle.as_ref());
+// Checks a bundle identifier against Apple's rules: reverse-DNS, with only ASCII letters, digits,
+// '-' and '.'.
+pub fn validate_bundle_id(id: &str) -> Result<(), String> {
+    let invalid = |why: &str| Err(format!("Invalid bundle identifier '{id}': {why}"));
 
-    // We'll get an error if we try to overwrite a non-empty bundle (the likely case). Instead,
-    // try to move it to the tmp dir; if we get an error trying to move the new bundle, we can try to
-    // move the original one back. This process is also failable, so its best-effort.
-    let mut saved_bundle = None;
-    if overwrite && dst_bundle.exists() && dst_bundle.file_name() == tmp_bundle.file_name() {
-        let saved_bundle_inner = tmp_bundle.with_extension("bak");
-        if let Ok(()) = fs::rename(dst_bundle, &saved_bundle_inner) {
-            saved_bundle = Some(saved_bundle_inner);
-        } // No else, best effort.
+    if id.is_empty() {
+        return invalid("it's empty");
+    }
+    if id.len() > MAX_BUNDLE_ID_LEN {
+        return invalid(&format!("it's longer than {MAX_BUNDLE_ID_LEN} characters"));
+    }
+    if let Some(c) = id
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && *c != '-' && *c != '.')
+    {
+        return invalid(&format!(
+            "'{c}' isn't allowed, only ASCII letters, digits, '-' and '.' are"
+        ));
+    }
+    if id.split('.').any(|x| x.is_empty()) {
+        return invalid("it has an empty component (leading, trailing or repeated '.')");
     }
+    Ok(())
+}
 
-    // The actual rename
-    let res = fs::rename(tmp_bundle, dst_bundle).map_err(|e| {
-        match e.raw_os_error() {
-            Some(libc::ENOTEMPTY) => SaveErr::AppAlreadyExists, // ErrorKind::DirectoryNotEmpty not available on stable
-            Some(_) | None => SaveErr::Other(format!(
-                "Error moving temporary app '{}' to out_dir '{}': {e}",
-                tmp_bundle.display(),
-                dst_bundle.display(),
-            )),
+// Makes one component of a bundle identifier from anything, replacing runs of characters that
+// aren't allowed with '-'.
+fn id_component(s: &str) -> String {
+    let mut component = String::new();
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() {
+            component.push(c);
+        } else if !component.ends_with('-') {
+            component.push('-');
         }
-    });
+    }
+    component.trim_matches('-').to_owned()
+}
 
-    if res.is_err() && saved_bundle.is_some() {
-        if let Some(saved) = saved_bundle {
-            // Best effort.
-            let _ = fs::rename(saved, dst_bundle);
+// Makes a valid bundle identifier, or prefix, from anything, normalizing each '.'-separated
+// component and dropping empty ones.
+pub fn normalize_bundle_id(s: &str) -> String {
+    s.split('.')
+        .map(id_component)
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+impl BundleIdOptions {
+    // The same every time for the same app name (or seed) and prefix. Only a given id is used as
+    // it is, anything derived is normalized.
+    pub fn derive(&self, app_name: &str) -> Result<String, String> {
+        if let Some(id) = &self.id {
+            validate_bundle_id(id)?;
+            return Ok(id.clone());
         }
+
+        let prefix = match &self.prefix {
+            Some(x) => {
+                let prefix = normalize_bundle_id(x);
+                if prefix.is_empty() {
+                    bailf!("Invalid bundle identifier prefix '{x}'");
+                }
+                prefix
+            }
+            None => {
+                let hostname = gethostname::gethostname().to_string_lossy().into_owned();
+                // Just the host, e.g. from "Jane’s MacBook Pro.local".
+                let host = hostname.split('.').next().unwrap_or_default();
+                match id_component(host) {
+                    x if x.is_empty() => "local".to_owned(),
+                    x => format!("local.{x}"),
+                }
+            }
+        };
+
+        let last = match id_component(self.seed.as_deref().unwrap_or(app_name)) {
+            x if x.is_empty() => "shim".to_owned(),
+            x => x,
+        };
+
+        let id = format!("{prefix}.{last}");
+        validate_bundle_id(&id)?;
+        Ok(id)
+    }
+}
+
+// The next CFBundleVersion after previous: its last number, incremented. A last component like
+// "0b3" or "1-rc" has its leading number incremented and the rest dropped, and one with no leading
+// number gets ".1" appended, so the version never goes down.
+pub fn bump_bundle_version(previous: Option<&str>) -> String {
+    let previous = previous.map(str::trim).unwrap_or_default();
+    if previous.is_empty() {
+        return "1".to_owned();
     }
 
-    res
+    let (head, last) = match previous.rsplit_once('.') {
+        Some((head, last)) => (format!("{head}."), last),
+        None => (String::new(), previous),
+    };
+    let digits = last.len() - last.trim_start_matches(|x: char| x.is_ascii_digit()).len();
+    match last[..digits].parse::<u64>() {
+        Ok(n) => format!("{head}{}", n + 1),
+        Err(_) => format!("{previous}.1"),
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub struct Generator {
-    tmp_dir: BundleTmpDir,
+// Run after a successful save(), failures are only warnings.
+enum PostSave {
+    UpdateDesktopDatabase(PathBuf),
+    UpdateMimeDatabase(PathBuf),
+}
+
+impl PostSave {
+    fn run(&self) -> Result<(), String> {
+        match self {
+            PostSave::UpdateDesktopDatabase(dir) => desktop::update_desktop_database(dir),
+            PostSave::UpdateMimeDatabase(dir) => desktop::update_mime_database(dir),
+        }
+    }
+}
+
+// A file or directory staged in the output target (relative to its root), and where save() moves
+// it.
+struct Install {
+    staged: PathBuf,
+    dst: PathBuf,
+    create_parent: bool, // For standard directories that may not exist yet.
+}
+
+impl Install {
+    fn new(staged: PathBuf, dst: PathBuf, create_parent: bool) -> Install {
+        Install {
+            staged,
+            dst,
+            create_parent,
+        }
+    }
+}
+
+// Whether a staged file or directory has the same contents as dst. Info.plists are compared without
+// CFBundleVersion, which is bumped every time.
+fn same_contents(staged: &Path, dst: &Path) -> bool {
+    let (Ok(a), Ok(b)) = (fs::symlink_metadata(staged), fs::symlink_metadata(dst)) else {
+        return false;
+    };
+
+    if a.is_symlink() || b.is_symlink() {
+        return a.is_symlink()
+            && b.is_symlink()
+            && fs::read_link(staged).ok() == fs::read_link(dst).ok();
+    }
+
+    if a.is_dir() || b.is_dir() {
+        let names = |dir: &Path| -> Option<BTreeSet<OsString>> {
+            fs::read_dir(dir)
+                .ok()?
+                .map(|x| x.ok().map(|x| x.file_name()))
+                .collect()
+        };
+        let (Some(x), Some(y)) = (names(staged), names(dst)) else {
+            return false;
+        };
+        return a.is_dir()
+            && b.is_dir()
+            && x == y
+            && x.iter()
+                .all(|name| same_contents(&staged.join(name), &dst.join(name)));
+    }
+
+    if staged.file_name() == Some(OsStr::new("Info.plist")) {
+        let read = |path: &Path| {
+            let mut dict = plist::Value::from_file(path).ok()?.into_dictionary()?;
+            dict.remove("CFBundleVersion");
+            Some(dict)
+        };
+        let staged = read(staged);
+        return staged.is_some() && staged == read(dst);
+    }
+
+    a.len() == b.len()
+        && a.permissions().mode() == b.permissions().mode()
+        && fs::read(staged).ok() == fs::read(dst).ok()
+}
+
+pub struct Generator<T: OutputTarget = TempDirOutput> {
+    output: T,
+    installs: Vec<Install>,
+    post_save: Vec<PostSave>,
+    handler: Option<Handler>,
     final_bundle_path: PathBuf,
+    // For the registry.
+    app_name: String,
+    command: String,
+    warnings: Vec<String>,
+    keep_backup: bool,
     saved: bool,
 }
 
-impl Generator {
-    // YOU MUST STILL CALL SAVE() AFTER.
+impl Generator<TempDirOutput> {
+    // YOU MUST STILL CALL SAVE() AFTER. type_decls are the user's own file types, opened in
+    // addition to doc_type's. extra_info_plist's keys are merged into (and override) the
+    // generated Info.plist's, it's ignored for Linux.
+    #[allow(clippy::too_many_arguments)]
     pub fn gen(
         config: &Config,
         doc_type: &DocTypes,
         shim_bin: &Path,
-        bundle_id: Option<&str>,
-        icon_path: Option<&Path>,
+        bundle_id: &BundleIdOptions,
+        icon: &ShimIcon,
         app_path: PathBuf,
+        target: Target,
+        type_decls: &[TypeDecl],
+        extra_info_plist: Option<&Dictionary>,
     ) -> Result<Generator, String> {
+        Generator::gen_into(
+            TempDirOutput::new()?,
+            config,
+            doc_type,
+            shim_bin,
+            bundle_id,
+            icon,
+            app_path,
+            target,
+            type_decls,
+            extra_info_plist,
+        )
+    }
+
+    // Installs everything or, on an error, nothing. Safe to call again after an error, but not
+    // after a success.
+    pub fn save(&mut self, overwrite: bool) -> Result<(), SaveErr> {
+        assert!(!self.saved);
+
+        let mut moves = vec![];
+        for install in &self.installs {
+            if let (true, Some(parent)) = (install.create_parent, install.dst.parent()) {
+                fs::create_dir_all(parent).map_err(|e| {
+                    SaveErr::Other(format!(
+                        "Error creating directory '{}': {e}",
+                        parent.display()
+                    ))
+                })?;
+            }
+
+            // Only bundles and directories, backups of single files would just be clutter.
+            let src = self.output.root().join(&install.staged);
+            moves.push(Move {
+                backup: self.keep_backup && src.is_dir(),
+                src,
+                dst: install.dst.clone(),
+            });
+        }
+        let warnings = replace::move_all_into_place(&moves, overwrite)?;
+        self.warnings.extend(warnings);
+        self.saved = true;
+
+        if let Some(handler) = &self.handler {
+            let bundle_id = (handler.target == Target::MacOs).then_some(handler.id.as_str());
+            let res = registry::record(
+                &self.app_name,
+                handler.target,
+                &self.final_bundle_path,
+                bundle_id,
+                &self.command,
+            );
+            if let Err(e) = res {
+                self.warnings.push(format!("Couldn't record the shim: {e}"));
+            }
+        }
+
+        for post_save in &self.post_save {
+            if let Err(e) = post_save.run() {
+                self.warnings.push(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Whether save() would leave everything as it is, apart from the build number.
+    pub fn is_unchanged(&self) -> bool {
+        assert!(!self.saved);
+        self.installs
+            .iter()
+            .all(|x| same_contents(&self.output.root().join(&x.staged), &x.dst))
+    }
+
+    // Whether save() keeps a replaced bundle as Name.app.bak (or <name>.bak on Linux).
+    pub fn keep_backup(&mut self, keep: bool) {
+        self.keep_backup = keep;
+    }
+
+    // Makes the saved shim the default handler for its document types. Reversible with
+    // defaults::restore().
+    pub fn make_default(&mut self) -> Result<(), String> {
+        assert!(self.saved);
+        let handler = self.handler.as_ref().expect("Generator without a handler");
+        let warnings = defaults::make_default(handler)?;
+        self.warnings.extend(warnings);
+        Ok(())
+    }
+}
+
+impl Generator<MemTree> {
+    // What save() would do: each destination, followed by what would be written there.
+    pub fn preview(&self) -> String {
+        self.installs
+            .iter()
+            .map(|x| self.output.render(&x.staged, &x.dst))
+            .collect()
+    }
+}
+
+impl<T: OutputTarget> Generator<T> {
+    // Generates into any output target, e.g. a MemTree to preview the result. Only a
+    // Generator<TempDirOutput> can be saved.
+    #[allow(clippy::too_many_arguments)]
+    pub fn gen_into(
+        output: T,
+        config: &Config,
+        doc_type: &DocTypes,
+        shim_bin: &Path,
+        bundle_id: &BundleIdOptions,
+        icon: &ShimIcon,
+        app_path: PathBuf,
+        target: Target,
+        type_decls: &[TypeDecl],
+        extra_info_plist: Option<&Dictionary>,
+    ) -> Result<Generator<T>, String> {
+        let config = &Config {
+            icon: icon.recorded(),
+            ..config.clone()
+        };
+        let mut gen = Generator {
+            output,
+            installs: vec![],
+            post_save: vec![],
+            handler: None,
+            final_bundle_path: PathBuf::new(),
+            app_name: String::new(),
+            command: config.command.clone(),
+            warnings: vec![],
+            keep_backup: false,
+            saved: false,
+        };
+
+        match target {
+            Target::MacOs => gen.gen_macos(
+                config,
+                doc_type,
+                shim_bin,
+                bundle_id,
+                icon,
+                app_path,
+                type_decls,
+                extra_info_plist,
+            )?,
+            Target::Linux => {
+                if extra_info_plist.is_some() {
+                    gen.warnings
+                        .push("Extra Info.plist keys are ignored for Linux shims".to_owned());
+                }
+                gen.gen_linux(config, doc_type, shim_bin, icon, app_path, type_decls)?
+            }
+        }
+
+        Ok(gen)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn gen_macos(
+        &mut self,
+        config: &Config,
+        doc_type: &DocTypes,
+        shim_bin: &Path,
+        bundle_id: &BundleIdOptions,
+        icon: &ShimIcon,
+        app_path: PathBuf,
+        type_decls: &[TypeDecl],
+        extra_info_plist: Option<&Dictionary>,
+    ) -> Result<(), String> {
         let (app_name, bundle_name, final_bundle_path) = get_names(app_path)?;
 
-        let tmp_dir = BundleTmpDir::new(&bundle_name)?;
+        let tmp_dir = BundleTmpDir::new(&mut self.output, &bundle_name)?;
 
-        let default_bundle_id = generate_bundle_id(&app_name.to_string_lossy());
+        // Regenerating over an existing shim keeps its identity, with a new build number.
+        let existing = info_plist::read_bundle(&final_bundle_path);
+        let existing_string = |key| {
+            existing
+                .as_ref()
+                .and_then(|x| x.get(key))
+                .and_then(|x| x.as_string())
+                .map(|x| x.to_owned())
+        };
+        let existing_id = existing_string("CFBundleIdentifier");
+        let bundle_id = match &existing_id {
+            Some(id) if bundle_id == &BundleIdOptions::default() => id.clone(),
+            _ => bundle_id.derive(&app_name.to_string_lossy())?,
+        };
+        if let Some(old) = existing_id.filter(|x| *x != bundle_id) {
+            self.warnings.push(format!(
+                "Changing the bundle identifier from '{old}' to '{bundle_id}', macOS will treat \
+                it as a different app"
+            ));
+        }
+        let bundle_version = bump_bundle_version(existing_string("CFBundleVersion").as_deref());
+        let exported_types = type_decl::unknown_ext_decls(doc_type, macos_knows_ext);
 
-        write_info_plist(
-            tmp_dir.contents(),
-            &app_name.to_string_lossy(),
+        let mut info_plist = InfoPlist {
+            app_name: &app_name.to_string_lossy(),
             doc_type,
-            bundle_id.unwrap_or(&default_bundle_id),
+            bundle_id: &bundle_id,
+            bundle_version: &bundle_version,
+            exported_types: &exported_types,
+            imported_types: type_decls,
+            terminal: config.terminal.name(),
+        }
+        .build();
+        if let Some(extra) = extra_info_plist {
+            info_plist::merge(&mut info_plist, extra);
+        }
+        // It may have been replaced.
+        let bundle_id = info_plist
+            .get("CFBundleIdentifier")
+            .and_then(|x| x.as_string())
+            .map(|x| x.to_owned())
+            .unwrap_or(bundle_id);
+        if extra_info_plist.is_some_and(|x| x.contains_key("CFBundleIdentifier")) {
+            validate_bundle_id(&bundle_id)?;
+        }
+
+        write_info_plist(&mut self.output, tmp_dir.contents(), &info_plist)?;
+        write_shim_bin(&mut self.output, tmp_dir.mac_os(), &app_name, shim_bin)?;
+        config.write(&mut self.output, tmp_dir.resources())?;
+        write_icon(
+            &mut self.output,
+            &mut self.warnings,
+            icon,
+            tmp_dir.resources(),
+            "AppIcon.icns",
         )?;
-        write_shim_bin(tmp_dir.mac_os(), &app_name, shim_bin)?;
-        config
-            .write(tmp_dir.resources())
-            .map_err(|e| e.to_string())?;
-        write_icon(icon_path, tmp_dir.resources())?;
-
-        Ok(Generator {
-            tmp_dir,
-            final_bundle_path,
-            saved: false,
-        })
+        for decl in type_decls {
+            if let Some(icon) = &decl.icon {
+                let file_name = decl.icon_file_name();
+                write_icon(
+                    &mut self.output,
+                    &mut self.warnings,
+                    &ShimIcon::from_path(Some(icon)),
+                    tmp_dir.resources(),
+                    &file_name,
+                )?;
+            }
+        }
+
+        for (i, group) in doc_type.groups().iter().enumerate() {
+            if !group.mime_types.is_empty() {
+                let name = group.name.as_deref().unwrap_or("unnamed");
+                self.warnings.push(format!(
+                    "MIME types are only used for Linux, ignoring those of '{name}'"
+                ));
+            }
+            if let Some(icon) = &group.icon {
+                let file_name = DocTypes::icon_file_name(i);
+                write_icon(
+                    &mut self.output,
+                    &mut self.warnings,
+                    &ShimIcon::from_path(Some(icon)),
+                    tmp_dir.resources(),
+                    &file_name,
+                )?;
+            }
+        }
+
+        // Only what the shim opens, it shouldn't become the default for what it just declares.
+        let opened = doc_type.groups().iter().filter(|x| x.opens());
+        let mut content_types: Vec<_> = opened.clone().flat_map(|x| x.utis.clone()).collect();
+        let extensions = opened
+            .flat_map(|x| x.exts.clone())
+            .filter(|x| x != "*")
+            .collect();
+        content_types.extend(type_decls.iter().map(|x| x.identifier.clone()));
+        self.handler = Some(Handler {
+            target: Target::MacOs,
+            id: bundle_id,
+            path: final_bundle_path.clone(),
+            content_types,
+            extensions,
+        });
+
+        self.installs.push(Install::new(
+            tmp_dir.app_root().to_owned(),
+            final_bundle_path.clone(),
+            false,
+        ));
+        self.final_bundle_path = final_bundle_path;
+        self.app_name = app_name.to_string_lossy().into_owned();
+        Ok(())
     }
 
-    // Safe to call again after an error, but not after a success.
-    pub fn save(&mut self, overwrite: bool) -> Result<(), SaveErr> {
-        assert!(!self.saved);
-        let res = save_bundle(self.tmp_dir.app_root(), &self.final_bundle_path, overwrite);
-        if res.is_ok() {
-            self.saved = true;
+    // Only the file name of app_path is used, everything goes in the standard XDG directories.
+    fn gen_linux(
+        &mut self,
+        config: &Config,
+        doc_type: &DocTypes,
+        shim_bin: &Path,
+        icon: &ShimIcon,
+        app_path: PathBuf,
+        type_decls: &[TypeDecl],
+    ) -> Result<(), String> {
+        let app_name = get_app_name(&app_path, "desktop")?;
+        let data_dir = desktop::data_dir(&app_name)?;
+        let desktop_file_name = desktop::desktop_file_name(&app_name);
+        let desktop_file = desktop::applications_dir()?.join(&desktop_file_name);
+
+        let tmp_data_dir = PathBuf::from(&app_name);
+        self.output.create_dir(&tmp_data_dir)?;
+
+        write_shim_bin(&mut self.output, &tmp_data_dir, &app_name, shim_bin)?;
+        config.write(&mut self.output, &tmp_data_dir)?;
+        let icon_installs =
+            write_hicolor_icons(&mut self.output, &mut self.warnings, icon, &app_name)?;
+
+        let globs = MimeGlobs::load();
+        let mut decls =
+            type_decl::unknown_ext_decls(doc_type, |ext| globs.mime_type(ext).is_some());
+        let (mut mime_types, warnings) = desktop::mime_types(doc_type, &globs, &decls);
+        self.warnings.extend(warnings);
+
+        for group in doc_type.groups() {
+            if group.icon.is_some() {
+                let name = group.name.as_deref().unwrap_or("unnamed");
+                self.warnings.push(format!(
+                    "Document icons are only used for macOS, ignoring the icon for '{name}'"
+                ));
+            }
+        }
+
+        for decl in type_decls {
+            if decl.icon.is_some() {
+                self.warnings.push(format!(
+                    "Icons for declared types are only used for macOS, ignoring the icon for '{}'",
+                    decl.identifier
+                ));
+            }
+            if !mime_types.contains(&decl.mime_type) {
+                mime_types.push(decl.mime_type.clone());
+            }
+            decls.push(decl.clone());
+        }
+
+        if !decls.is_empty() {
+            let mime_dir = desktop::mime_dir()?;
+            let package_name = desktop::mime_package_file_name(&app_name);
+            let tmp_package = PathBuf::from(&package_name);
+            self.output.write_file(
+                &tmp_package,
+                desktop::render_mime_package(&decls).as_bytes(),
+            )?;
+
+            let package = mime_dir.join("packages").join(package_name);
+            self.installs.push(Install::new(tmp_package, package, true));
+            self.post_save.push(PostSave::UpdateMimeDatabase(mime_dir));
+        }
+
+        let entry = DesktopEntry {
+            name: &app_name.to_string_lossy(),
+            comment: &desktop::entry_comment(&config.command),
+            exec: &data_dir.join(&app_name),
+            icon: &desktop::icon_name(&app_name),
+            mime_types: &mime_types,
+        }
+        .render()?;
+
+        let tmp_desktop_file = PathBuf::from(&desktop_file_name);
+        self.output
+            .write_file(&tmp_desktop_file, entry.as_bytes())?;
+
+        self.installs
+            .push(Install::new(tmp_data_dir, data_dir.clone(), true));
+        self.installs.extend(icon_installs);
+        self.installs
+            .push(Install::new(tmp_desktop_file, desktop_file.clone(), true));
+        if let Some(applications) = desktop_file.parent() {
+            self.post_save
+                .push(PostSave::UpdateDesktopDatabase(applications.to_owned()));
         }
-        res
+
+        self.handler = Some(Handler {
+            target: Target::Linux,
+            id: desktop_file_name.to_string_lossy().into_owned(),
+            path: desktop_file.clone(),
+            content_types: mime_types,
+            extensions: vec![],
+        });
+        self.final_bundle_path = desktop_file;
+        self.app_name = app_name.to_string_lossy().into_owned();
+        Ok(())
     }
 
     pub fn final_bundle_path(&self) -> &Path {
         &self.final_bundle_path
     }
+
+    // Non-fatal problems encountered while generating or saving.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub fn output(&self) -> &T {
+        &self.output
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Checking an existing shim for what commonly breaks one, e.g. after it's been copied around or
+// edited by hand.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    // Likely fine, but worth a look.
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Pass => write!(f, "pass"),
+            Status::Warn => write!(f, "warn"),
+            Status::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Check {
+        Check {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    // Pass with ok's detail, or fail with the error.
+    fn from_result(name: &'static str, res: Result<String, String>) -> Check {
+        match res {
+            Ok(x) => Check::new(name, Status::Pass, x),
+            Err(e) => Check::new(name, Status::Fail, e),
+        }
+    }
+}
+
+// The program a command runs first, unquoted, skipping leading VAR=value assignments.
+fn command_program(command: &str) -> Option<String> {
+    let mut rest = command.trim_start();
+    loop {
+        let mut word = String::new();
+        let mut chars = rest.char_indices().peekable();
+        let mut end = rest.len();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                _ if c.is_whitespace() => {
+                    end = i;
+                    break;
+                }
+                '$' if chars.peek().map(|x| x.1) == Some('\'') => (),
+                '\'' | '"' => {
+                    for (_, x) in chars.by_ref() {
+                        if x == c {
+                            break;
+                        }
+                        word.push(x);
+                    }
+                }
+                '\\' => word.extend(chars.next().map(|x| x.1)),
+                _ => word.push(c),
+            }
+        }
+
+        let is_assignment = word
+            .split_once('=')
+            .is_some_and(|(name, _)| !name.is_empty() && !name.contains('/'));
+        if !is_assignment {
+            return Some(word).filter(|x| !x.is_empty());
+        }
+        rest = rest[end..].trim_start();
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|x| x.is_file() && x.permissions().mode() & 0o111 != 0)
+}
+
+// Whether the command's program can be found, here at least: the terminal's shell may have a
+// different PATH, and it may be a shell function or alias, so it's only a warning.
+fn check_command(command: &str) -> Check {
+    const NAME: &str = "Command";
+    let Some(program) = command_program(command) else {
+        return Check::new(
+            NAME,
+            Status::Fail,
+            format!("Can't tell what '{command}' runs"),
+        );
+    };
+
+    if program.contains('/') {
+        return if is_executable(Path::new(&program)) {
+            Check::new(NAME, Status::Pass, format!("'{program}' is executable"))
+        } else {
+            Check::new(
+                NAME,
+                Status::Warn,
+                format!("'{program}' doesn't exist or isn't executable"),
+            )
+        };
+    }
+
+    let path = env::var_os("PATH").unwrap_or_default();
+    match env::split_paths(&path)
+        .map(|x| x.join(&program))
+        .find(|x| is_executable(x))
+    {
+        Some(found) => Check::new(
+            NAME,
+            Status::Pass,
+            format!("'{program}' is {}", found.display()),
+        ),
+        None => Check::new(
+            NAME,
+            Status::Warn,
+            format!("'{program}' isn't on PATH here, which is fine if it's a shell function or alias, or on the terminal's PATH"),
+        ),
+    }
+}
+
+fn check_binary(binary: &Path) -> Vec<Check> {
+    let Ok(contents) = fs::read(binary) else {
+        return vec![Check::new(
+            "Executable bit",
+            Status::Fail,
+            format!("Can't read '{}'", binary.display()),
+        )];
+    };
+
+    let executable = if is_executable(binary) {
+        Check::new(
+            "Executable bit",
+            Status::Pass,
+            format!("'{}' is executable", binary.display()),
+        )
+    } else {
+        Check::new(
+            "Executable bit",
+            Status::Fail,
+            format!("'{}' isn't executable", binary.display()),
+        )
+    };
+
+    let current = env!("CARGO_PKG_VERSION");
+    let version = match embedded_version(&contents) {
+        Some(x) if x == current => Check::new("Shim version", Status::Pass, x),
+        Some(x) => Check::new(
+            "Shim version",
+            Status::Warn,
+            format!("{x}, this is {current}, run upgrade to update it"),
+        ),
+        None => Check::new(
+            "Shim version",
+            Status::Warn,
+            "Too old to tell, or not echidna-shim, run upgrade to update it",
+        ),
+    };
+
+    vec![executable, version]
+}
+
+fn check_config(path: &Path) -> Check {
+    const NAME: &str = "config.json";
+    let contents = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) => {
+            return Check::new(
+                NAME,
+                Status::Fail,
+                format!("Error reading '{}': {e}", path.display()),
+            )
+        }
+    };
+    match Config::parse(&contents) {
+        Err(e) => Check::new(NAME, Status::Fail, e),
+        Ok(_) if serde_json::from_str::<Config>(&contents).is_err() => Check::new(
+            NAME,
+            Status::Warn,
+            "Written by an older version, run upgrade to update it",
+        ),
+        Ok(config) => Check::new(NAME, Status::Pass, format!("Runs '{}'", config.command)),
+    }
+}
+
+fn verify_macos(path: &Path) -> Vec<Check> {
+    let info_plist_path = path.join("Contents/Info.plist");
+    let info_plist = match plist::Value::from_file(&info_plist_path) {
+        Ok(plist::Value::Dictionary(x)) => x,
+        Ok(_) => return vec![Check::new("Info.plist", Status::Fail, "Isn't a dictionary")],
+        Err(e) => return vec![Check::new("Info.plist", Status::Fail, e.to_string())],
+    };
+    let string = |key: &str| {
+        info_plist
+            .get(key)
+            .and_then(|x| x.as_string())
+            .map(|x| x.to_owned())
+    };
+    let resources = path.join("Contents/Resources");
+    let mut checks = vec![Check::new("Info.plist", Status::Pass, "Parses")];
+
+    match string("CFBundleExecutable") {
+        None => checks.push(Check::new(
+            "CFBundleExecutable",
+            Status::Fail,
+            "Missing from the Info.plist",
+        )),
+        Some(executable) => {
+            let binary = path.join("Contents/MacOS").join(&executable);
+            if binary.is_file() {
+                checks.push(Check::new(
+                    "CFBundleExecutable",
+                    Status::Pass,
+                    format!("'{executable}' is in Contents/MacOS"),
+                ));
+                checks.extend(check_binary(&binary));
+            } else {
+                checks.push(Check::new(
+                    "CFBundleExecutable",
+                    Status::Fail,
+                    format!("'{executable}' isn't in Contents/MacOS"),
+                ));
+            }
+        }
+    }
+
+    let icon_file = string("CFBundleIconFile").unwrap_or("AppIcon.icns".to_owned());
+    let icon = resources.join(&icon_file);
+    checks.push(Check::from_result(
+        "Icon",
+        fs::read(&icon)
+            .map_err(|e| format!("Error reading '{}': {e}", icon.display()))
+            .and_then(|x| {
+                let family = icns::IconFamily::read(x.as_slice())
+                    .map_err(|e| format!("'{icon_file}' isn't a valid icns: {e}"))?;
+                if family.elements.is_empty() {
+                    bailf!("'{icon_file}' has no images");
+                }
+                Ok(format!(
+                    "'{icon_file}' has {} images",
+                    family.elements.len()
+                ))
+            }),
+    ));
+
+    let config_path = resources.join("config.json");
+    checks.push(check_config(&config_path));
+    if let Ok(config) = Config::load_from(&config_path) {
+        checks.push(check_command(&config.command));
+    }
+
+    // Every UTI the shim opens, declares, or declares its types as conforming to.
+    let dicts = |key: &str| -> Vec<plist::Dictionary> {
+        info_plist
+            .get(key)
+            .and_then(|x| x.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|x| x.as_dictionary().cloned())
+            .collect()
+    };
+    let strings = |dict: &plist::Dictionary, key: &str| -> Vec<String> {
+        dict.get(key)
+            .and_then(|x| x.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|x| x.as_string().map(|x| x.to_owned()))
+            .collect()
+    };
+    let mut utis = vec![];
+    for doc_type in dicts("CFBundleDocumentTypes") {
+        utis.extend(strings(&doc_type, "LSItemContentTypes"));
+    }
+    for decl in dicts("UTImportedTypeDeclarations")
+        .iter()
+        .chain(&dicts("UTExportedTypeDeclarations"))
+    {
+        utis.extend(
+            decl.get("UTTypeIdentifier")
+                .and_then(|x| x.as_string())
+                .map(|x| x.to_owned()),
+        );
+        utis.extend(strings(decl, "UTTypeConformsTo"));
+    }
+    let invalid: Vec<String> = utis
+        .iter()
+        .filter_map(|x| type_decl::validate_uti(x).err())
+        .collect();
+    checks.push(if invalid.is_empty() {
+        Check::new("UTIs", Status::Pass, format!("{} well-formed", utis.len()))
+    } else {
+        Check::new("UTIs", Status::Fail, invalid.join("; "))
+    });
+
+    checks.push(Check::from_result(
+        "Bundle identifier",
+        match string("CFBundleIdentifier") {
+            Some(id) => validate_bundle_id(&id).map(|_| id),
+            None => Err("Missing from the Info.plist".to_owned()),
+        },
+    ));
+
+    checks
+}
+
+fn verify_linux(path: &Path) -> Result<Vec<Check>, String> {
+    let name = shim::linux_name(path)?;
+    let name = OsStr::new(&name);
+    let desktop_file = desktop::applications_dir()?.join(desktop::desktop_file_name(name));
+    let data_dir = desktop::data_dir(name)?;
+
+    let entry = match fs::read_to_string(&desktop_file) {
+        Ok(x) => desktop::parse_entry(&x),
+        Err(e) => {
+            return Ok(vec![Check::new(
+                "Desktop entry",
+                Status::Fail,
+                format!("Error reading '{}': {e}", desktop_file.display()),
+            )])
+        }
+    };
+    let mut checks = vec![];
+    checks.push(match entry.get("Exec") {
+        Some(exec) => Check::new("Desktop entry", Status::Pass, format!("Runs {exec}")),
+        None => Check::new("Desktop entry", Status::Fail, "Has no Exec key"),
+    });
+
+    checks.extend(check_binary(&data_dir.join(name)));
+
+    let icon = desktop::icons_dir()?.join(desktop::hicolor_icon_path(name, 512));
+    checks.push(Check::from_result(
+        "Icon",
+        fs::read(&icon)
+            .map_err(|e| format!("Error reading '{}': {e}", icon.display()))
+            .and_then(|x| {
+                image::load_from_memory(&x)
+                    .map(|x| format!("{}x{} PNG", x.width(), x.height()))
+                    .map_err(|e| format!("'{}' isn't a valid image: {e}", icon.display()))
+            }),
+    ));
+
+    let config_path = data_dir.join("config.json");
+    checks.push(check_config(&config_path));
+    if let Ok(config) = Config::load_from(&config_path) {
+        checks.push(check_command(&config.command));
+    }
+
+    let mime_types: Vec<&str> = entry
+        .get("MimeType")
+        .map(|x| x.split(';').filter(|x| !x.is_empty()).collect())
+        .unwrap_or_default();
+    let invalid: Vec<&str> = mime_types
+        .iter()
+        .filter(|x| {
+            !matches!(x.split_once('/'), Some((kind, sub)) if !kind.is_empty() && !sub.is_empty() && !sub.contains('/'))
+        })
+        .copied()
+        .collect();
+    checks.push(if invalid.is_empty() {
+        Check::new(
+            "MIME types",
+            Status::Pass,
+            format!("{} well-formed", mime_types.len()),
+        )
+    } else {
+        Check::new(
+            "MIME types",
+            Status::Fail,
+            format!("Malformed: {}", invalid.join(", ")),
+        )
+    });
+
+    Ok(checks)
+}
+
+// Checks the shim at path, a .app bundle, or for Linux, its desktop entry or data directory. Only
+// an error if it can't be checked at all.
+pub fn verify(path: &Path) -> Result<Vec<Check>, String> {
+    if path.join("Contents").is_dir() {
+        Ok(verify_macos(path))
+    } else if path.extension() == Some(OsStr::new("desktop")) || path.is_dir() {
+        verify_linux(path)
+    } else {
+        bailf!("'{}' isn't a shim", path.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GroupBy, TerminalApp};
+    use crate::xdg;
+
+    fn snapshot_file(name: &str) -> PathBuf {
+        Path::new(file!()).with_file_name("snapshots").join(name)
+    }
+
+    // ext must be known on macOS, so it's the same on any host, and unknown on Linux, so it's
+    // declared.
+    fn gen_snapshot(target: Target, app_path: &str, terminal: &str, ext: &str) -> MemTree {
+        let config = Config {
+            command: "vim -p".to_owned(),
+            group_open_by: GroupBy::All,
+            terminal: TerminalApp::Supported(terminal.to_owned()),
+            dry_run: None,
+            icon: None,
+        };
+        let doc_types = DocTypes(vec![DocTypeGroup {
+            utis: vec!["public.plain-text".to_owned()],
+            exts: vec![ext.to_owned()],
+            ..Default::default()
+        }]);
+        let type_decls = [TypeDecl {
+            identifier: "com.example.tfvars".to_owned(),
+            mime_type: "application/x-tfvars".to_owned(),
+            description: "Terraform variables".to_owned(),
+            conforms_to: vec!["public.text".to_owned()],
+            extensions: vec!["tfvars".to_owned()],
+            icon: None,
+        }];
+        let bundle_id = BundleIdOptions {
+            id: Some("com.example.vim".to_owned()),
+            ..Default::default()
+        };
+
+        let gen = Generator::gen_into(
+            MemTree::new(),
+            &config,
+            &doc_types,
+            // Only its path is recorded.
+            Path::new("Cargo.toml"),
+            &bundle_id,
+            &ShimIcon::default(),
+            PathBuf::from(app_path),
+            target,
+            &type_decls,
+            None,
+        )
+        .unwrap();
+        gen.output
+    }
+
+    #[test]
+    fn validate_bundle_id_follows_apples_rules() {
+        for id in [
+            "com.example.vim",
+            "a",
+            "local.host-1.Vim-2",
+            &"a".repeat(255),
+        ] {
+            assert_eq!(validate_bundle_id(id), Ok(()), "{id}");
+        }
+        for id in [
+            "",
+            &"a".repeat(256),
+            "com.example.vim_2",
+            "com.exämple.vim",
+            "com example",
+            ".com.example",
+            "com.example.",
+            "com..example",
+        ] {
+            assert!(validate_bundle_id(id).is_err(), "{id}");
+        }
+    }
+
+    #[test]
+    fn derive_bundle_id() {
+        let derive = |prefix: Option<&str>, seed: Option<&str>, name| {
+            BundleIdOptions {
+                id: None,
+                prefix: prefix.map(|x| x.to_owned()),
+                seed: seed.map(|x| x.to_owned()),
+            }
+            .derive(name)
+        };
+        let prefix = Some("com.example");
+
+        assert_eq!(
+            derive(prefix, None, "Vim"),
+            Ok("com.example.Vim".to_owned())
+        );
+        assert_eq!(
+            derive(prefix, Some("nvim"), "Vim"),
+            Ok("com.example.nvim".to_owned())
+        );
+        assert_eq!(
+            derive(Some(" Jane’s..Mac_Book "), None, "Vim (2)"),
+            Ok("Jane-s.Mac-Book.Vim-2".to_owned())
+        );
+        assert_eq!(derive(prefix, None, "☃"), Ok("com.example.shim".to_owned()));
+        assert!(derive(Some("..."), None, "Vim").is_err());
+
+        let id = derive(None, None, "Vim").unwrap();
+        assert!(id.starts_with("local.") && id.ends_with(".Vim"), "{id}");
+        assert_eq!(validate_bundle_id(&id), Ok(()));
+    }
+
+    #[test]
+    fn derive_bundle_id_uses_given_id() {
+        let options = |id: &str| BundleIdOptions {
+            id: Some(id.to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            options("org.vim.Vim").derive("Other"),
+            Ok("org.vim.Vim".to_owned())
+        );
+        // Not normalized.
+        assert!(options("org.vim.Vim_").derive("Other").is_err());
+    }
+
+    #[test]
+    fn bump_bundle_version_increments_last_number() {
+        let bump = |x| bump_bundle_version(Some(x));
+        assert_eq!(bump_bundle_version(None), "1");
+        assert_eq!(bump("1"), "2");
+        assert_eq!(bump("41"), "42");
+        assert_eq!(bump("1.2.9"), "1.2.10");
+        assert_eq!(bump(" 7 "), "8");
+        assert_eq!(bump("1.0b3"), "1.1");
+        assert_eq!(bump("2.1-rc"), "2.2");
+        assert_eq!(bump("beta"), "beta.1");
+        assert_eq!(bump("1.beta"), "1.beta.1");
+        assert_eq!(bump(""), "1");
+    }
+
+    #[test]
+    fn command_program_unquotes_first_word() {
+        assert_eq!(command_program("vim -p"), Some("vim".to_owned()));
+        assert_eq!(
+            command_program("  /usr/bin/vim"),
+            Some("/usr/bin/vim".to_owned())
+        );
+        assert_eq!(
+            command_program("'/Applications/My Editor/ed' -f"),
+            Some("/Applications/My Editor/ed".to_owned())
+        );
+        assert_eq!(command_program("\"my ed\" x"), Some("my ed".to_owned()));
+        assert_eq!(command_program("my\\ ed x"), Some("my ed".to_owned()));
+        assert_eq!(command_program("$'ed' x"), Some("ed".to_owned()));
+        assert_eq!(
+            command_program("TERM=xterm-256color LANG=C nvim"),
+            Some("nvim".to_owned())
+        );
+        assert_eq!(command_program("./a=b x"), Some("./a=b".to_owned()));
+        assert_eq!(command_program("FOO=bar"), None);
+        assert_eq!(command_program("   "), None);
+    }
+
+    #[test]
+    fn macos_snapshot() {
+        gen_snapshot(Target::MacOs, "/nonexistent/Vim.app", "Terminal.app", "md")
+            .check_snapshot(&snapshot_file("macos.snap"), &[])
+            .unwrap();
+    }
+
+    #[test]
+    fn linux_snapshot() {
+        // The desktop entry runs the shim from its data directory.
+        let data_home = xdg::data_home().unwrap();
+        gen_snapshot(Target::Linux, "Vim", "x-terminal-emulator", "echidnatest")
+            .check_snapshot(
+                &snapshot_file("linux.snap"),
+                &[(&data_home.to_string_lossy(), "$XDG_DATA_HOME")],
+            )
+            .unwrap();
+    }
 }
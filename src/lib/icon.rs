@@ -0,0 +1,458 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+use crate::icon_composer::ShimIcon;
+
+use icns::{IconFamily, IconType, PixelFormat};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageOutputFormat, RgbaImage};
+
+// Loading shim icons from whatever the user gives us, and rendering them at the sizes each target
+// needs.
+
+pub const SHIM_APP_ICON: &[u8] = include_bytes!("../../app_files/ShimAppIcon.icns");
+
+// Sizes installed in the freedesktop hicolor icon theme.
+pub const HICOLOR_SIZES: &[u32] = &[16, 32, 48, 64, 128, 256, 512];
+
+const ICNS_MAGIC: &[u8] = b"icns";
+
+// Decodes the largest icon in an icon family.
+fn decode_icns(bytes: &[u8]) -> Result<DynamicImage, String> {
+    let family = IconFamily::read(Cursor::new(bytes)).map_err(|e| e.to_string())?;
+
+    let Some(icon_type) = family
+        .available_icons()
+        .into_iter()
+        .max_by_key(|x| x.pixel_width())
+    else {
+        return Err("No icons in icon family".to_owned());
+    };
+
+    let icon = family
+        .get_icon_with_type(icon_type)
+        .map_err(|e| e.to_string())?
+        .convert_to(PixelFormat::RGBA);
+
+    let rgba = RgbaImage::from_raw(icon.width(), icon.height(), icon.into_data().into_vec())
+        .ok_or("Icon's data doesn't match its dimensions")?;
+    Ok(DynamicImage::ImageRgba8(rgba))
+}
+
+// An icon as loaded from the user's file, either a bitmap or an SVG, which is rendered at each
+// size rather than scaled.
+pub enum IconSource {
+    Raster(DynamicImage),
+    Svg(resvg::Tree),
+}
+
+// Bitmaps rendered from SVGs, for things that need one, are this big.
+pub const SVG_RENDER_SIZE: u32 = 1024;
+
+fn is_svg(icon_path: &Path, bytes: &[u8]) -> bool {
+    let ext = icon_path.extension().and_then(|x| x.to_str());
+    if ext.is_some_and(|x| x.eq_ignore_ascii_case("svg") || x.eq_ignore_ascii_case("svgz")) {
+        return true;
+    }
+
+    let text = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]);
+    let text = text.trim_start();
+    text.starts_with("<svg") || (text.starts_with("<?xml") && text.contains("<svg"))
+}
+
+fn parse_svg(bytes: &[u8]) -> Result<resvg::Tree, String> {
+    use resvg::usvg::TreeParsing;
+
+    // Handles gzipped .svgz too.
+    let tree = resvg::usvg::Tree::from_data(bytes, &resvg::usvg::Options::default())
+        .map_err(|e| e.to_string())?;
+    Ok(resvg::Tree::from_usvg(&tree))
+}
+
+// Loads the icon at icon_path, or the default shim icon. Anything the image crate can read is
+// accepted, plus .icns and .svg.
+pub fn load_source(icon_path: Option<&Path>) -> Result<IconSource, String> {
+    let Some(icon_path) = icon_path else {
+        return decode_icns(SHIM_APP_ICON)
+            .map(IconSource::Raster)
+            .map_err(|e| format!("Error decoding the default shim icon: {e}"));
+    };
+
+    let bytes = fs::read(icon_path)
+        .map_err(|e| format!("Error reading icon '{}': {e}", icon_path.display()))?;
+
+    // Going by contents rather than extension, .icns files are sometimes just PNGs.
+    let source = if bytes.starts_with(ICNS_MAGIC) {
+        decode_icns(&bytes).map(IconSource::Raster)
+    } else if is_svg(icon_path, &bytes) {
+        parse_svg(&bytes).map(IconSource::Svg)
+    } else {
+        image::io::Reader::new(Cursor::new(&bytes))
+            .with_guessed_format()
+            .map_err(|e| e.to_string())
+            .and_then(|x| x.decode().map_err(|e| e.to_string()))
+            .map(IconSource::Raster)
+    };
+
+    source.map_err(|e| format!("Error loading icon from '{}': {e}", icon_path.display()))
+}
+
+// Decodes the icon at icon_path, or the default shim icon, as a bitmap. SVGs are rendered at
+// SVG_RENDER_SIZE.
+pub fn load_image(icon_path: Option<&Path>) -> Result<DynamicImage, String> {
+    Ok(match load_source(icon_path)? {
+        IconSource::Raster(image) => image,
+        svg => svg.render(SVG_RENDER_SIZE),
+    })
+}
+
+impl IconSource {
+    // Renders the icon on a transparent square canvas of side pixels, centered and keeping its
+    // aspect ratio.
+    pub fn render(&self, side: u32) -> DynamicImage {
+        match self {
+            IconSource::Raster(image) => {
+                square(image).resize_exact(side, side, FilterType::Lanczos3)
+            }
+            IconSource::Svg(tree) => {
+                let mut pixmap =
+                    resvg::tiny_skia::Pixmap::new(side, side).expect("Icon sizes are never 0");
+                let (width, height) = (tree.size.width(), tree.size.height());
+                let scale = side as f32 / width.max(height);
+                let transform = resvg::tiny_skia::Transform::from_translate(
+                    (side as f32 - width * scale) / 2.0,
+                    (side as f32 - height * scale) / 2.0,
+                )
+                .pre_scale(scale, scale);
+                tree.render(transform, &mut pixmap.as_mut());
+
+                // tiny-skia's pixels are premultiplied.
+                let rgba: Vec<u8> = pixmap
+                    .pixels()
+                    .iter()
+                    .flat_map(|x| {
+                        let x = x.demultiply();
+                        [x.red(), x.green(), x.blue(), x.alpha()]
+                    })
+                    .collect();
+                let image = RgbaImage::from_raw(side, side, rgba)
+                    .expect("Pixmap's data matches its dimensions");
+                DynamicImage::ImageRgba8(image)
+            }
+        }
+    }
+
+    // A warning if a bitmap is too small to look sharp at the largest sizes. SVGs are always
+    // sharp.
+    pub fn size_warning(&self, icon_path: &Path) -> Option<String> {
+        match self {
+            IconSource::Raster(image) => size_warning(image, icon_path),
+            IconSource::Svg(_) => None,
+        }
+    }
+}
+
+// Centers the image on a transparent square canvas, so resizing doesn't distort it.
+pub fn square(image: &DynamicImage) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    if width == height {
+        return image.clone();
+    }
+
+    let side = width.max(height);
+    let mut canvas = RgbaImage::new(side, side);
+    image::imageops::overlay(
+        &mut canvas,
+        &image.to_rgba8(),
+        ((side - width) / 2).into(),
+        ((side - height) / 2).into(),
+    );
+    DynamicImage::ImageRgba8(canvas)
+}
+
+pub fn encode_png(image: &DynamicImage) -> Result<Vec<u8>, String> {
+    let mut buffer = Cursor::new(vec![]);
+    image
+        .write_to(&mut buffer, ImageOutputFormat::Png)
+        .map_err(|e| format!("Error encoding icon as PNG: {e}"))?;
+    Ok(buffer.into_inner())
+}
+
+// A square PNG for each of HICOLOR_SIZES, as (size, PNG data).
+pub fn hicolor_pngs(source: &IconSource) -> Result<Vec<(u32, Vec<u8>)>, String> {
+    HICOLOR_SIZES
+        .iter()
+        .map(|&size| Ok((size, encode_png(&source.render(size))?)))
+        .collect()
+}
+
+// The default icon's PNG for size, as hicolor_pngs() renders it.
+pub fn default_hicolor_png(size: u32) -> Result<Vec<u8>, String> {
+    encode_png(&load_source(None)?.render(size))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Sources smaller than this get blurry in the larger icns sizes.
+pub const MIN_ICNS_SOURCE: u32 = 512;
+
+// Each icns entry, as (type, side in pixels), from 16x16 to 512x512@2x.
+const ICNS_TYPES: &[(IconType, u32)] = &[
+    (IconType::RGBA32_16x16, 16),
+    (IconType::RGBA32_16x16_2x, 32),
+    (IconType::RGBA32_32x32, 32),
+    (IconType::RGBA32_32x32_2x, 64),
+    (IconType::RGBA32_128x128, 128),
+    (IconType::RGBA32_128x128_2x, 256),
+    (IconType::RGBA32_256x256, 256),
+    (IconType::RGBA32_256x256_2x, 512),
+    (IconType::RGBA32_512x512, 512),
+    (IconType::RGBA32_512x512_2x, 1024),
+];
+
+// macOS app icons are drawn on a 1024 canvas with an 824 square body, the rest left transparent.
+const ICNS_BODY_RATIO: f32 = 824.0 / 1024.0;
+
+// Whether anything opaque touches the image's edge, i.e. it isn't already padded like a macOS
+// icon.
+fn touches_edge(image: &RgbaImage) -> bool {
+    let (width, height) = image.dimensions();
+    // A little slack, for antialiasing.
+    let margin = (width.min(height) / 64).max(1);
+    image.enumerate_pixels().any(|(x, y, pixel)| {
+        let edge = x < margin || y < margin || x >= width - margin || y >= height - margin;
+        edge && pixel[3] > 16
+    })
+}
+
+// Renders the icon at side pixels and, unless it's already padded, shrinks it to the body of a
+// macOS icon on a transparent canvas.
+fn render_padded(source: &IconSource, side: u32, pad: bool) -> DynamicImage {
+    if !pad {
+        return source.render(side);
+    }
+
+    let body = ((side as f32) * ICNS_BODY_RATIO).round().max(1.0) as u32;
+    let mut canvas = RgbaImage::new(side, side);
+    let offset = ((side - body) / 2).into();
+    image::imageops::overlay(&mut canvas, &source.render(body).to_rgba8(), offset, offset);
+    DynamicImage::ImageRgba8(canvas)
+}
+
+// An icon family with every size from 16x16 to 512x512@2x, rendered from source.
+pub fn encode_icns(source: &IconSource) -> Result<Vec<u8>, String> {
+    let pad = touches_edge(&source.render(SVG_RENDER_SIZE).to_rgba8());
+
+    let mut family = IconFamily::new();
+    for &(icon_type, size) in ICNS_TYPES {
+        let rendered = render_padded(source, size, pad);
+        let icon = icns::Image::from_data(
+            PixelFormat::RGBA,
+            size,
+            size,
+            rendered.to_rgba8().into_raw(),
+        )
+        .map_err(|e| format!("Error creating {size}x{size} icon: {e}"))?;
+        family
+            .add_icon_with_type(&icon, icon_type)
+            .map_err(|e| format!("Error adding {size}x{size} icon: {e}"))?;
+    }
+
+    let mut buffer = vec![];
+    family
+        .write(&mut buffer)
+        .map_err(|e| format!("Error encoding icon family: {e}"))?;
+    Ok(buffer)
+}
+
+// A warning if the image is too small to look sharp at the largest sizes.
+pub fn size_warning(image: &DynamicImage, icon_path: &Path) -> Option<String> {
+    let (width, height) = image.dimensions();
+    (width.max(height) < MIN_ICNS_SOURCE).then(|| {
+        format!(
+            "Icon '{}' is only {width}x{height}, it will look blurry at larger sizes (at least \
+            {MIN_ICNS_SOURCE}x{MIN_ICNS_SOURCE} is recommended)",
+            icon_path.display()
+        )
+    })
+}
+
+// The icon family for a bundle, and a warning if the image is too small. Icon families that
+// already have every size are used as they are, unless there's something to draw over them.
+pub fn icns_for(icon: &ShimIcon) -> Result<(Vec<u8>, Option<String>), String> {
+    match &icon.path {
+        None if !icon.is_composed() => return Ok((SHIM_APP_ICON.to_owned(), None)),
+        Some(icon_path) if !icon.is_composed() => {
+            let bytes = fs::read(icon_path)
+                .map_err(|e| format!("Error reading icon '{}': {e}", icon_path.display()))?;
+            if bytes.starts_with(ICNS_MAGIC) {
+                if let Ok(family) = IconFamily::read(Cursor::new(&bytes)) {
+                    if ICNS_TYPES
+                        .iter()
+                        .all(|(x, _)| family.has_icon_with_type(*x))
+                    {
+                        return Ok((bytes, None));
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+
+    let (source, warning) = icon.load()?;
+    Ok((encode_icns(&source)?, warning))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    // A side pixel square PNG in dir, opaque to the edges.
+    fn write_png(dir: &Path, side: u32) -> std::path::PathBuf {
+        let path = dir.join(format!("{side}.png"));
+        let image = RgbaImage::from_pixel(side, side, Rgba([0x3b, 0x82, 0xf0, 0xff]));
+        fs::write(&path, encode_png(&DynamicImage::ImageRgba8(image)).unwrap()).unwrap();
+        path
+    }
+
+    fn read_family(bytes: &[u8]) -> IconFamily {
+        IconFamily::read(Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn encode_icns_has_every_size() {
+        let dir = tempdir::TempDir::new("echidna-test").unwrap();
+        let source = load_source(Some(&write_png(dir.path(), 64))).unwrap();
+        let family = read_family(&encode_icns(&source).unwrap());
+
+        for &(icon_type, size) in ICNS_TYPES {
+            let icon = family.get_icon_with_type(icon_type).unwrap();
+            assert_eq!((icon.width(), icon.height()), (size, size), "{icon_type:?}");
+        }
+        assert_eq!(family.available_icons().len(), ICNS_TYPES.len());
+
+        // It reads back as the largest, padded like a macOS icon since the source wasn't.
+        let image = decode_icns(&encode_icns(&source).unwrap()).unwrap();
+        assert_eq!(image.dimensions(), (1024, 1024));
+        assert!(!touches_edge(&image.to_rgba8()));
+    }
+
+    #[test]
+    fn icns_for_uses_complete_families_as_they_are() {
+        let dir = tempdir::TempDir::new("echidna-test").unwrap();
+        let source = load_source(Some(&write_png(dir.path(), 64))).unwrap();
+        let path = dir.path().join("complete.icns");
+        let complete = encode_icns(&source).unwrap();
+        fs::write(&path, &complete).unwrap();
+        let (icns, warning) = icns_for(&ShimIcon::from_path(Some(&path))).unwrap();
+        assert_eq!(icns, complete);
+        assert_eq!(warning, None);
+
+        let (icns, warning) = icns_for(&ShimIcon::default()).unwrap();
+        assert_eq!(icns, SHIM_APP_ICON);
+        assert_eq!(warning, None);
+
+        // A small PNG gets every size, and a warning.
+        let png = write_png(dir.path(), 64);
+        let (icns, warning) = icns_for(&ShimIcon::from_path(Some(&png))).unwrap();
+        assert_eq!(read_family(&icns).available_icons().len(), ICNS_TYPES.len());
+        assert!(warning.unwrap().contains("64x64"));
+    }
+
+    #[test]
+    fn load_source_reads_icns_and_default() {
+        let dir = tempdir::TempDir::new("echidna-test").unwrap();
+        let path = dir.path().join("default.icns");
+        fs::write(&path, SHIM_APP_ICON).unwrap();
+        let IconSource::Raster(image) = load_source(Some(&path)).unwrap() else {
+            panic!("an icon family isn't a bitmap");
+        };
+        assert_eq!(image.dimensions(), load_image(None).unwrap().dimensions());
+    }
+
+    #[test]
+    fn malformed_icons_are_errors() {
+        let dir = tempdir::TempDir::new("echidna-test").unwrap();
+        let png = encode_png(&DynamicImage::ImageRgba8(RgbaImage::new(8, 8))).unwrap();
+        for (name, contents) in [
+            ("truncated.icns", &SHIM_APP_ICON[..100]),
+            ("empty.icns", b"icns\0\0\0\x08".as_slice()),
+            ("garbage.icns", b"icns garbage".as_slice()),
+            ("truncated.png", &png[..png.len() / 2]),
+            ("text.png", b"not an image".as_slice()),
+            ("empty.png", b"".as_slice()),
+        ] {
+            let path = dir.path().join(name);
+            fs::write(&path, contents).unwrap();
+            assert!(load_source(Some(&path)).is_err(), "{name}");
+            assert!(
+                icns_for(&ShimIcon::from_path(Some(&path))).is_err(),
+                "{name}"
+            );
+        }
+        assert!(load_source(Some(&dir.path().join("missing.png"))).is_err());
+    }
+
+    // A wide red rectangle, so rendering has to center it.
+    const WIDE_SVG: &str = concat!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"200\" height=\"100\">",
+        "<rect width=\"200\" height=\"100\" fill=\"#ff0000\"/></svg>",
+    );
+
+    #[test]
+    fn svgs_are_rendered_at_each_size() {
+        let dir = tempdir::TempDir::new("echidna-test").unwrap();
+        // Told apart by contents too.
+        for name in ["wide.svg", "wide.icon"] {
+            let path = dir.path().join(name);
+            fs::write(&path, WIDE_SVG).unwrap();
+            let source = load_source(Some(&path)).unwrap();
+            assert!(matches!(source, IconSource::Svg(_)), "{name}");
+            assert_eq!(source.size_warning(&path), None);
+
+            let pngs = hicolor_pngs(&source).unwrap();
+            let sizes: Vec<_> = pngs.iter().map(|x| x.0).collect();
+            assert_eq!(sizes, HICOLOR_SIZES);
+            for (size, png) in pngs {
+                let image = image::load_from_memory(&png).unwrap();
+                assert_eq!(image.dimensions(), (size, size));
+            }
+        }
+
+        let path = dir.path().join("wide.svg");
+        let image = load_image(Some(&path)).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), (SVG_RENDER_SIZE, SVG_RENDER_SIZE));
+        // Centered, with transparent bands above and below.
+        let middle = SVG_RENDER_SIZE / 2;
+        assert_eq!(image.get_pixel(middle, middle), &Rgba([0xff, 0, 0, 0xff]));
+        assert_eq!(image.get_pixel(middle, 10)[3], 0);
+        assert_eq!(image.get_pixel(middle, SVG_RENDER_SIZE - 10)[3], 0);
+
+        let family = read_family(&encode_icns(&load_source(Some(&path)).unwrap()).unwrap());
+        assert_eq!(family.available_icons().len(), ICNS_TYPES.len());
+    }
+
+    #[test]
+    fn malformed_svgs_are_errors() {
+        let dir = tempdir::TempDir::new("echidna-test").unwrap();
+        for (name, contents) in [
+            (
+                "unclosed.svg",
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"10\"",
+            ),
+            ("not-svg.svg", "<html></html>"),
+            ("empty.svg", ""),
+            (
+                "zero.svg",
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"0\" height=\"0\"/>",
+            ),
+            ("unclosed.png", "<?xml version=\"1.0\"?>\n<svg><rect"),
+        ] {
+            let path = dir.path().join(name);
+            fs::write(&path, contents).unwrap();
+            assert!(load_source(Some(&path)).is_err(), "{name}");
+        }
+    }
+}
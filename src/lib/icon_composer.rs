@@ -0,0 +1,506 @@
+use crate::bailf;
+use crate::icon::{self, IconSource, SVG_RENDER_SIZE};
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use ab_glyph::{point, Font, FontRef, GlyphId, PxScale, ScaleFont};
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+// Tells shims apart when they share an icon, usually the default one, by drawing a short label, a
+// tint or a small image badge over it.
+
+const LABEL_FONT: &[u8] = include_bytes!("../../app_files/fonts/Hack-Regular.ttf");
+// For emoji, which Hack doesn't have.
+const FALLBACK_FONT: &[u8] = include_bytes!("../../app_files/fonts/NotoEmoji-Regular.ttf");
+
+// Longer labels get too small to read in the Dock.
+pub const MAX_LABEL_CHARS: usize = 8;
+
+// Relative to the icon's side.
+const LABEL_HEIGHT: f32 = 0.24;
+const LABEL_MAX_WIDTH: f32 = 0.78;
+const LABEL_BOTTOM: f32 = 0.14;
+const BADGE_SIZE: f32 = 0.4;
+const BADGE_INSET: f32 = 0.06;
+
+// How much of the tint color replaces the icon's own.
+const TINT_STRENGTH: f32 = 0.7;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Color(pub [u8; 4]);
+
+const NAMED_COLORS: &[(&str, [u8; 3])] = &[
+    ("red", [0xe5, 0x48, 0x4d]),
+    ("orange", [0xf7, 0x8c, 0x2f]),
+    ("yellow", [0xf5, 0xc4, 0x2c]),
+    ("green", [0x4c, 0xb0, 0x5a]),
+    ("teal", [0x2d, 0xaa, 0xa8]),
+    ("blue", [0x3b, 0x82, 0xf0]),
+    ("purple", [0x9b, 0x59, 0xd0]),
+    ("pink", [0xe8, 0x5c, 0xa8]),
+    ("gray", [0x8e, 0x8e, 0x93]),
+    ("black", [0x1c, 0x1c, 0x1e]),
+    ("white", [0xff, 0xff, 0xff]),
+];
+
+impl FromStr for Color {
+    type Err = String;
+
+    // #rgb, #rrggbb, #rrggbbaa (the # is optional) or one of NAMED_COLORS.
+    fn from_str(s: &str) -> Result<Color, String> {
+        let s = s.trim();
+        if let Some((_, [r, g, b])) = NAMED_COLORS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+        {
+            return Ok(Color([*r, *g, *b, 0xff]));
+        }
+
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let digits: Option<Vec<u8>> = hex
+            .chars()
+            .map(|x| x.to_digit(16).map(|x| x as u8))
+            .collect();
+        let channel = |hi: u8, lo: u8| hi << 4 | lo;
+        match digits.as_deref() {
+            Some(&[r, g, b]) => Ok(Color([r * 0x11, g * 0x11, b * 0x11, 0xff])),
+            Some(&[r1, r2, g1, g2, b1, b2]) => Ok(Color([
+                channel(r1, r2),
+                channel(g1, g2),
+                channel(b1, b2),
+                0xff,
+            ])),
+            Some(&[r1, r2, g1, g2, b1, b2, a1, a2]) => Ok(Color([
+                channel(r1, r2),
+                channel(g1, g2),
+                channel(b1, b2),
+                channel(a1, a2),
+            ])),
+            _ => {
+                let names: Vec<_> = NAMED_COLORS.iter().map(|(x, _)| *x).collect();
+                bailf!(
+                    "Invalid color '{s}', expected #rrggbb or one of {}",
+                    names.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b, a] = self.0;
+        write!(f, "#{r:02x}{g:02x}{b:02x}")?;
+        if a != 0xff {
+            write!(f, "{a:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Color, String> {
+        s.parse()
+    }
+}
+
+impl From<Color> for String {
+    fn from(color: Color) -> String {
+        color.to_string()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// A shim's icon: the user's image, or the default, and what to draw over it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShimIcon {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tint: Option<Color>,
+    // Drawn small, in the top right corner.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub badge: Option<PathBuf>,
+}
+
+impl ShimIcon {
+    pub fn from_path(path: Option<&Path>) -> ShimIcon {
+        ShimIcon {
+            path: path.map(|x| x.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    // What to record in the shim's config, so the icon can be made again from its source rather
+    // than drawn over. None for the default icon as it is.
+    pub fn recorded(&self) -> Option<ShimIcon> {
+        if self.path.is_none() && !self.is_composed() {
+            return None;
+        }
+        let absolute = |x: &Option<PathBuf>| {
+            x.as_ref()
+                .map(|x| std::path::absolute(x).unwrap_or(x.clone()))
+        };
+        Some(ShimIcon {
+            path: absolute(&self.path),
+            badge: absolute(&self.badge),
+            ..self.clone()
+        })
+    }
+
+    // Whether there's anything to compose, otherwise the icon is used as it is.
+    pub fn is_composed(&self) -> bool {
+        self.label.is_some() || self.tint.is_some() || self.badge.is_some()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(label) = &self.label {
+            if label.trim().is_empty() {
+                bailf!("Icon label must not be empty");
+            }
+            if label.trim().chars().count() > MAX_LABEL_CHARS {
+                bailf!("Icon label '{label}' is longer than {MAX_LABEL_CHARS} characters");
+            }
+        }
+        Ok(())
+    }
+
+    // The icon to render, with everything drawn over it, and a warning if the image is too
+    // small.
+    pub fn load(&self) -> Result<(IconSource, Option<String>), String> {
+        self.validate()?;
+        let source = icon::load_source(self.path.as_deref())?;
+        let warning = self
+            .path
+            .as_deref()
+            .and_then(|path| source.size_warning(path));
+        if !self.is_composed() {
+            return Ok((source, warning));
+        }
+        let composed = self.compose(&source, SVG_RENDER_SIZE)?;
+        Ok((IconSource::Raster(composed), warning))
+    }
+
+    // Renders a side pixel square preview, e.g. for a thumbnail.
+    pub fn preview(&self, side: u32) -> Result<DynamicImage, String> {
+        self.validate()?;
+        let source = icon::load_source(self.path.as_deref())?;
+        self.compose(&source, side)
+    }
+
+    fn compose(&self, source: &IconSource, side: u32) -> Result<DynamicImage, String> {
+        let badge = match &self.badge {
+            Some(path) => Some(icon::load_source(Some(path))?),
+            None => None,
+        };
+
+        let mut canvas = source.render(side).to_rgba8();
+
+        if let Some(tint) = self.tint {
+            apply_tint(&mut canvas, tint);
+        }
+
+        if let Some(badge) = badge {
+            let badge_side = (side as f32 * BADGE_SIZE).round().max(1.0) as u32;
+            let inset = (side as f32 * BADGE_INSET).round() as i64;
+            let x = i64::from(side) - i64::from(badge_side) - inset;
+            image::imageops::overlay(&mut canvas, &badge.render(badge_side).to_rgba8(), x, inset);
+        }
+
+        if let Some(label) = &self.label {
+            draw_label(&mut canvas, label.trim(), self.tint);
+        }
+
+        Ok(DynamicImage::ImageRgba8(canvas))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn luminance([r, g, b, _]: [u8; 4]) -> f32 {
+    (0.299 * f32::from(r) + 0.587 * f32::from(g) + 0.114 * f32::from(b)) / 255.0
+}
+
+// Recolors the icon with the tint's hue, keeping its shading and transparency.
+fn apply_tint(canvas: &mut RgbaImage, tint: Color) {
+    let strength = TINT_STRENGTH * f32::from(tint.0[3]) / 255.0;
+    for pixel in canvas.pixels_mut() {
+        // Lighter than the tint keeps highlights, darker keeps shadows.
+        let shade = (luminance(pixel.0) * 1.4).min(1.6);
+        for (channel, tint) in pixel.0.iter_mut().zip(tint.0).take(3) {
+            let tinted = (f32::from(tint) * shade).min(255.0);
+            let mixed = f32::from(*channel) * (1.0 - strength) + tinted * strength;
+            *channel = mixed.round() as u8;
+        }
+    }
+}
+
+fn blend(pixel: &mut Rgba<u8>, color: [u8; 3], coverage: f32) {
+    let coverage = coverage.clamp(0.0, 1.0);
+    let dst_alpha = f32::from(pixel.0[3]) / 255.0;
+    let alpha = coverage + dst_alpha * (1.0 - coverage);
+    if alpha <= 0.0 {
+        return;
+    }
+    for (channel, color) in pixel.0.iter_mut().zip(color) {
+        let src = f32::from(color) * coverage;
+        let dst = f32::from(*channel) * dst_alpha * (1.0 - coverage);
+        *channel = ((src + dst) / alpha).round() as u8;
+    }
+    pixel.0[3] = (alpha * 255.0).round() as u8;
+}
+
+// Fills a rounded rectangle, antialiased.
+fn fill_rounded_rect(
+    canvas: &mut RgbaImage,
+    (left, top, width, height): (f32, f32, f32, f32),
+    color: [u8; 4],
+) {
+    let radius = height / 2.0;
+    let opacity = f32::from(color[3]) / 255.0;
+    let (right, bottom) = (left + width, top + height);
+
+    let x_range = left.floor().max(0.0) as u32..(right.ceil() as u32).min(canvas.width());
+    let y_range = top.floor().max(0.0) as u32..(bottom.ceil() as u32).min(canvas.height());
+    for y in y_range {
+        for x in x_range.clone() {
+            let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+            // Distance outside the rectangle, shrunk by the radius.
+            let dx = (left + radius - px).max(px - (right - radius)).max(0.0);
+            let dy = (top + radius - py).max(py - (bottom - radius)).max(0.0);
+            let distance = (dx * dx + dy * dy).sqrt() - radius;
+            let coverage = (0.5 - distance).clamp(0.0, 1.0) * opacity;
+            if coverage > 0.0 {
+                blend(
+                    canvas.get_pixel_mut(x, y),
+                    [color[0], color[1], color[2]],
+                    coverage,
+                );
+            }
+        }
+    }
+}
+
+// The label's glyphs, by font, and their x positions for a font height of 1.0.
+fn layout(fonts: &[FontRef], label: &str) -> (Vec<(usize, GlyphId, f32)>, f32) {
+    let mut glyphs = vec![];
+    let mut x = 0.0;
+    let mut previous: Option<(usize, GlyphId)> = None;
+    for c in label.chars() {
+        let index = fonts.iter().position(|x| x.glyph_id(c).0 != 0).unwrap_or(0);
+        let font = fonts[index].as_scaled(PxScale::from(1.0));
+        let id = font.glyph_id(c);
+        if let Some((previous_index, previous_id)) = previous {
+            if previous_index == index {
+                x += font.kern(previous_id, id);
+            }
+        }
+        glyphs.push((index, id, x));
+        x += font.h_advance(id);
+        previous = Some((index, id));
+    }
+    (glyphs, x)
+}
+
+// Draws the label in a pill along the bottom of the icon.
+fn draw_label(canvas: &mut RgbaImage, label: &str, tint: Option<Color>) {
+    let fonts = [
+        FontRef::try_from_slice(LABEL_FONT).expect("Bundled label font is valid"),
+        FontRef::try_from_slice(FALLBACK_FONT).expect("Bundled fallback font is valid"),
+    ];
+
+    let (width, height) = canvas.dimensions();
+    let side = width.min(height) as f32;
+    let pill_height = side * LABEL_HEIGHT;
+
+    let (glyphs, unit_width) = layout(&fonts, label);
+    let max_text_width = side * LABEL_MAX_WIDTH - pill_height * 0.6;
+    let font_size = (pill_height * 0.7).min(max_text_width / unit_width.max(f32::EPSILON));
+    let text_width = unit_width * font_size;
+
+    let pill_width = (text_width + pill_height * 0.6).max(pill_height);
+    let pill_left = (width as f32 - pill_width) / 2.0;
+    let pill_top = height as f32 - side * LABEL_BOTTOM - pill_height;
+    let background = match tint {
+        Some(Color([r, g, b, _])) => [r, g, b, 0xf0],
+        None => [0x1c, 0x1c, 0x1e, 0xe0],
+    };
+    fill_rounded_rect(
+        canvas,
+        (pill_left, pill_top, pill_width, pill_height),
+        background,
+    );
+
+    let foreground = if luminance(background) > 0.6 {
+        [0x1c, 0x1c, 0x1e]
+    } else {
+        [0xff, 0xff, 0xff]
+    };
+
+    // Centered on the pill, going by the primary font's metrics.
+    let scale = PxScale::from(font_size);
+    let metrics = fonts[0].as_scaled(scale);
+    let baseline = pill_top + (pill_height + metrics.ascent() + metrics.descent()) / 2.0;
+    let text_left = (width as f32 - text_width) / 2.0;
+
+    for (index, id, x) in glyphs {
+        let glyph = id.with_scale_and_position(scale, point(text_left + x * font_size, baseline));
+        let Some(outlined) = fonts[index].outline_glyph(glyph) else {
+            continue; // Whitespace.
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|x, y, coverage| {
+            let (x, y) = (
+                bounds.min.x as i64 + i64::from(x),
+                bounds.min.y as i64 + i64::from(y),
+            );
+            if (0..i64::from(width)).contains(&x) && (0..i64::from(height)).contains(&y) {
+                blend(
+                    canvas.get_pixel_mut(x as u32, y as u32),
+                    foreground,
+                    coverage,
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+    use std::fs;
+
+    const SIDE: u32 = 128;
+
+    // A side pixel square PNG of a single color, in dir.
+    fn write_png(dir: &Path, name: &str, side: u32, color: [u8; 4]) -> PathBuf {
+        let path = dir.join(name);
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(side, side, Rgba(color)));
+        fs::write(&path, icon::encode_png(&image).unwrap()).unwrap();
+        path
+    }
+
+    #[test]
+    fn colors_parse_and_display() {
+        for (s, color) in [
+            ("#3b82f0", [0x3b, 0x82, 0xf0, 0xff]),
+            ("3B82F0", [0x3b, 0x82, 0xf0, 0xff]),
+            ("#fa0", [0xff, 0xaa, 0x00, 0xff]),
+            ("#3b82f080", [0x3b, 0x82, 0xf0, 0x80]),
+            (" Blue ", [0x3b, 0x82, 0xf0, 0xff]),
+        ] {
+            assert_eq!(s.parse(), Ok(Color(color)), "{s}");
+        }
+        assert_eq!(Color([0x3b, 0x82, 0xf0, 0xff]).to_string(), "#3b82f0");
+        assert_eq!(Color([0x3b, 0x82, 0xf0, 0x80]).to_string(), "#3b82f080");
+
+        for s in ["", "#", "#12", "#12345", "#gggggg", "#3b82f0801", "mauve"] {
+            assert!(s.parse::<Color>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn labels_are_validated_before_loading() {
+        let missing = Some(PathBuf::from("/nonexistent/icon.png"));
+        for label in ["", "  ", "123456789"] {
+            let icon = ShimIcon {
+                path: missing.clone(),
+                label: Some(label.to_owned()),
+                ..Default::default()
+            };
+            let e = icon.load().err().unwrap();
+            assert!(e.starts_with("Icon label"), "{label}: {e}");
+        }
+
+        let icon = ShimIcon {
+            label: Some(" 12345678 ".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(icon.validate(), Ok(()));
+    }
+
+    #[test]
+    fn composed_icons_have_the_requested_size() {
+        let dir = tempdir::TempDir::new("echidna-test").unwrap();
+        let white = [0xff, 0xff, 0xff, 0xff];
+        let path = write_png(dir.path(), "white.png", 64, white);
+        let badge = write_png(dir.path(), "badge.png", 16, [0, 0, 0xff, 0xff]);
+
+        let plain = ShimIcon::from_path(Some(&path)).preview(SIDE).unwrap();
+        assert_eq!(plain.dimensions(), (SIDE, SIDE));
+        assert!(plain.to_rgba8().pixels().all(|x| x.0 == white));
+
+        let tinted = ShimIcon {
+            tint: "red".parse().ok(),
+            ..ShimIcon::from_path(Some(&path))
+        };
+        let image = tinted.preview(SIDE).unwrap().to_rgba8();
+        let pixel = image.get_pixel(SIDE / 2, SIDE / 2).0;
+        assert!(pixel[0] > pixel[2], "{pixel:?}");
+
+        let badged = ShimIcon {
+            badge: Some(badge),
+            ..ShimIcon::from_path(Some(&path))
+        };
+        let image = badged.preview(SIDE).unwrap().to_rgba8();
+        // In the top right corner, not the top left.
+        assert_eq!(image.get_pixel(SIDE - 20, 20).0, [0, 0, 0xff, 0xff]);
+        assert_eq!(image.get_pixel(20, 20).0, white);
+
+        for label in ["vim", "🐍", "日本"] {
+            let labeled = ShimIcon {
+                label: Some(label.to_owned()),
+                ..ShimIcon::from_path(Some(&path))
+            };
+            let image = labeled.preview(SIDE).unwrap().to_rgba8();
+            // Drawn in a dark pill along the bottom, leaving the rest.
+            let y = SIDE - (SIDE as f32 * (LABEL_BOTTOM + LABEL_HEIGHT / 2.0)) as u32;
+            let dark = |x| luminance(image.get_pixel(x, y).0) < 0.5;
+            assert!((0..SIDE).any(dark), "{label}");
+            assert_eq!(image.get_pixel(SIDE / 2, 4).0, white, "{label}");
+        }
+
+        let (source, warning) = badged.load().unwrap();
+        assert!(warning.unwrap().contains("64x64"));
+        let IconSource::Raster(image) = source else {
+            panic!("a composed icon isn't an SVG");
+        };
+        assert_eq!(image.dimensions(), (SVG_RENDER_SIZE, SVG_RENDER_SIZE));
+    }
+
+    #[test]
+    fn malformed_badges_are_errors() {
+        let dir = tempdir::TempDir::new("echidna-test").unwrap();
+        let badge = dir.path().join("badge.png");
+        fs::write(&badge, "not an image").unwrap();
+        let icon = ShimIcon {
+            badge: Some(badge),
+            ..Default::default()
+        };
+        assert!(icon.preview(SIDE).is_err());
+        assert!(icon.load().is_err());
+        assert!(icon::icns_for(&icon).is_err());
+    }
+
+    #[test]
+    fn recorded_keeps_only_what_makes_the_icon() {
+        assert_eq!(ShimIcon::default().recorded(), None);
+        let icon = ShimIcon {
+            tint: "red".parse().ok(),
+            ..Default::default()
+        };
+        assert_eq!(icon.recorded(), Some(icon));
+    }
+}
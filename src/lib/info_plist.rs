@@ -0,0 +1,326 @@
+use crate::bailf;
+use crate::generate::DocTypes;
+use crate::type_decl::TypeDecl;
+
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+use plist::{Dictionary, Value};
+
+// A macOS shim's Info.plist, built as a plist::Dictionary so every value is escaped properly, and
+// so extra keys can be merged in.
+
+// Echidna's version, as of generating the shim.
+pub const GENERATOR_VERSION_KEY: &str = "EchidnaVersion";
+
+pub struct InfoPlist<'a> {
+    pub app_name: &'a str,
+    pub doc_type: &'a DocTypes,
+    pub bundle_id: &'a str,
+    // CFBundleVersion, the build number.
+    pub bundle_version: &'a str,
+    pub exported_types: &'a [TypeDecl],
+    // The user's own types, which the shim also opens.
+    pub imported_types: &'a [TypeDecl],
+    // Shown when the shim first asks for permission to control it.
+    pub terminal: &'a str,
+}
+
+fn strings(values: &[String]) -> Value {
+    Value::Array(values.iter().map(|x| Value::String(x.clone())).collect())
+}
+
+fn type_decl(decl: &TypeDecl) -> Value {
+    let mut tags = Dictionary::new();
+    tags.insert(
+        "public.filename-extension".into(),
+        strings(&decl.extensions),
+    );
+    tags.insert("public.mime-type".into(), decl.mime_type.clone().into());
+
+    let mut dict = Dictionary::new();
+    dict.insert("UTTypeIdentifier".into(), decl.identifier.clone().into());
+    dict.insert("UTTypeDescription".into(), decl.description.clone().into());
+    dict.insert("UTTypeConformsTo".into(), strings(&decl.conforms_to));
+    dict.insert("UTTypeTagSpecification".into(), Value::Dictionary(tags));
+    if decl.icon.is_some() {
+        dict.insert("UTTypeIconFile".into(), decl.icon_file_name().into());
+    }
+    Value::Dictionary(dict)
+}
+
+impl InfoPlist<'_> {
+    pub fn build(&self) -> Dictionary {
+        let mut doc_types = vec![];
+        for (i, group) in self.doc_type.groups().iter().enumerate() {
+            let mut doc_type = Dictionary::new();
+            if let Some(name) = &group.name {
+                doc_type.insert("CFBundleTypeName".into(), name.clone().into());
+            }
+            doc_type.insert("CFBundleTypeRole".into(), group.role.to_string().into());
+            if let Some(rank) = group.rank {
+                doc_type.insert("LSHandlerRank".into(), rank.to_string().into());
+            }
+            if group.icon.is_some() {
+                doc_type.insert(
+                    "CFBundleTypeIconFile".into(),
+                    DocTypes::icon_file_name(i).into(),
+                );
+            }
+
+            // LSItemContentTypes makes macOS ignore CFBundleTypeExtensions in the same entry, so
+            // a group with both becomes two entries.
+            if !group.utis.is_empty() {
+                let mut doc_type = doc_type.clone();
+                doc_type.insert("LSItemContentTypes".into(), strings(&group.utis));
+                doc_types.push(Value::Dictionary(doc_type));
+            }
+            if !group.exts.is_empty() {
+                doc_type.insert("CFBundleTypeExtensions".into(), strings(&group.exts));
+                doc_types.push(Value::Dictionary(doc_type));
+            }
+        }
+
+        if !self.imported_types.is_empty() {
+            // Separate, a CFBundleTypeExtensions entry would be ignored alongside these.
+            let identifiers: Vec<_> = self
+                .imported_types
+                .iter()
+                .map(|x| x.identifier.clone())
+                .collect();
+
+            let mut doc_type = Dictionary::new();
+            doc_type.insert("LSItemContentTypes".into(), strings(&identifiers));
+            doc_type.insert("CFBundleTypeRole".into(), "Editor".into());
+            doc_types.push(Value::Dictionary(doc_type));
+        }
+
+        let mut dict = Dictionary::new();
+        dict.insert("CFBundleDevelopmentRegion".into(), "en".into());
+        dict.insert("CFBundleDocumentTypes".into(), Value::Array(doc_types));
+        dict.insert("CFBundleExecutable".into(), self.app_name.into());
+        dict.insert("CFBundleIconFile".into(), "AppIcon.icns".into());
+        dict.insert("CFBundleIdentifier".into(), self.bundle_id.into());
+        dict.insert("CFBundleInfoDictionaryVersion".into(), "6.0".into());
+        dict.insert("CFBundleName".into(), self.app_name.into());
+        dict.insert("CFBundlePackageType".into(), "APPL".into());
+        dict.insert(
+            "CFBundleShortVersionString".into(),
+            env!("CARGO_PKG_VERSION").into(),
+        );
+        dict.insert("CFBundleVersion".into(), self.bundle_version.into());
+        // What generated the shim, CFBundleShortVersionString may be replaced by extra keys.
+        dict.insert(
+            GENERATOR_VERSION_KEY.into(),
+            env!("CARGO_PKG_VERSION").into(),
+        );
+        dict.insert(
+            "NSAppleEventsUsageDescription".into(),
+            apple_events_usage(self.app_name, self.terminal).into(),
+        );
+
+        if !self.exported_types.is_empty() {
+            dict.insert(
+                "UTExportedTypeDeclarations".into(),
+                Value::Array(self.exported_types.iter().map(type_decl).collect()),
+            );
+        }
+
+        if !self.imported_types.is_empty() {
+            dict.insert(
+                "UTImportedTypeDeclarations".into(),
+                Value::Array(self.imported_types.iter().map(type_decl).collect()),
+            );
+        }
+
+        dict
+    }
+}
+
+// Shown when the shim first asks for permission to control the terminal.
+pub fn apple_events_usage(app_name: &str, terminal: &str) -> String {
+    format!("{app_name} uses Apple Events to open files in {terminal}.")
+}
+
+// Top-level keys in extra replace the generated ones.
+pub fn merge(dict: &mut Dictionary, extra: &Dictionary) {
+    for (key, value) in extra {
+        dict.insert(key.clone(), value.clone());
+    }
+}
+
+pub fn to_xml(dict: &Dictionary) -> Result<Vec<u8>, String> {
+    let mut buffer = vec![];
+    plist::to_writer_xml(&mut buffer, dict)
+        .map_err(|e| format!("Error serializing Info.plist: {e}"))?;
+    Ok(buffer)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn from_json(json: serde_json::Value) -> Result<Value, String> {
+    use serde_json::Value as Json;
+    Ok(match json {
+        Json::Null => bailf!("null has no plist equivalent"),
+        Json::Bool(x) => Value::Boolean(x),
+        Json::Number(x) => match (x.as_i64(), x.as_u64(), x.as_f64()) {
+            (Some(x), _, _) => Value::Integer(x.into()),
+            (_, Some(x), _) => Value::Integer(x.into()),
+            (_, _, Some(x)) => Value::Real(x),
+            _ => bailf!("Unrepresentable number {x}"),
+        },
+        Json::String(x) => Value::String(x),
+        Json::Array(x) => Value::Array(x.into_iter().map(from_json).collect::<Result<_, _>>()?),
+        Json::Object(x) => {
+            let mut dict = Dictionary::new();
+            for (k, v) in x {
+                dict.insert(k.clone(), from_json(v).map_err(|e| format!("'{k}': {e}"))?);
+            }
+            Value::Dictionary(dict)
+        }
+    })
+}
+
+// Parses keys to merge into the Info.plist: a JSON object, or a plist dictionary (XML, with or
+// without the surrounding <plist>, or binary).
+pub fn parse_extra(contents: &[u8]) -> Result<Dictionary, String> {
+    let trimmed = String::from_utf8_lossy(contents);
+    let trimmed = trimmed.trim_start();
+
+    let value = if trimmed.starts_with(['{', '[']) {
+        let json: serde_json::Value =
+            serde_json::from_slice(contents).map_err(|e| format!("Invalid JSON: {e}"))?;
+        from_json(json)?
+    } else if trimmed.starts_with("<dict") {
+        let wrapped = format!("<plist version=\"1.0\">{trimmed}</plist>");
+        Value::from_reader(Cursor::new(wrapped)).map_err(|e| format!("Invalid plist: {e}"))?
+    } else {
+        Value::from_reader(Cursor::new(contents)).map_err(|e| format!("Invalid plist: {e}"))?
+    };
+
+    match value {
+        Value::Dictionary(x) => Ok(x),
+        _ => bailf!("Extra Info.plist keys must be a dictionary"),
+    }
+}
+
+// The Info.plist of the bundle at bundle_path, if there's a readable one.
+pub fn read_bundle(bundle_path: &Path) -> Option<Dictionary> {
+    let path = bundle_path.join("Contents").join("Info.plist");
+    Value::from_file(path).ok()?.into_dictionary()
+}
+
+pub fn load_extra(path: &Path) -> Result<Dictionary, String> {
+    let contents =
+        fs::read(path).map_err(|e| format!("Error reading '{}': {e}", path.display()))?;
+    parse_extra(&contents).map_err(|e| format!("Error parsing '{}': {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::DocTypeGroup;
+
+    fn build(app_name: &str, doc_type: &DocTypes, imported_types: &[TypeDecl]) -> Dictionary {
+        InfoPlist {
+            app_name,
+            doc_type,
+            bundle_id: "com.example.vim",
+            bundle_version: "1",
+            exported_types: &[],
+            imported_types,
+            terminal: "Terminal.app",
+        }
+        .build()
+    }
+
+    #[test]
+    fn user_supplied_strings_are_escaped() {
+        let doc_type = DocTypes(vec![DocTypeGroup {
+            name: Some("Notes & <Drafts>".to_owned()),
+            utis: vec!["com.example.a&b".to_owned()],
+            exts: vec!["<md>".to_owned()],
+            ..Default::default()
+        }]);
+        let decl = TypeDecl {
+            identifier: "com.example.tom&jerry".to_owned(),
+            mime_type: "application/x-tom&jerry".to_owned(),
+            description: "\"Tom\" & 'Jerry' <3".to_owned(),
+            conforms_to: vec!["public.text".to_owned()],
+            extensions: vec!["t&j".to_owned()],
+            icon: None,
+        };
+        let dict = build("Vim & <Co>", &doc_type, &[decl]);
+
+        let xml = String::from_utf8(to_xml(&dict).unwrap()).unwrap();
+        for escaped in [
+            "<string>Vim &amp; &lt;Co&gt;</string>",
+            "<string>Notes &amp; &lt;Drafts&gt;</string>",
+            "<string>com.example.a&amp;b</string>",
+            "<string>&lt;md&gt;</string>",
+            "<string>com.example.tom&amp;jerry</string>",
+            "<string>t&amp;j</string>",
+        ] {
+            assert!(xml.contains(escaped), "no {escaped} in {xml}");
+        }
+        assert!(!xml.contains("<Co>") && !xml.contains("<md>"), "{xml}");
+
+        // And it reads back as the same strings.
+        let read = Value::from_reader(Cursor::new(xml)).unwrap();
+        assert_eq!(read.into_dictionary().unwrap(), dict);
+    }
+
+    #[test]
+    fn merge_replaces_top_level_keys_whole() {
+        let doc_type = DocTypes(vec![DocTypeGroup {
+            exts: vec!["txt".to_owned()],
+            ..Default::default()
+        }]);
+        let mut dict = build("Vim", &doc_type, &[]);
+        let extra = parse_extra(
+            br#"{
+                "CFBundleShortVersionString": "9.1",
+                "CFBundleDocumentTypes": [{"CFBundleTypeExtensions": ["md"]}],
+                "LSUIElement": true
+            }"#,
+        )
+        .unwrap();
+        merge(&mut dict, &extra);
+
+        assert_eq!(dict["CFBundleShortVersionString"], "9.1".into());
+        // Arrays and dictionaries aren't merged, they're replaced.
+        let doc_types = dict["CFBundleDocumentTypes"].as_array().unwrap();
+        assert_eq!(
+            doc_types,
+            &extra["CFBundleDocumentTypes"].as_array().unwrap()[..]
+        );
+        assert_eq!(dict["LSUIElement"], Value::Boolean(true));
+        // Generated keys that weren't given are kept, including the generator's version.
+        assert_eq!(dict["CFBundleIdentifier"], "com.example.vim".into());
+        assert_eq!(
+            dict[GENERATOR_VERSION_KEY],
+            env!("CARGO_PKG_VERSION").into()
+        );
+    }
+
+    #[test]
+    fn parse_extra_accepts_json_and_plists() {
+        let expected = parse_extra(br#"{"LSUIElement": true, "Name": "a & b"}"#).unwrap();
+        let dict =
+            "<dict><key>LSUIElement</key><true/><key>Name</key><string>a &amp; b</string></dict>";
+        assert_eq!(parse_extra(dict.as_bytes()).unwrap(), expected);
+        let plist = format!("<?xml version=\"1.0\"?>\n<plist version=\"1.0\">{dict}</plist>");
+        assert_eq!(parse_extra(plist.as_bytes()).unwrap(), expected);
+
+        for invalid in [
+            "[1, 2]",
+            r#"{"LSUIElement": null}"#,
+            "{",
+            "<dict><key>a</key>",
+            "<plist version=\"1.0\"><string>a</string></plist>",
+        ] {
+            assert!(parse_extra(invalid.as_bytes()).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::bailf;
+
+use std::path::Path;
+
+use core_foundation::base::{Boolean, TCFType};
+use core_foundation::string::{CFString, CFStringRef};
+use core_foundation::url::{CFURLRef, CFURL};
+
+// Thin wrappers around the parts of CoreServices (Uniform Type Identifiers and LaunchServices)
+// that we need. macOS only.
+
+#[link(name = "CoreServices", kind = "framework")]
+extern "C" {
+    static kUTTagClassFilenameExtension: CFStringRef;
+
+    fn UTTypeCreatePreferredIdentifierForTag(
+        tag_class: CFStringRef,
+        tag: CFStringRef,
+        conforming_to_uti: CFStringRef,
+    ) -> CFStringRef;
+}
+
+// The UTI the system associates with an extension. If no type declares the extension, this is a
+// dynamic ("dyn.") identifier.
+pub fn uti_for_ext(ext: &str) -> Option<String> {
+    let ext = CFString::new(ext);
+    unsafe {
+        let uti = UTTypeCreatePreferredIdentifierForTag(
+            kUTTagClassFilenameExtension,
+            ext.as_concrete_TypeRef(),
+            std::ptr::null(),
+        );
+        if uti.is_null() {
+            return None;
+        }
+        Some(CFString::wrap_under_create_rule(uti).to_string())
+    }
+}
+
+// Whether some installed type declares the extension.
+pub fn is_known_ext(ext: &str) -> bool {
+    uti_for_ext(ext).is_some_and(|x| !x.starts_with("dyn."))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+type OSStatus = i32;
+type LSRolesMask = u32;
+
+const K_LS_ROLES_ALL: LSRolesMask = 0xFFFFFFFF;
+
+#[link(name = "CoreServices", kind = "framework")]
+extern "C" {
+    fn LSRegisterURL(url: CFURLRef, update: Boolean) -> OSStatus;
+
+    fn LSCopyDefaultRoleHandlerForContentType(
+        content_type: CFStringRef,
+        role: LSRolesMask,
+    ) -> CFStringRef;
+
+    fn LSSetDefaultRoleHandlerForContentType(
+        content_type: CFStringRef,
+        role: LSRolesMask,
+        handler_bundle_id: CFStringRef,
+    ) -> OSStatus;
+}
+
+// Tells LaunchServices about an app (and the types it declares), which otherwise only happens
+// once Finder notices it.
+pub fn register(app: &Path) -> Result<(), String> {
+    let Some(url) = CFURL::from_path(app, true) else {
+        bailf!("Couldn't make a URL from '{}'", app.display());
+    };
+
+    let status = unsafe { LSRegisterURL(url.as_concrete_TypeRef(), 1) };
+    if status != 0 {
+        bailf!("Error registering '{}': OSStatus {status}", app.display());
+    }
+
+    Ok(())
+}
+
+// The bundle identifier of the default handler for the UTI, if any.
+pub fn default_handler(uti: &str) -> Option<String> {
+    let uti = CFString::new(uti);
+    unsafe {
+        let handler =
+            LSCopyDefaultRoleHandlerForContentType(uti.as_concrete_TypeRef(), K_LS_ROLES_ALL);
+        if handler.is_null() {
+            return None;
+        }
+        Some(CFString::wrap_under_create_rule(handler).to_string())
+    }
+}
+
+pub fn set_default_handler(uti: &str, bundle_id: &str) -> Result<(), String> {
+    let (cf_uti, cf_bundle_id) = (CFString::new(uti), CFString::new(bundle_id));
+    let status = unsafe {
+        LSSetDefaultRoleHandlerForContentType(
+            cf_uti.as_concrete_TypeRef(),
+            K_LS_ROLES_ALL,
+            cf_bundle_id.as_concrete_TypeRef(),
+        )
+    };
+    if status != 0 {
+        bailf!("Error setting default handler for '{uti}' to '{bundle_id}': OSStatus {status}");
+    }
+
+    Ok(())
+}
@@ -1,5 +1,23 @@
+pub mod archive;
 pub mod bundle_tmp_dir;
 pub mod config;
+pub mod defaults;
+pub mod desktop;
 pub mod generate;
+pub mod icon;
+pub mod icon_composer;
+pub mod info_plist;
+#[cfg(target_os = "macos")]
+pub mod launch_services;
 pub mod misc;
+pub mod open;
+pub mod output;
+pub mod platypus;
+pub mod recipe;
+pub mod registry;
+pub mod replace;
+pub mod shim;
 pub mod term;
+pub mod type_decl;
+pub mod upgrade;
+pub mod xdg;
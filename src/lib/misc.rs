@@ -1,4 +1,9 @@
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[macro_export]
 macro_rules! bail {
@@ -38,3 +43,185 @@ pub fn get_app_resources() -> Result<PathBuf, String> {
     path.push("Resources");
     Ok(path)
 }
+
+// Per-user directory for Echidna's own records.
+pub fn state_dir() -> Result<PathBuf, String> {
+    if cfg!(target_os = "macos") {
+        let mut path = crate::xdg::home_dir().ok_or("Couldn't find home directory")?;
+        path.push("Library/Application Support/Echidna");
+        Ok(path)
+    } else {
+        Ok(crate::xdg::state_home()?.join("echidna"))
+    }
+}
+
+// An exclusive advisory lock on state_dir(), held while reading, changing and writing back a file
+// in it, so concurrent echidna processes (e.g. `sync` while the GUI saves a shim) don't lose each
+// other's changes. Released when dropped. Not reentrant, even within a process.
+pub struct StateLock {
+    _file: fs::File,
+}
+
+pub fn lock_state_dir() -> Result<StateLock, String> {
+    let dir = state_dir()?;
+    fs::create_dir_all(&dir)
+        .map_err(|e| format!("Error creating directory '{}': {e}", dir.display()))?;
+
+    let path = dir.join(".lock");
+    let err = |e: io::Error| format!("Error locking '{}': {e}", path.display());
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(err)?;
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(StateLock { _file: file });
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(err(e));
+        }
+    }
+}
+
+// The temporary sibling path is written to before it's renamed over path, creating path's parent
+// directory if needed.
+fn write_tmp(path: &Path, contents: &[u8]) -> Result<PathBuf, String> {
+    let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+        bailf!("Invalid path '{}'", path.display());
+    };
+
+    fs::create_dir_all(parent)
+        .map_err(|e| format!("Error creating directory '{}': {e}", parent.display()))?;
+
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(".tmp");
+    let tmp_path = parent.join(tmp_name);
+
+    fs::write(&tmp_path, contents).map_err(|e| {
+        let _ = fs::remove_file(&tmp_path);
+        format!("Error writing '{}': {e}", tmp_path.display())
+    })?;
+    Ok(tmp_path)
+}
+
+// Writes via a temporary sibling and a rename, so readers never see a partial file. Creates the
+// parent directory if needed.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let tmp_path = write_tmp(path, contents)?;
+    fs::rename(&tmp_path, path).map_err(|e| {
+        let _ = fs::remove_file(&tmp_path);
+        format!("Error moving '{}' into place: {e}", path.display())
+    })
+}
+
+// Like write_atomic(), for several files that must change together: all are written before any
+// is moved into place, and if moving one fails, those already moved are put back as they were.
+pub fn write_all_atomic(files: &[(PathBuf, Vec<u8>)]) -> Result<(), String> {
+    let mut tmp_paths = vec![];
+    for (path, contents) in files {
+        match write_tmp(path, contents) {
+            Ok(x) => tmp_paths.push(x),
+            Err(e) => {
+                for tmp_path in &tmp_paths {
+                    let _ = fs::remove_file(tmp_path);
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    // None for files that didn't exist.
+    let originals: Vec<Option<Vec<u8>>> = files.iter().map(|(x, _)| fs::read(x).ok()).collect();
+
+    for (i, ((path, _), tmp_path)) in files.iter().zip(&tmp_paths).enumerate() {
+        if let Err(e) = fs::rename(tmp_path, path) {
+            for tmp_path in &tmp_paths[i..] {
+                let _ = fs::remove_file(tmp_path);
+            }
+            for ((path, _), original) in files[..i].iter().zip(&originals) {
+                let _ = match original {
+                    Some(x) => write_atomic(path, x),
+                    None => fs::remove_file(path).map_err(|e| e.to_string()),
+                };
+            }
+            bailf!("Error moving '{}' into place: {e}", path.display());
+        }
+    }
+    Ok(())
+}
+
+// Seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs())
+}
+
+// As "YYYY-MM-DD HH:MM" in UTC.
+pub fn format_unix_time(secs: u64) -> String {
+    // Howard Hinnant's civil_from_days.
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let (hour, minute) = (secs % 86400 / 3600, secs % 3600 / 60);
+    format!("{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    #[test]
+    fn write_all_atomic_writes_every_file() {
+        let dir = TempDir::new("echidna-test").unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("sub/b"));
+        fs::write(&a, "old").unwrap();
+
+        write_all_atomic(&[(a.clone(), b"new".to_vec()), (b.clone(), b"b".to_vec())]).unwrap();
+        assert_eq!(fs::read(&a).unwrap(), b"new");
+        assert_eq!(fs::read(&b).unwrap(), b"b");
+    }
+
+    #[test]
+    fn write_all_atomic_rolls_back() {
+        let dir = TempDir::new("echidna-test").unwrap();
+        let (a, b, c) = (
+            dir.path().join("a"),
+            dir.path().join("b"),
+            dir.path().join("c"),
+        );
+        fs::write(&a, "old").unwrap();
+        // A file can't replace a directory that isn't empty.
+        fs::create_dir_all(c.join("x")).unwrap();
+
+        let files = [
+            (a.clone(), b"new".to_vec()),
+            (b.clone(), b"b".to_vec()),
+            (c.clone(), b"c".to_vec()),
+        ];
+        assert!(write_all_atomic(&files).is_err());
+        assert_eq!(fs::read(&a).unwrap(), b"old");
+        assert!(!b.exists());
+        assert!(c.join("x").is_dir());
+
+        let mut left: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|x| x.unwrap().file_name())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["a", "c"]);
+    }
+}
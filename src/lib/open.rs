@@ -0,0 +1,93 @@
+use crate::config::{Config, GroupBy};
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+use shell_quote::Bash;
+use url::Url;
+
+// Turning the files a shim is asked to open into the scripts to run in the terminal. Shared by
+// the shim's AppKit and command-line runtimes.
+
+// Extend an OsString with anything that implements AsRef<OsStr>.
+macro_rules! os_extend {
+    ($os_string:ident, $($vals:expr),*) => {{
+        $os_string.extend([$($vals.as_ref()),+]);
+    }};
+}
+
+// Concatenate anything that implements AsRef<OsStr> into an OsString.
+macro_rules! os_cat {
+    ($($vals:expr),*) => {{
+        let mut os_string = OsString::new();
+        os_extend!(os_string, $($vals),+);
+        os_string
+    }};
+}
+
+pub fn bash_quote<S: AsRef<OsStr>>(string: S) -> OsString {
+    let string = string.as_ref();
+    OsString::from_vec(Bash::quote(string))
+}
+
+pub fn path_from_url(url: &Url) -> Result<PathBuf, String> {
+    if url.scheme() != "file" {
+        return Err(format!(
+            "Only 'file' schemes are supported, '{url}''s scheme is {}",
+            url.scheme()
+        ));
+    }
+
+    url.to_file_path()
+        .map_err(|_| format!("'{url}' has no path"))
+}
+
+// A command-line argument may be a path or a file:// URI (as in desktop entries' %F and %U).
+// Relative paths are made absolute.
+pub fn path_from_arg(arg: &OsStr) -> Result<PathBuf, String> {
+    if let Some(uri) = arg.to_str().filter(|x| x.starts_with("file://")) {
+        let url = Url::parse(uri).map_err(|e| format!("Invalid URI '{uri}': {e}"))?;
+        return path_from_url(&url);
+    }
+
+    let path = PathBuf::from(arg);
+    if path.is_absolute() {
+        return Ok(path);
+    }
+
+    let cwd =
+        std::env::current_dir().map_err(|e| format!("Couldn't get working directory: {e}"))?;
+    Ok(cwd.join(path))
+}
+
+// The scripts to run, one per window. Everything is run from the first file's directory.
+pub fn scripts(config: &Config, paths: &[PathBuf]) -> Vec<OsString> {
+    if paths.is_empty() {
+        return vec![];
+    }
+
+    let mut cmd = OsString::new();
+    if let Some(parent) = paths[0].parent() {
+        let parent = bash_quote(parent);
+        cmd = os_cat!("cd ", &parent, "; ");
+    }
+
+    match config.group_open_by {
+        GroupBy::All => {
+            cmd.push(&config.command);
+            for path in paths {
+                let path = bash_quote(path);
+                os_extend!(cmd, " ", &path);
+            }
+            vec![cmd]
+        }
+        GroupBy::None => paths
+            .iter()
+            .map(|path| {
+                let path = bash_quote(path);
+                os_cat!(&cmd, &config.command, " ", &path)
+            })
+            .collect(),
+    }
+}
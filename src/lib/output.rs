@@ -0,0 +1,341 @@
+use crate::bailf;
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use tempdir::TempDir;
+
+// Where the Generator writes a shim's files before they're moved into place. Paths are relative
+// to the target's root.
+
+pub trait OutputTarget {
+    // The parent must already exist, and the directory must not.
+    fn create_dir(&mut self, path: &Path) -> Result<(), String>;
+    // Creates or replaces the file, the parent must already exist.
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<(), String>;
+    // Copies src, from the real filesystem, and makes it executable.
+    fn copy_executable(&mut self, src: &Path, path: &Path) -> Result<(), String>;
+    fn set_mode(&mut self, path: &Path, mode: u32) -> Result<(), String>;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// A temporary directory, from which Generator::save() renames everything into place.
+pub struct TempDirOutput {
+    tmp_dir: TempDir,
+}
+
+impl TempDirOutput {
+    pub fn new() -> Result<TempDirOutput, String> {
+        let tmp_dir = TempDir::new("echidna-lib")
+            .map_err(|e| format!("Error creating temporary directory: {e}"))?;
+        Ok(TempDirOutput { tmp_dir })
+    }
+
+    pub fn root(&self) -> &Path {
+        self.tmp_dir.path()
+    }
+}
+
+impl OutputTarget for TempDirOutput {
+    fn create_dir(&mut self, path: &Path) -> Result<(), String> {
+        fs::create_dir(self.root().join(path)).map_err(|e| {
+            format!(
+                "Error creating directory '{}' in temp dir {}: {e}",
+                path.display(),
+                self.root().display()
+            )
+        })
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<(), String> {
+        fs::write(self.root().join(path), contents).map_err(|e| {
+            format!(
+                "Error writing '{}' to temporary directory '{}': {e}",
+                path.display(),
+                self.root().display()
+            )
+        })
+    }
+
+    fn copy_executable(&mut self, src: &Path, path: &Path) -> Result<(), String> {
+        fs::copy(src, self.root().join(path)).map_err(|e| {
+            format!(
+                "Error copying '{}' to temporary directory '{}': {e}",
+                src.display(),
+                self.root().display()
+            )
+        })?;
+        self.set_mode(path, 0o755)
+    }
+
+    fn set_mode(&mut self, path: &Path, mode: u32) -> Result<(), String> {
+        let full_path = self.root().join(path);
+        fs::set_permissions(&full_path, fs::Permissions::from_mode(mode)).map_err(|e| {
+            format!(
+                "Error setting mode of '{}' to {mode:o}: {e}",
+                full_path.display()
+            )
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Contents {
+    Bytes(Vec<u8>),
+    // Copied executables aren't read, they're big and only their source matters.
+    CopyOf(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Dir,
+    File { contents: Contents, mode: u32 },
+}
+
+// An in-memory tree, for previewing what would be generated. Follows the filesystem's rules for
+// what may be created where, so it fails wherever TempDirOutput would.
+#[derive(Debug, Default)]
+pub struct MemTree {
+    nodes: BTreeMap<PathBuf, Node>,
+}
+
+// Files at least this big aren't included in snapshots, even if they're text.
+const MAX_SNAPSHOT_TEXT: usize = 64 * 1024;
+
+// Set to update snapshot files instead of checking against them.
+pub const UPDATE_SNAPSHOTS_KEY: &str = "ECHIDNA_UPDATE_SNAPSHOTS";
+
+impl MemTree {
+    pub fn new() -> MemTree {
+        MemTree::default()
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Node> {
+        self.nodes.get(path)
+    }
+
+    // The contents of a file written with write_file().
+    pub fn bytes(&self, path: &Path) -> Option<&[u8]> {
+        match self.nodes.get(path) {
+            Some(Node::File {
+                contents: Contents::Bytes(x),
+                ..
+            }) => Some(x),
+            _ => None,
+        }
+    }
+
+    // Everything, in path order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &Node)> {
+        self.nodes.iter().map(|(k, v)| (k.as_path(), v))
+    }
+
+    fn check_parent(&self, path: &Path) -> Result<(), String> {
+        match path.parent() {
+            Some(parent) if parent != Path::new("") => match self.nodes.get(parent) {
+                Some(Node::Dir) => Ok(()),
+                Some(Node::File { .. }) => bailf!("'{}' is not a directory", parent.display()),
+                None => bailf!("Directory '{}' doesn't exist", parent.display()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn check_file(&self, path: &Path) -> Result<(), String> {
+        self.check_parent(path)?;
+        if let Some(Node::Dir) = self.nodes.get(path) {
+            bailf!("'{}' is a directory", path.display());
+        }
+        Ok(())
+    }
+
+    // A line for each entry under prefix (or prefix itself, if it's a file), in path order,
+    // with paths relative to prefix joined to base. Text files are included in full, indented;
+    // other files are summarized with a hash of their contents.
+    pub fn render(&self, prefix: &Path, base: &Path) -> String {
+        self.render_redacted(prefix, base, &[])
+    }
+
+    // Like render(), with each (from, to) in redactions replaced in text files. Their sizes are of
+    // the redacted text.
+    fn render_redacted(&self, prefix: &Path, base: &Path, redactions: &[(&str, &str)]) -> String {
+        let mut out = String::new();
+        for (path, node) in self.iter().filter(|(x, _)| x.starts_with(prefix)) {
+            let relative = path.strip_prefix(prefix).unwrap();
+            let shown = if relative == Path::new("") {
+                base.to_owned()
+            } else {
+                base.join(relative)
+            };
+
+            match node {
+                Node::Dir => {
+                    let _ = writeln!(out, "{}/", shown.display());
+                }
+                Node::File {
+                    contents: Contents::CopyOf(src),
+                    mode,
+                } => {
+                    let _ = writeln!(
+                        out,
+                        "{}  {mode:04o}  copy of {}",
+                        shown.display(),
+                        src.display()
+                    );
+                }
+                Node::File {
+                    contents: Contents::Bytes(bytes),
+                    mode,
+                } => match std::str::from_utf8(bytes) {
+                    Ok(text) if bytes.len() < MAX_SNAPSHOT_TEXT && !text.contains('\0') => {
+                        let text = redactions
+                            .iter()
+                            .fold(text.to_owned(), |text, (from, to)| text.replace(from, to));
+                        let _ =
+                            writeln!(out, "{}  {mode:04o}  {} bytes", shown.display(), text.len());
+                        for line in text.lines() {
+                            match line {
+                                "" => out.push('\n'),
+                                line => {
+                                    let _ = writeln!(out, "    {line}");
+                                }
+                            }
+                        }
+                    }
+                    _ => {
+                        let _ = writeln!(
+                            out,
+                            "{}  {mode:04o}  {} bytes  fnv1a:{:016x}",
+                            shown.display(),
+                            bytes.len(),
+                            fnv1a(bytes)
+                        );
+                    }
+                },
+            }
+        }
+        out
+    }
+
+    // The whole tree, deterministic for the same inputs so it can be compared with a stored
+    // snapshot. Echidna's version is redacted, so it doesn't change with every release, as is
+    // each (from, to) in redactions, e.g. a directory that depends on the environment.
+    pub fn snapshot(&self, redactions: &[(&str, &str)]) -> String {
+        let version = [(env!("CARGO_PKG_VERSION"), "<version>")];
+        let redactions: Vec<_> = version.iter().chain(redactions).copied().collect();
+        self.render_redacted(Path::new(""), Path::new(""), &redactions)
+    }
+
+    // Compares snapshot() with the contents of snapshot_file. With UPDATE_SNAPSHOTS_KEY set, it's
+    // written instead, a missing snapshot is an error otherwise. On a mismatch, the actual
+    // snapshot is written next to it with a .new extension.
+    pub fn check_snapshot(
+        &self,
+        snapshot_file: &Path,
+        redactions: &[(&str, &str)],
+    ) -> Result<(), String> {
+        let actual = self.snapshot(redactions);
+
+        if std::env::var_os(UPDATE_SNAPSHOTS_KEY).is_some_and(|x| !x.is_empty()) {
+            return fs::write(snapshot_file, actual)
+                .map_err(|e| format!("Error writing snapshot '{}': {e}", snapshot_file.display()));
+        }
+        let expected = match fs::read_to_string(snapshot_file) {
+            Ok(x) => x,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => bailf!(
+                "Snapshot '{}' doesn't exist, run with {UPDATE_SNAPSHOTS_KEY}=1 to write it",
+                snapshot_file.display()
+            ),
+            Err(e) => bailf!("Error reading snapshot '{}': {e}", snapshot_file.display()),
+        };
+
+        if expected == actual {
+            return Ok(());
+        }
+
+        let new_file = snapshot_file.with_extension("new");
+        let _ = fs::write(&new_file, &actual); // Only a convenience.
+
+        let (line, expected_line, actual_line) = expected
+            .lines()
+            .map(Some)
+            .chain(std::iter::repeat(None))
+            .zip(actual.lines().map(Some).chain(std::iter::repeat(None)))
+            .enumerate()
+            .find(|(_, (e, a))| e != a)
+            .map(|(i, (e, a))| (i + 1, e.unwrap_or("<end>"), a.unwrap_or("<end>")))
+            .unwrap_or((0, "", ""));
+
+        bailf!(
+            "Snapshot '{}' doesn't match (actual written to '{}'), first difference on line \
+            {line}:\n  expected: {expected_line}\n  actual:   {actual_line}",
+            snapshot_file.display(),
+            new_file.display()
+        )
+    }
+}
+
+impl OutputTarget for MemTree {
+    fn create_dir(&mut self, path: &Path) -> Result<(), String> {
+        self.check_parent(path)?;
+        if self.nodes.contains_key(path) {
+            bailf!("'{}' already exists", path.display());
+        }
+        self.nodes.insert(path.to_owned(), Node::Dir);
+        Ok(())
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<(), String> {
+        self.check_file(path)?;
+        self.nodes.insert(
+            path.to_owned(),
+            Node::File {
+                contents: Contents::Bytes(contents.to_owned()),
+                mode: 0o644,
+            },
+        );
+        Ok(())
+    }
+
+    fn copy_executable(&mut self, src: &Path, path: &Path) -> Result<(), String> {
+        self.check_file(path)?;
+        if !src.is_file() {
+            bailf!("Error copying '{}': not a file", src.display());
+        }
+        self.nodes.insert(
+            path.to_owned(),
+            Node::File {
+                contents: Contents::CopyOf(src.to_owned()),
+                mode: 0o755,
+            },
+        );
+        Ok(())
+    }
+
+    fn set_mode(&mut self, path: &Path, new_mode: u32) -> Result<(), String> {
+        match self.nodes.get_mut(path) {
+            Some(Node::File { mode, .. }) => {
+                *mode = new_mode;
+                Ok(())
+            }
+            Some(Node::Dir) => Ok(()), // Directories are always shown the same.
+            None => bailf!("'{}' doesn't exist", path.display()),
+        }
+    }
+}
+
+// Not for security, just a stable fingerprint for snapshots without another dependency.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
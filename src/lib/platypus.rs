@@ -0,0 +1,356 @@
+use crate::bailf;
+use crate::config::GroupBy;
+use crate::generate::{DocTypeGroup, Target};
+use crate::open::bash_quote;
+use crate::recipe::Recipe;
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use plist::{Dictionary, Value};
+
+// Importing Platypus (https://sveinbjorn.org/platypus) apps and their .platypus profiles, which
+// wrap a script much like a shim wraps a command. Only the script, the app's identity and the
+// files it accepts carry over; the script runs in a terminal instead of Platypus' interfaces.
+
+pub struct Import {
+    pub recipe: Recipe,
+    // Settings that couldn't be carried over.
+    pub skipped: Vec<String>,
+}
+
+fn read_dict(path: &Path) -> Result<Dictionary, String> {
+    let value =
+        Value::from_file(path).map_err(|e| format!("Error reading '{}': {e}", path.display()))?;
+    match value {
+        Value::Dictionary(x) => Ok(x),
+        _ => bailf!("'{}' isn't a dictionary", path.display()),
+    }
+}
+
+fn string(dict: &Dictionary, key: &str) -> Option<String> {
+    dict.get(key)
+        .and_then(|x| x.as_string())
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty())
+}
+
+fn strings(dict: &Dictionary, key: &str) -> Vec<String> {
+    dict.get(key)
+        .and_then(|x| x.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|x| x.as_string())
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+fn boolean(dict: &Dictionary, key: &str) -> Option<bool> {
+    dict.get(key).and_then(|x| x.as_boolean())
+}
+
+// The interpreter (if any), its arguments, the script and its arguments, as one command.
+fn command(settings: &Dictionary, script: &Path) -> String {
+    let mut words = vec![];
+    // Older profiles call it Interpreter.
+    if let Some(interpreter) =
+        string(settings, "InterpreterPath").or_else(|| string(settings, "Interpreter"))
+    {
+        words.push(interpreter);
+        words.extend(strings(settings, "InterpreterArgs"));
+    }
+    words.push(script.to_string_lossy().into_owned());
+    words.extend(strings(settings, "ScriptArgs"));
+
+    words
+        .iter()
+        .map(|x| bash_quote(x).to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// What Platypus does that a shim doesn't, going by settings shared by profiles and apps'
+// AppSettings.plist.
+fn skipped_settings(settings: &Dictionary) -> Vec<String> {
+    let mut skipped = vec![];
+    if let Some(interface) = string(settings, "InterfaceType") {
+        if interface != "Text Window" {
+            skipped.push(format!(
+                "Interface type '{interface}': the script runs in a terminal window instead"
+            ));
+        }
+    }
+
+    let unsupported = [
+        ("Authentication", "Running with administrator privileges"),
+        ("AcceptsText", "Accepting dropped text"),
+        ("PromptForFileOnLaunch", "Prompting for a file on launch"),
+        ("SendNotifications", "Sending notifications"),
+    ];
+    for (key, what) in unsupported {
+        if boolean(settings, key) == Some(true) {
+            skipped.push(format!("{what} ({key})"));
+        }
+    }
+
+    skipped
+}
+
+// The documents the app accepted dropped on it: "*" is any file, as public.data.
+fn doc_types(utis: Vec<String>, suffixes: Vec<String>) -> Vec<DocTypeGroup> {
+    let any = suffixes.iter().any(|x| x == "*");
+    let mut group = DocTypeGroup {
+        utis,
+        exts: suffixes.into_iter().filter(|x| x != "*").collect(),
+        ..Default::default()
+    };
+    if any
+        && !group
+            .utis
+            .iter()
+            .any(|x| x == "public.data" || x == "public.item")
+    {
+        group.utis.push("public.data".to_owned());
+    }
+
+    if group.utis.is_empty() && group.exts.is_empty() {
+        return vec![];
+    }
+    vec![group]
+}
+
+fn import_profile(path: &Path) -> Result<Import, String> {
+    let profile = read_dict(path)?;
+    let mut skipped = skipped_settings(&profile);
+
+    let Some(script) = string(&profile, "ScriptPath") else {
+        bailf!(
+            "'{}' has no ScriptPath, is it a Platypus profile?",
+            path.display()
+        );
+    };
+    let name = string(&profile, "Name")
+        .or_else(|| path.file_stem().map(|x| x.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+
+    let droppable = boolean(&profile, "Droppable").unwrap_or(false)
+        || boolean(&profile, "AcceptsFiles").unwrap_or(false);
+    let docs_groups = if droppable {
+        doc_types(
+            strings(&profile, "UniformTypes"),
+            strings(&profile, "Suffixes"),
+        )
+    } else {
+        vec![]
+    };
+    if docs_groups.is_empty() {
+        skipped.push("Not accepting files: the shim opens text files".to_owned());
+    }
+
+    if !Path::new(&script).is_file() {
+        skipped.push(missing_script(Path::new(&script)));
+    }
+
+    let bundled = strings(&profile, "BundledFiles");
+    if !bundled.is_empty() {
+        skipped.push(format!("Bundled files: {}", bundled.join(", ")));
+    }
+
+    let mut recipe = Recipe {
+        name: Some(name),
+        command: command(&profile, Path::new(&script)),
+        group_open_by: GroupBy::All,
+        bundle_id: string(&profile, "Identifier"),
+        icon: string(&profile, "IconPath").map(PathBuf::from),
+        docs_groups,
+        ..Default::default()
+    };
+    set_extra_keys(&mut recipe, &profile, string(&profile, "Version"));
+
+    Ok(Import { recipe, skipped })
+}
+
+fn import_app(path: &Path) -> Result<Import, String> {
+    let resources = path.join("Contents/Resources");
+    let settings_path = resources.join("AppSettings.plist");
+    if !settings_path.is_file() {
+        bailf!("'{}' isn't a Platypus app", path.display());
+    }
+    let settings = read_dict(&settings_path)?;
+    let info = read_dict(&path.join("Contents/Info.plist"))?;
+    let mut skipped = skipped_settings(&settings);
+
+    let script = resources.join("script");
+    if script.is_file() {
+        // The shim can't carry the script along, so it runs it where it is.
+        skipped.push(format!(
+            "Bundling the script: the shim runs '{}', so keep the app, or move the script and change the command with `edit`",
+            script.display()
+        ));
+    } else {
+        skipped.push(missing_script(&script));
+    }
+
+    let name = string(&info, "CFBundleName")
+        .or_else(|| path.file_stem().map(|x| x.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+
+    let (mut utis, mut suffixes) = (vec![], vec![]);
+    for doc_type in info
+        .get("CFBundleDocumentTypes")
+        .and_then(|x| x.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|x| x.as_dictionary())
+    {
+        utis.extend(strings(doc_type, "LSItemContentTypes"));
+        suffixes.extend(strings(doc_type, "CFBundleTypeExtensions"));
+    }
+    let docs_groups = doc_types(utis, suffixes);
+    if docs_groups.is_empty() {
+        skipped.push("Not accepting files: the shim opens text files".to_owned());
+    }
+
+    let icon = string(&info, "CFBundleIconFile").map(|x| {
+        let icon = resources.join(x);
+        if icon.extension().is_some() {
+            icon
+        } else {
+            icon.with_extension("icns")
+        }
+    });
+
+    // Anything Platypus doesn't put there itself was bundled with the script.
+    let known = ["script", "AppSettings.plist", "Credits.rtf", "Credits.html"];
+    let mut bundled: Vec<String> = fs::read_dir(&resources)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|x| x.path())
+        .filter(|x| Some(x) != icon.as_ref())
+        .filter(|x| !matches!(x.extension().and_then(OsStr::to_str), Some("nib" | "lproj")))
+        .filter_map(|x| x.file_name().map(|x| x.to_string_lossy().into_owned()))
+        .filter(|x| !known.contains(&x.as_str()))
+        .collect();
+    bundled.sort();
+    if !bundled.is_empty() {
+        skipped.push(format!(
+            "Bundled files (still in the app): {}",
+            bundled.join(", ")
+        ));
+    }
+
+    let mut recipe = Recipe {
+        name: Some(name),
+        command: command(&settings, &script),
+        group_open_by: GroupBy::All,
+        bundle_id: string(&info, "CFBundleIdentifier"),
+        icon: icon.filter(|x| x.is_file()),
+        docs_groups,
+        ..Default::default()
+    };
+    set_extra_keys(
+        &mut recipe,
+        &settings,
+        string(&info, "CFBundleShortVersionString"),
+    );
+
+    Ok(Import { recipe, skipped })
+}
+
+fn missing_script(script: &Path) -> String {
+    format!(
+        "The script: '{}' doesn't exist, so the shim won't run until it does or its command is changed with `edit`",
+        script.display()
+    )
+}
+
+// The version, and running without a Dock icon, become Info.plist keys.
+fn set_extra_keys(recipe: &mut Recipe, settings: &Dictionary, version: Option<String>) {
+    let mut keys = serde_json::Map::new();
+    if let Some(version) = version {
+        keys.insert("CFBundleShortVersionString".to_owned(), version.into());
+    }
+    if boolean(settings, "RunInBackground") == Some(true) {
+        keys.insert("LSUIElement".to_owned(), true.into());
+    }
+    if !keys.is_empty() {
+        recipe.extra_info_plist = Some(keys.into());
+    }
+}
+
+// Linux shims have no bundle identifier or Info.plist.
+fn drop_macos_keys(import: &mut Import) {
+    if let Some(bundle_id) = import.recipe.bundle_id.take() {
+        import.skipped.push(format!(
+            "Bundle identifier '{bundle_id}': Linux shims have none"
+        ));
+    }
+    let keys = import.recipe.extra_info_plist.take();
+    let keys = keys.as_ref().and_then(|x| x.as_object());
+    if let Some(version) = keys
+        .and_then(|x| x.get("CFBundleShortVersionString"))
+        .and_then(|x| x.as_str())
+    {
+        import
+            .skipped
+            .push(format!("Version '{version}': Linux shims have none"));
+    }
+    if keys.is_some_and(|x| x.contains_key("LSUIElement")) {
+        import
+            .skipped
+            .push("Running without a Dock icon (RunInBackground)".to_owned());
+    }
+}
+
+// Maps a Platypus app, or a .platypus profile, onto a recipe for an equivalent shim for target, and
+// reports what couldn't be.
+pub fn import(path: &Path, target: Target) -> Result<Import, String> {
+    let mut import = if path.is_dir() {
+        // The shim runs the app's script by its absolute path.
+        let path = fs::canonicalize(path)
+            .map_err(|e| format!("Error resolving '{}': {e}", path.display()))?;
+        import_app(&path)?
+    } else {
+        import_profile(path)?
+    };
+    if import.recipe.name().is_empty() {
+        bailf!("'{}' has no name", path.display());
+    }
+    import.recipe.target = target;
+    if target == Target::Linux {
+        drop_macos_keys(&mut import);
+    }
+    Ok(import)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn import_reports_what_linux_drops() {
+        let dir = TempDir::new("echidna-test").unwrap();
+        let profile = dir.path().join("Tool.platypus");
+        let mut dict = Dictionary::new();
+        dict.insert("Name".to_owned(), "Tool".into());
+        dict.insert("ScriptPath".to_owned(), "/nonexistent/tool.sh".into());
+        dict.insert("Identifier".to_owned(), "org.example.Tool".into());
+        dict.insert("Version".to_owned(), "1.2".into());
+        plist::to_file_xml(&profile, &Value::Dictionary(dict)).unwrap();
+
+        let macos = import(&profile, Target::MacOs).unwrap();
+        assert_eq!(macos.recipe.bundle_id.as_deref(), Some("org.example.Tool"));
+        assert!(macos.skipped.iter().any(|x| x.contains("tool.sh")));
+        assert!(!macos.skipped.iter().any(|x| x.contains("org.example.Tool")));
+
+        let linux = import(&profile, Target::Linux).unwrap();
+        assert_eq!(linux.recipe.bundle_id, None);
+        assert_eq!(linux.recipe.extra_info_plist, None);
+        assert!(linux.skipped.iter().any(|x| x.contains("org.example.Tool")));
+        assert!(linux.skipped.iter().any(|x| x.contains("'1.2'")));
+    }
+}
@@ -0,0 +1,638 @@
+use crate::config::{Config, GroupBy, TerminalApp};
+use crate::generate::{BundleIdOptions, DocTypeGroup, DocTypes, Generator, Rank, Role, Target};
+use crate::icon_composer::{Color, ShimIcon};
+use crate::shim::Shim;
+use crate::type_decl::TypeDecl;
+use crate::{bail, bailf, info_plist, registry, term};
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use plist::Dictionary;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+// A recipe is a shim described in a file, TOML or JSON, with the same settings as echidna-cli's
+// arguments, so shims can be kept in version control and rebuilt anywhere. A manifest lists
+// several, with shared defaults, and sync() makes the installed shims match it.
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Recipe {
+    // The app's name. [default: the recipe's file name]
+    pub name: Option<String>,
+    pub command: String,
+    pub target: Target,
+    pub group_open_by: GroupBy,
+    pub terminal: Option<String>,
+    pub generic_terminal: Option<String>,
+    pub shim_dry_run: Option<PathBuf>,
+    pub bundle_id: Option<String>,
+    pub bundle_id_prefix: Option<String>,
+    pub bundle_id_seed: Option<String>,
+    pub backup: bool,
+    pub make_default: bool,
+    pub icon: Option<PathBuf>,
+    pub icon_label: Option<String>,
+    pub icon_tint: Option<Color>,
+    pub icon_badge: Option<PathBuf>,
+    // Inline, rather than in a file as for the CLI.
+    pub declare_types: Vec<TypeDecl>,
+    // A table of keys, or the path of a file as for the CLI.
+    pub extra_info_plist: Option<Value>,
+    pub docs_text_files: bool,
+    pub docs_all_docs: bool,
+    pub docs_utis: Vec<String>,
+    pub docs_exts: Vec<String>,
+    // Inline, rather than in a file as for the CLI.
+    pub docs_groups: Vec<DocTypeGroup>,
+    pub docs_role: Option<Role>,
+    pub docs_rank: Option<Rank>,
+}
+
+// Reads a JSON file, or for any other extension, a TOML one.
+fn read_value(path: &Path) -> Result<Value, String> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("Error reading '{}': {e}", path.display()))?;
+    let value = if path.extension() == Some(OsStr::new("json")) {
+        serde_json::from_str(&contents).map_err(|e| e.to_string())
+    } else {
+        toml::from_str(&contents).map_err(|e| e.to_string())
+    };
+    value.map_err(|e| format!("Error parsing '{}': {e}", path.display()))
+}
+
+// Relative paths in a file are relative to the file, and may start with ~ for the home directory.
+fn resolve_path(path: &Path, base_dir: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), home::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => base_dir.join(path),
+    }
+}
+
+fn base_dir(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new(""))
+}
+
+impl Recipe {
+    pub fn load(path: &Path) -> Result<Recipe, String> {
+        let mut recipe = Recipe::from_value(read_value(path)?, base_dir(path))
+            .map_err(|e| format!("Error in '{}': {e}", path.display()))?;
+        if recipe.name.is_none() {
+            recipe.name = path.file_stem().map(|x| x.to_string_lossy().into_owned());
+        }
+        Ok(recipe)
+    }
+
+    fn from_value(value: Value, base_dir: &Path) -> Result<Recipe, String> {
+        let recipe: Recipe = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        recipe.resolved(base_dir)
+    }
+
+    // Checks what the CLI's argument parser would, and resolves paths against base_dir. For
+    // recipes made in code, paths can be absolute, and base_dir empty.
+    pub fn resolved(mut self, base_dir: &Path) -> Result<Recipe, String> {
+        if self.command.trim().is_empty() {
+            bail!("No command");
+        }
+        if let Some(name) = &self.name {
+            if name.contains('/') {
+                bailf!("Name '{name}' must not contain '/'");
+            }
+        }
+
+        if self.terminal.is_some() && self.generic_terminal.is_some() {
+            bail!("Only one of terminal and generic_terminal may be set");
+        }
+        if let Some(term) = &self.terminal {
+            if !term::is_supported_for(self.target, term) {
+                bailf!(
+                    "Terminal {term} is not supported for {} (supported terminals: {}), but you can try it with generic_terminal",
+                    self.target,
+                    term::supported_terminals_string_for(self.target)
+                );
+            }
+        }
+
+        if self.bundle_id.is_some()
+            && (self.bundle_id_prefix.is_some() || self.bundle_id_seed.is_some())
+        {
+            bail!("bundle_id can't be set along with bundle_id_prefix or bundle_id_seed");
+        }
+
+        let docs = [
+            self.docs_text_files,
+            self.docs_all_docs,
+            !self.docs_utis.is_empty(),
+            !self.docs_exts.is_empty(),
+            !self.docs_groups.is_empty(),
+        ];
+        if docs.iter().filter(|x| **x).count() > 1 {
+            bail!("Only one of docs_text_files, docs_all_docs, docs_utis, docs_exts and docs_groups may be set");
+        }
+        if !self.docs_groups.is_empty() && (self.docs_role.is_some() || self.docs_rank.is_some()) {
+            bail!("docs_role and docs_rank don't apply to docs_groups, set them in each group");
+        }
+
+        match &mut self.extra_info_plist {
+            None | Some(Value::Object(_)) => (),
+            Some(Value::String(path)) => {
+                *path = resolve_path(Path::new(path), base_dir)
+                    .to_string_lossy()
+                    .into_owned()
+            }
+            Some(_) => bail!("extra_info_plist must be a table of keys, or a file"),
+        }
+
+        let resolve = |path: &mut Option<PathBuf>| {
+            if let Some(path) = path {
+                *path = resolve_path(path, base_dir);
+            }
+        };
+        resolve(&mut self.shim_dry_run);
+        resolve(&mut self.icon);
+        resolve(&mut self.icon_badge);
+        for group in &mut self.docs_groups {
+            resolve(&mut group.icon);
+        }
+        for decl in &mut self.declare_types {
+            resolve(&mut decl.icon);
+        }
+
+        self.docs_groups = mem::take(&mut self.docs_groups)
+            .into_iter()
+            .map(|x| x.normalized(None))
+            .collect::<Result<_, _>>()?;
+        self.declare_types = mem::take(&mut self.declare_types)
+            .into_iter()
+            .map(|x| x.normalized(None))
+            .collect::<Result<_, _>>()?;
+
+        Ok(self)
+    }
+
+    // A recipe that regenerates an existing shim. Extra Info.plist keys can't be told apart from
+    // the generated ones, so aren't included.
+    pub fn from_shim(shim: &Shim) -> Recipe {
+        let (terminal, generic_terminal) = match &shim.config.terminal {
+            TerminalApp::Supported(x) => (Some(x.clone()), None),
+            TerminalApp::Generic(x) => (None, Some(x.clone())),
+        };
+        Recipe {
+            name: Some(shim.name.clone()),
+            command: shim.config.command.clone(),
+            target: shim.target,
+            group_open_by: shim.config.group_open_by,
+            terminal,
+            generic_terminal,
+            shim_dry_run: shim.config.dry_run.clone(),
+            bundle_id: shim.bundle_id.clone(),
+            icon: shim.icon.clone(),
+            declare_types: shim.type_decls.clone(),
+            docs_groups: shim.doc_types.0.clone(),
+            ..Default::default()
+        }
+    }
+
+    // Makes the paths under dir relative to it, e.g. for a recipe saved in dir.
+    pub fn relative_to(&mut self, dir: &Path) {
+        let relative = |path: &mut Option<PathBuf>| {
+            if let Some(path) = path {
+                if let Ok(x) = path.strip_prefix(dir) {
+                    *path = x.to_owned();
+                }
+            }
+        };
+        relative(&mut self.icon);
+        relative(&mut self.icon_badge);
+        for group in &mut self.docs_groups {
+            relative(&mut group.icon);
+        }
+        for decl in &mut self.declare_types {
+            relative(&mut decl.icon);
+        }
+    }
+
+    // The recipe as Recipe::load() reads it, leaving out what's left at its default.
+    pub fn to_toml(&self) -> Result<String, String> {
+        let err = |e: String| format!("Error serializing recipe: {e}");
+        let Value::Object(mut keys) = serde_json::to_value(self).map_err(|e| err(e.to_string()))?
+        else {
+            return Err(err("not a table".to_owned()));
+        };
+        // TOML has no null.
+        keys.retain(|_, x| {
+            !(x.is_null() || *x == Value::Bool(false) || x.as_array().is_some_and(|x| x.is_empty()))
+        });
+        toml::to_string(&keys).map_err(|e| err(e.to_string()))
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or_default()
+    }
+
+    pub fn config(&self) -> Config {
+        let terminal = match (&self.terminal, &self.generic_terminal) {
+            (_, Some(term)) => TerminalApp::Generic(term.clone()),
+            (Some(term), None) => TerminalApp::Supported(term.clone()),
+            (None, None) => {
+                TerminalApp::Supported(term::default_terminal_for(self.target).to_owned())
+            }
+        };
+        Config {
+            command: self.command.clone(),
+            group_open_by: self.group_open_by,
+            terminal,
+            dry_run: self.shim_dry_run.clone(),
+            icon: None,
+        }
+    }
+
+    pub fn doc_types(&self) -> DocTypes {
+        if !self.docs_groups.is_empty() {
+            return DocTypes(self.docs_groups.clone());
+        }
+
+        let mut doc_types = if self.docs_all_docs {
+            DocTypes::all_docs()
+        } else if !self.docs_utis.is_empty() {
+            DocTypes::utis(&self.docs_utis.join(","))
+        } else if !self.docs_exts.is_empty() {
+            DocTypes::exts(&self.docs_exts.join(","))
+        } else {
+            DocTypes::text_files()
+        };
+        for group in &mut doc_types.0 {
+            group.role = self.docs_role.unwrap_or_default();
+            group.rank = self.docs_rank;
+        }
+        doc_types
+    }
+
+    fn extra_info_plist(&self) -> Result<Option<Dictionary>, String> {
+        match &self.extra_info_plist {
+            Some(Value::String(path)) => info_plist::load_extra(Path::new(path)).map(Some),
+            Some(keys) => {
+                let json = serde_json::to_vec(keys)
+                    .map_err(|e| format!("Error serializing extra_info_plist: {e}"))?;
+                info_plist::parse_extra(&json)
+                    .map(Some)
+                    .map_err(|e| format!("Error in extra_info_plist: {e}"))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Generates the shim, to be saved in dest. Linux shims ignore dest, like the CLI's out_path.
+    pub fn gen(&self, dest: &Path, shim_bin: &Path) -> Result<Generator, String> {
+        let bundle_id = BundleIdOptions {
+            id: self.bundle_id.clone(),
+            prefix: self.bundle_id_prefix.clone(),
+            seed: self.bundle_id_seed.clone(),
+        };
+        let icon = ShimIcon {
+            path: self.icon.clone(),
+            label: self.icon_label.clone(),
+            tint: self.icon_tint,
+            badge: self.icon_badge.clone(),
+        };
+
+        Generator::gen(
+            &self.config(),
+            &self.doc_types(),
+            shim_bin,
+            &bundle_id,
+            &icon,
+            dest.join(self.name()),
+            self.target,
+            &self.declare_types,
+            self.extra_info_plist()?.as_ref(),
+        )
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Several recipes, e.g.
+//     [defaults]
+//     terminal = "kitty"
+//
+//     [[shims]]
+//     name = "Vim"
+//     command = "vim"
+// or the same as JSON, {"defaults": {...}, "shims": [{...}]}. Each shim's keys replace the
+// defaults', and each must have a name.
+pub struct Manifest {
+    // Canonical, as the registry records it.
+    pub path: PathBuf,
+    pub recipes: Vec<Recipe>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Manifest, String> {
+        let err = |e: &str| format!("Error in '{}': {e}", path.display());
+
+        let Value::Object(mut value) = read_value(path)? else {
+            return Err(err("Expected a table of defaults and shims"));
+        };
+        let defaults = match value.remove("defaults") {
+            Some(Value::Object(x)) => x,
+            None => Map::new(),
+            Some(_) => return Err(err("defaults must be a table")),
+        };
+        let shims = match value.remove("shims") {
+            Some(Value::Array(x)) => x,
+            None => vec![],
+            Some(_) => return Err(err("shims must be an array of tables")),
+        };
+        if let Some(key) = value.keys().next() {
+            return Err(err(&format!(
+                "Unknown key '{key}', expected defaults or shims"
+            )));
+        }
+
+        let mut recipes: Vec<Recipe> = vec![];
+        for (i, shim) in shims.into_iter().enumerate() {
+            let Value::Object(shim) = shim else {
+                return Err(err(&format!("Shim {} isn't a table", i + 1)));
+            };
+            let mut merged = defaults.clone();
+            merged.extend(shim);
+
+            let Some(name) = merged.get("name").and_then(|x| x.as_str()) else {
+                return Err(err(&format!("Shim {} has no name", i + 1)));
+            };
+            let name = name.to_owned();
+            if recipes.iter().any(|x| x.name() == name) {
+                return Err(err(&format!("More than one shim is named '{name}'")));
+            }
+
+            let recipe = Recipe::from_value(Value::Object(merged), base_dir(path))
+                .map_err(|e| err(&format!("Shim '{name}': {e}")))?;
+            recipes.push(recipe);
+        }
+
+        let path = fs::canonicalize(path)
+            .map_err(|e| format!("Error resolving '{}': {e}", path.display()))?;
+        Ok(Manifest { path, recipes })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Created => write!(f, "created"),
+            Outcome::Updated => write!(f, "updated"),
+            Outcome::Unchanged => write!(f, "unchanged"),
+        }
+    }
+}
+
+pub struct Built {
+    pub name: String,
+    // The .app bundle, or for Linux, the desktop entry.
+    pub path: PathBuf,
+    pub outcome: Outcome,
+    pub warnings: Vec<String>,
+}
+
+// Builds recipe, read from source (a recipe or manifest, canonicalized), in dest, only replacing
+// an existing shim if the result is actually different (the build number aside). Without force,
+// only a shim the registry records as made from source is replaced. With dry_run, nothing is
+// saved, only the outcome reported.
+pub fn build(
+    recipe: &Recipe,
+    source: &Path,
+    dest: &Path,
+    shim_bin: &Path,
+    force: bool,
+    dry_run: bool,
+) -> Result<Built, String> {
+    let mut gen = recipe.gen(dest, shim_bin)?;
+    let path = gen.final_bundle_path().to_owned();
+
+    let exists = fs::symlink_metadata(&path).is_ok();
+    if exists && !force && !registry::made_from(source)?.iter().any(|x| x.path == path) {
+        bailf!(
+            "'{}' already exists and wasn't built from '{}'. Run with [-f|--force] to overwrite.",
+            path.display(),
+            source.display()
+        );
+    }
+    let outcome = if !exists {
+        Outcome::Created
+    } else if gen.is_unchanged() {
+        Outcome::Unchanged
+    } else {
+        Outcome::Updated
+    };
+
+    if !dry_run {
+        if outcome == Outcome::Unchanged {
+            // It may have been made before there was a registry, or by hand.
+            if !registry::contains(&path)? {
+                registry::record_shim(&Shim::load(&path)?)?;
+            }
+        } else {
+            if recipe.target == Target::MacOs {
+                fs::create_dir_all(dest)
+                    .map_err(|e| format!("Error creating directory '{}': {e}", dest.display()))?;
+            }
+            gen.keep_backup(recipe.backup);
+            gen.save(true).map_err(|e| e.to_msg(&path))?;
+            if recipe.make_default {
+                gen.make_default()?;
+            }
+        }
+        registry::set_source(&path, Some(source))?;
+    }
+
+    Ok(Built {
+        name: recipe.name().to_owned(),
+        path,
+        outcome,
+        warnings: gen.warnings().to_vec(),
+    })
+}
+
+pub struct Pruned {
+    pub path: PathBuf,
+    // Warnings from uninstalling it.
+    pub result: Result<Vec<String>, String>,
+}
+
+pub struct Synced {
+    // A failed recipe's error starts with its name.
+    pub built: Vec<Result<Built, String>>,
+    pub pruned: Vec<Pruned>,
+}
+
+// Builds each of manifest's recipes in dest (force is as for build()), then uninstalls the shims
+// an earlier sync made from the manifest that it no longer lists. Running it again changes
+// nothing. A recipe that fails doesn't stop the others, and its shim is never pruned.
+pub fn sync(
+    manifest: &Manifest,
+    dest: &Path,
+    shim_bin: &Path,
+    force: bool,
+    dry_run: bool,
+) -> Result<Synced, String> {
+    let made = registry::made_from(&manifest.path)?;
+
+    let mut built = vec![];
+    for recipe in &manifest.recipes {
+        let res = build(recipe, &manifest.path, dest, shim_bin, force, dry_run);
+        built.push(res.map_err(|e| format!("{}: {e}", recipe.name())));
+    }
+
+    let mut pruned = vec![];
+    for entry in made {
+        let listed = manifest.recipes.iter().any(|x| x.name() == entry.name)
+            || built.iter().flatten().any(|x| x.path == entry.path);
+        if listed {
+            continue;
+        }
+        let result = if dry_run {
+            Ok(vec![])
+        } else {
+            registry::uninstall(&entry.path.to_string_lossy()).map(|(_, warnings)| warnings)
+        };
+        pruned.push(Pruned {
+            path: entry.path,
+            result,
+        });
+    }
+
+    Ok(Synced { built, pruned })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xdg::TestHome;
+
+    // A manifest of macOS shims, which only touch dest, for each name.
+    fn write_manifest(dir: &Path, names: &[&str]) -> Manifest {
+        let mut toml = "[defaults]\ntarget = \"macos\"\ncommand = \"vim\"\n".to_owned();
+        for name in names {
+            toml += &format!("\n[[shims]]\nname = \"{name}\"\n");
+        }
+        let path = dir.join("shims.toml");
+        fs::write(&path, toml).unwrap();
+        Manifest::load(&path).unwrap()
+    }
+
+    struct Setup {
+        home: TestHome,
+        dest: PathBuf,
+        shim_bin: PathBuf,
+    }
+
+    fn setup() -> Setup {
+        let home = TestHome::new();
+        let dest = home.path().join("Applications");
+        let shim_bin = home.path().join("echidna-shim");
+        fs::write(&shim_bin, "shim").unwrap();
+        Setup {
+            home,
+            dest,
+            shim_bin,
+        }
+    }
+
+    fn outcomes(synced: &Synced) -> Vec<Outcome> {
+        synced
+            .built
+            .iter()
+            .map(|x| x.as_ref().unwrap().outcome)
+            .collect()
+    }
+
+    #[test]
+    fn sync_again_changes_nothing() {
+        let s = setup();
+        let manifest = write_manifest(s.home.path(), &["Vim", "Emacs"]);
+
+        let synced = sync(&manifest, &s.dest, &s.shim_bin, false, false).unwrap();
+        assert_eq!(outcomes(&synced), [Outcome::Created, Outcome::Created]);
+        let plist = s.dest.join("Vim.app/Contents/Info.plist");
+        let before = fs::read(&plist).unwrap();
+
+        let synced = sync(&manifest, &s.dest, &s.shim_bin, false, false).unwrap();
+        assert_eq!(outcomes(&synced), [Outcome::Unchanged, Outcome::Unchanged]);
+        assert!(synced.pruned.is_empty());
+        // Not even the build number was bumped.
+        assert_eq!(fs::read(&plist).unwrap(), before);
+    }
+
+    #[test]
+    fn sync_prunes_shims_no_longer_listed() {
+        let s = setup();
+        let manifest = write_manifest(s.home.path(), &["Vim", "Emacs"]);
+        sync(&manifest, &s.dest, &s.shim_bin, false, false).unwrap();
+
+        let manifest = write_manifest(s.home.path(), &["Vim"]);
+        let synced = sync(&manifest, &s.dest, &s.shim_bin, false, true).unwrap();
+        assert_eq!(synced.pruned.len(), 1);
+        assert!(s.dest.join("Emacs.app").exists(), "pruned in a dry run");
+
+        let synced = sync(&manifest, &s.dest, &s.shim_bin, false, false).unwrap();
+        assert_eq!(outcomes(&synced), [Outcome::Unchanged]);
+        let [pruned] = synced.pruned.as_slice() else {
+            panic!("expected one pruned shim");
+        };
+        assert_eq!(pruned.path, s.dest.join("Emacs.app"));
+        assert!(pruned.result.is_ok());
+        assert!(!s.dest.join("Emacs.app").exists());
+        assert!(s.dest.join("Vim.app").exists());
+        assert!(registry::made_from(&manifest.path)
+            .unwrap()
+            .iter()
+            .all(|x| x.name == "Vim"));
+    }
+
+    #[test]
+    fn sync_leaves_shims_from_elsewhere_alone() {
+        let s = setup();
+        let other_dir = s.home.path().join("other");
+        fs::create_dir(&other_dir).unwrap();
+        let other = write_manifest(&other_dir, &["Vim", "Emacs"]);
+        sync(&other, &s.dest, &s.shim_bin, false, false).unwrap();
+        let plist = s.dest.join("Vim.app/Contents/Info.plist");
+        let before = fs::read(&plist).unwrap();
+
+        // Lists one of the other manifest's shims, with a different command, and not the other.
+        let path = s.home.path().join("shims.toml");
+        fs::write(
+            &path,
+            "[[shims]]\nname = \"Vim\"\ntarget = \"macos\"\ncommand = \"nvim\"\n",
+        )
+        .unwrap();
+        let manifest = Manifest::load(&path).unwrap();
+        let synced = sync(&manifest, &s.dest, &s.shim_bin, false, false).unwrap();
+
+        let err = synced.built[0].as_ref().err().unwrap();
+        assert!(err.contains("wasn't built from"), "{err}");
+        assert!(synced.pruned.is_empty());
+        assert_eq!(fs::read(&plist).unwrap(), before);
+        assert!(s.dest.join("Emacs.app").exists());
+        assert_eq!(registry::made_from(&other.path).unwrap().len(), 2);
+
+        // Unless forced, when it's claimed.
+        let synced = sync(&manifest, &s.dest, &s.shim_bin, true, false).unwrap();
+        assert_eq!(outcomes(&synced), [Outcome::Updated]);
+        assert_eq!(registry::made_from(&manifest.path).unwrap().len(), 1);
+    }
+}
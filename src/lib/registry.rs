@@ -0,0 +1,430 @@
+use crate::bailf;
+use crate::defaults;
+use crate::desktop;
+use crate::generate::Target;
+use crate::icon::HICOLOR_SIZES;
+use crate::misc::{lock_state_dir, state_dir, unix_time, write_atomic};
+use crate::shim::{self, Shim};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+// Every shim generated for this user, wherever it was saved, recorded in the state directory so
+// they can be listed and uninstalled later. Shims made by older versions, or copied from
+// elsewhere, aren't in it, but are found by scanning the usual places.
+
+const REGISTRY_FILE: &str = "shims.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub target: Target,
+    // The .app bundle, or for Linux, the desktop entry.
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle_id: Option<String>,
+    pub command: String,
+    // Seconds since the Unix epoch.
+    pub created: u64,
+    pub updated: u64,
+    // The recipe or manifest `build` or `sync` made it from, which alone may replace it, and for a
+    // manifest, prunes it once it's no longer listed there.
+    #[serde(default, alias = "manifest", skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Registry(Vec<Entry>);
+
+impl Registry {
+    fn path() -> Result<PathBuf, String> {
+        Ok(state_dir()?.join(REGISTRY_FILE))
+    }
+
+    fn load() -> Result<Registry, String> {
+        let path = Self::path()?;
+        match fs::read_to_string(&path) {
+            Ok(x) => serde_json::from_str(&x)
+                .map_err(|e| format!("Error parsing '{}': {e}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Registry::default()),
+            Err(e) => Err(format!("Error reading '{}': {e}", path.display())),
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Error serializing shim registry: {e}"))?;
+        write_atomic(&Self::path()?, json.as_bytes())
+    }
+
+    // Loads the registry, changes it and saves it, under the state directory's lock so that
+    // another echidna process's changes aren't lost.
+    fn update<R>(change: impl FnOnce(&mut Registry) -> Result<R, String>) -> Result<R, String> {
+        let _lock = lock_state_dir()?;
+        let mut registry = Self::load()?;
+        let res = change(&mut registry)?;
+        registry.save()?;
+        Ok(res)
+    }
+}
+
+// Adds the shim at path, or updates its entry, keeping when it was created.
+pub fn record(
+    name: &str,
+    target: Target,
+    path: &Path,
+    bundle_id: Option<&str>,
+    command: &str,
+) -> Result<(), String> {
+    let now = unix_time();
+    let entry = Entry {
+        name: name.to_owned(),
+        target,
+        path: path.to_owned(),
+        bundle_id: bundle_id.map(|x| x.to_owned()),
+        command: command.to_owned(),
+        created: now,
+        updated: now,
+        source: None,
+    };
+
+    Registry::update(|registry| {
+        match registry.0.iter_mut().find(|x| x.path == path) {
+            Some(existing) => {
+                *existing = Entry {
+                    created: existing.created,
+                    source: existing.source.take(),
+                    ..entry
+                }
+            }
+            None => registry.0.push(entry),
+        }
+        Ok(())
+    })
+}
+
+// Records a shim as it is now, e.g. after editing it.
+pub fn record_shim(shim: &Shim) -> Result<(), String> {
+    record(
+        &shim.name,
+        shim.target,
+        &shim.path,
+        shim.bundle_id.as_deref(),
+        &shim.config.command,
+    )
+}
+
+// Whether the shim at path is in the registry.
+pub fn contains(path: &Path) -> Result<bool, String> {
+    Ok(Registry::load()?.0.iter().any(|x| x.path == path))
+}
+
+// Marks the shim at path as made from source, or as no longer made from one.
+pub fn set_source(path: &Path, source: Option<&Path>) -> Result<(), String> {
+    Registry::update(|registry| {
+        let Some(entry) = registry.0.iter_mut().find(|x| x.path == path) else {
+            bailf!("'{}' isn't in the shim registry", path.display());
+        };
+        entry.source = source.map(|x| x.to_owned());
+        Ok(())
+    })
+}
+
+// The shims made from source.
+pub fn made_from(source: &Path) -> Result<Vec<Entry>, String> {
+    let registry = Registry::load()?;
+    Ok(registry
+        .0
+        .into_iter()
+        .filter(|x| x.source.as_deref() == Some(source))
+        .collect())
+}
+
+pub struct Listed {
+    pub entry: Entry,
+    // Found by scanning rather than in the registry.
+    pub untracked: bool,
+    // In the registry, but no longer there.
+    pub missing: bool,
+}
+
+// The registry's shims, then any others found in the usual places.
+pub fn list() -> Result<Vec<Listed>, String> {
+    let registry = Registry::load()?;
+    let mut listed: Vec<Listed> = registry
+        .0
+        .iter()
+        .map(|x| Listed {
+            entry: x.clone(),
+            untracked: false,
+            missing: fs::symlink_metadata(&x.path).is_err(),
+        })
+        .collect();
+
+    for path in shim::find_all() {
+        if registry.0.iter().any(|x| x.path == path) {
+            continue;
+        }
+        // Not an Echidna shim after all, or a broken one.
+        let Ok(shim) = Shim::load(&path) else {
+            continue;
+        };
+        listed.push(Listed {
+            entry: Entry {
+                name: shim.name,
+                target: shim.target,
+                path,
+                bundle_id: shim.bundle_id,
+                command: shim.config.command,
+                created: 0,
+                updated: 0,
+                source: None,
+            },
+            untracked: true,
+            missing: false,
+        });
+    }
+
+    Ok(listed)
+}
+
+// Finds the one shim that shim (a name, path or bundle identifier) refers to.
+fn find(shim: &str) -> Result<Entry, String> {
+    let listed = list()?;
+    let path = Path::new(shim);
+    let matches: Vec<_> = listed
+        .iter()
+        .filter(|x| {
+            let entry = &x.entry;
+            entry.name == shim || entry.path == path || entry.bundle_id.as_deref() == Some(shim)
+        })
+        .collect();
+
+    match matches.as_slice() {
+        [x] => Ok(x.entry.clone()),
+        [] => {
+            // Somewhere that isn't scanned.
+            let shim = Shim::load(path).map_err(|_| format!("No shim named '{shim}' found"))?;
+            Ok(Entry {
+                name: shim.name,
+                target: shim.target,
+                path: shim.path,
+                bundle_id: shim.bundle_id,
+                command: shim.config.command,
+                created: 0,
+                updated: 0,
+                source: None,
+            })
+        }
+        _ => {
+            let paths: Vec<_> = matches
+                .iter()
+                .map(|x| x.entry.path.display().to_string())
+                .collect();
+            bailf!(
+                "'{shim}' could be any of {}, pass its path instead",
+                paths.join(", ")
+            )
+        }
+    }
+}
+
+fn remove(path: &Path, removed: &mut Vec<PathBuf>) -> Result<(), String> {
+    let res = match fs::symlink_metadata(path) {
+        Ok(x) if x.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => Err(e),
+    };
+    res.map_err(|e| format!("Error removing '{}': {e}", path.display()))?;
+    removed.push(path.to_owned());
+    Ok(())
+}
+
+// Removes a shim, and everything installed with it, after restoring the default handlers it
+// replaced. shim is its name, path or bundle identifier. Returns what was removed, and warnings.
+pub fn uninstall(shim: &str) -> Result<(Vec<PathBuf>, Vec<String>), String> {
+    let entry = find(shim)?;
+    let mut warnings = vec![];
+    let mut removed = vec![];
+
+    let path = entry.path.to_string_lossy();
+    let id = entry.bundle_id.clone().unwrap_or_default();
+    for key in [path.as_ref(), id.as_str()] {
+        if !key.is_empty() && defaults::is_recorded(key)? {
+            warnings.extend(defaults::restore(key)?);
+            break;
+        }
+    }
+
+    match entry.target {
+        Target::MacOs => remove(&entry.path, &mut removed)?,
+        Target::Linux => {
+            let Some(name) = entry
+                .path
+                .file_stem()
+                .and_then(|x| x.to_str())
+                .and_then(|x| x.strip_prefix("echidna-"))
+            else {
+                bailf!("'{}' isn't an Echidna shim", entry.path.display());
+            };
+            let name = name.as_ref();
+
+            remove(&entry.path, &mut removed)?;
+            remove(&desktop::data_dir(name)?, &mut removed)?;
+            let icons_dir = desktop::icons_dir()?;
+            for size in HICOLOR_SIZES {
+                remove(
+                    &icons_dir.join(desktop::hicolor_icon_path(name, *size)),
+                    &mut removed,
+                )?;
+            }
+
+            let mime_dir = desktop::mime_dir()?;
+            let package = mime_dir
+                .join("packages")
+                .join(desktop::mime_package_file_name(name));
+            if package.exists() {
+                remove(&package, &mut removed)?;
+                warnings.extend(desktop::update_mime_database(&mime_dir).err());
+            }
+            if let Some(applications) = entry.path.parent() {
+                warnings.extend(desktop::update_desktop_database(applications).err());
+            }
+        }
+    }
+
+    Registry::update(|registry| {
+        registry.0.retain(|x| x.path != entry.path);
+        Ok(())
+    })?;
+
+    Ok((removed, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xdg::{self, TestHome};
+
+    fn record_at(name: &str, path: &Path) {
+        record(name, Target::MacOs, path, Some("com.example.vim"), "vim").unwrap();
+    }
+
+    #[test]
+    fn record_keeps_created_and_source() {
+        let home = TestHome::new();
+        let path = home.path().join("Vim.app");
+        record_at("Vim", &path);
+        set_source(&path, Some(Path::new("/recipes/vim.toml"))).unwrap();
+        Registry::update(|x| {
+            x.0[0].created = 1;
+            Ok(())
+        })
+        .unwrap();
+
+        record("Vim", Target::MacOs, &path, None, "nvim").unwrap();
+        let registry = Registry::load().unwrap();
+        let [entry] = registry.0.as_slice() else {
+            panic!("expected one entry, got {:?}", registry.0);
+        };
+        assert_eq!(entry.created, 1);
+        assert!(entry.updated > 1);
+        assert_eq!(
+            entry.source.as_deref(),
+            Some(Path::new("/recipes/vim.toml"))
+        );
+        assert_eq!(entry.command, "nvim");
+        assert_eq!(entry.bundle_id, None);
+        assert_eq!(made_from(Path::new("/recipes/vim.toml")).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn concurrent_records_are_all_kept() {
+        let home = TestHome::new();
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let home = home.path().to_owned();
+                std::thread::spawn(move || {
+                    xdg::use_test_home(&home);
+                    for j in 0..5 {
+                        record_at("Vim", &home.join(format!("{i}/{j}/Vim.app")));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(Registry::load().unwrap().0.len(), 40);
+    }
+
+    #[test]
+    fn find_reports_ambiguous_names() {
+        let home = TestHome::new();
+        let (a, b) = (home.path().join("a/Vim.app"), home.path().join("b/Vim.app"));
+        record_at("Vim", &a);
+        record_at("Vim", &b);
+
+        let err = find("Vim").unwrap_err();
+        assert!(err.contains(&a.display().to_string()), "{err}");
+        assert!(err.contains(&b.display().to_string()), "{err}");
+        assert_eq!(find(&a.to_string_lossy()).unwrap().path, a);
+        assert!(find("Emacs").is_err());
+    }
+
+    #[test]
+    fn uninstall_linux_removes_only_the_shims_files() {
+        let _home = TestHome::new();
+        let write = |path: &Path| {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        };
+        let files = |name: &str| {
+            let name = name.as_ref();
+            let icons_dir = desktop::icons_dir().unwrap();
+            let mut files = vec![
+                desktop::applications_dir()
+                    .unwrap()
+                    .join(desktop::desktop_file_name(name)),
+                desktop::data_dir(name).unwrap(),
+                desktop::mime_dir()
+                    .unwrap()
+                    .join("packages")
+                    .join(desktop::mime_package_file_name(name)),
+            ];
+            files.extend(
+                HICOLOR_SIZES
+                    .iter()
+                    .map(|x| icons_dir.join(desktop::hicolor_icon_path(name, *x))),
+            );
+            files
+        };
+
+        let (vim, other) = (files("Vim"), files("Vim2"));
+        for file in vim.iter().chain(&other) {
+            if file.extension().is_none() {
+                write(&file.join("config.json"));
+            } else {
+                write(file);
+            }
+        }
+        record("Vim", Target::Linux, &vim[0], None, "vim").unwrap();
+        record("Vim2", Target::Linux, &other[0], None, "vim").unwrap();
+
+        let (mut removed, _) = uninstall("Vim").unwrap();
+        removed.sort();
+        let mut expected = vim.clone();
+        expected.sort();
+        assert_eq!(removed, expected);
+        assert!(vim.iter().all(|x| !x.exists()));
+        assert!(other.iter().all(|x| x.exists()));
+
+        let registry = Registry::load().unwrap();
+        assert_eq!(registry.0.len(), 1);
+        assert_eq!(registry.0[0].name, "Vim2");
+    }
+}
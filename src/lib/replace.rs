@@ -0,0 +1,569 @@
+use crate::generate::SaveErr;
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+// Moving generated files and bundles from the temporary directory into place. Everything is first
+// staged next to its destination, on the same filesystem, so the last step is a rename, which
+// can't leave a half-written bundle behind. Existing destinations are swapped out atomically where
+// the filesystem supports it. Several files (a Linux shim's desktop entry, data directory, icons
+// and MIME package) are moved all or nothing: each is atomic, and if one fails, those already
+// moved are swapped back.
+
+// Where a replaced destination is kept, if asked to.
+pub fn backup_path(dst: &Path) -> PathBuf {
+    let mut name = dst.file_name().unwrap_or_default().to_owned();
+    name.push(".bak");
+    dst.with_file_name(name)
+}
+
+// A hidden sibling of dst, unique to this process.
+fn staging_path(dst: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(dst.file_name().unwrap_or_default());
+    name.push(format!(".echidna-{}", std::process::id()));
+    dst.with_file_name(name)
+}
+
+fn remove_any(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(x) if x.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+// Copies files, directories and symlinks, keeping permissions.
+fn copy_recursive(src: &Path, dst: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(src)?;
+    if metadata.is_symlink() {
+        symlink(fs::read_link(src)?, dst)
+    } else if metadata.is_dir() {
+        fs::create_dir(dst)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+        fs::set_permissions(dst, metadata.permissions())
+    } else {
+        fs::copy(src, dst).map(|_| ()) // Copies permissions too.
+    }
+}
+
+// Moves src to staged by copying it, for when they're on different filesystems.
+fn stage_by_copying(src: &Path, staged: &Path) -> Result<(), String> {
+    copy_recursive(src, staged).map_err(|e| {
+        let _ = remove_any(staged);
+        format!(
+            "Error copying '{}' to '{}': {e}",
+            src.display(),
+            staged.display()
+        )
+    })?;
+    let _ = remove_any(src); // It's in a temporary directory anyway.
+    Ok(())
+}
+
+// Moves src next to dst, copying it if it's on another filesystem.
+fn stage(src: &Path, dst: &Path) -> Result<PathBuf, String> {
+    let staged = staging_path(dst);
+    // Left over from an earlier failure.
+    let _ = remove_any(&staged);
+
+    match fs::rename(src, &staged) {
+        Ok(()) => Ok(staged),
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            stage_by_copying(src, &staged).map(|()| staged)
+        }
+        Err(e) => Err(format!(
+            "Error moving '{}' to '{}': {e}",
+            src.display(),
+            staged.display()
+        )),
+    }
+}
+
+// Copies src over dst, which readers see either as it was or as src, never half-written.
+pub fn copy_into_place(src: &Path, dst: &Path) -> Result<(), String> {
+    let staged = staging_path(dst);
+    let _ = remove_any(&staged);
+
+    copy_recursive(src, &staged)
+        .and_then(|()| fs::rename(&staged, dst))
+        .map_err(|e| {
+            let _ = remove_any(&staged);
+            format!(
+                "Error copying '{}' to '{}': {e}",
+                src.display(),
+                dst.display()
+            )
+        })
+}
+
+fn path_cstring(path: &Path) -> io::Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+// Atomically swaps a and b, which must both exist.
+#[cfg(target_os = "linux")]
+fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+    let (a, b) = (path_cstring(a)?, path_cstring(b)?);
+    // Through syscall(), glibc only has a wrapper since 2.28.
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+    let (a, b) = (path_cstring(a)?, path_cstring(b)?);
+    let res = unsafe { libc::renamex_np(a.as_ptr(), b.as_ptr(), libc::RENAME_SWAP) };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn exchange(_a: &Path, _b: &Path) -> io::Result<()> {
+    Err(io::Error::from_raw_os_error(libc::ENOTSUP))
+}
+
+// Whether exchange() failed because the filesystem (or kernel) can't do it, rather than because
+// of the paths.
+fn exchange_unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EINVAL | libc::ENOSYS | libc::ENOTSUP | libc::EXDEV)
+    )
+}
+
+// Replaces dst with staged, leaving what was at dst at old. Not atomic: dst is briefly missing,
+// and is put back if staged can't be moved in.
+fn replace_by_renaming(staged: &Path, dst: &Path, old: &Path) -> io::Result<()> {
+    fs::rename(dst, old)?;
+    if let Err(e) = fs::rename(staged, dst) {
+        let _ = fs::rename(old, dst); // Best effort.
+        return Err(e);
+    }
+    // Leaves the new contents where exchange() would have left the old.
+    fs::rename(old, staged)
+}
+
+// Swaps a and b, both of which must exist, atomically if the filesystem can.
+fn swap(a: &Path, b: &Path) -> io::Result<()> {
+    match exchange(a, b) {
+        Err(e) if exchange_unsupported(&e) => {
+            replace_by_renaming(a, b, &staging_path(&backup_path(b)))
+        }
+        res => res,
+    }
+}
+
+// What to move where, with move_all_into_place().
+pub struct Move {
+    // A file or directory.
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    // Whether an existing dst is kept at backup_path(dst).
+    pub backup: bool,
+}
+
+// A Move in progress. Dropped before it's finished, src is moved back, so it can be tried again.
+struct Replacement<'a> {
+    mv: &'a Move,
+    staged: PathBuf,
+    existed: bool,
+    swapped: bool,
+    finished: bool,
+}
+
+impl<'a> Replacement<'a> {
+    fn stage(mv: &'a Move) -> Result<Replacement<'a>, String> {
+        Ok(Replacement {
+            mv,
+            existed: fs::symlink_metadata(&mv.dst).is_ok(),
+            staged: stage(&mv.src, &mv.dst)?,
+            swapped: false,
+            finished: false,
+        })
+    }
+
+    // Moves staged to dst. Afterwards, staged holds what was at dst, if anything.
+    fn swap(&mut self) -> Result<(), SaveErr> {
+        let dst = &self.mv.dst;
+        let res = if self.existed {
+            swap(&self.staged, dst)
+        } else {
+            fs::rename(&self.staged, dst)
+        };
+        match res {
+            Ok(()) => {
+                self.swapped = true;
+                Ok(())
+            }
+            // ErrorKind::DirectoryNotEmpty not available on stable. Created in the meantime.
+            Err(e)
+                if !self.existed
+                    && matches!(e.raw_os_error(), Some(libc::ENOTEMPTY | libc::EEXIST)) =>
+            {
+                Err(SaveErr::AppAlreadyExists)
+            }
+            Err(e) => Err(SaveErr::Other(format!(
+                "Error replacing '{}': {e}",
+                dst.display()
+            ))),
+        }
+    }
+
+    // Undoes swap().
+    fn swap_back(&mut self) -> Result<(), String> {
+        let dst = &self.mv.dst;
+        let res = if self.existed {
+            swap(&self.staged, dst)
+        } else {
+            fs::rename(dst, &self.staged)
+        };
+        res.map_err(|e| format!("Couldn't put back the previous '{}': {e}", dst.display()))?;
+        self.swapped = false;
+        Ok(())
+    }
+
+    // Removes what was at dst, or keeps it as the backup. Returns warnings about anything left
+    // behind.
+    fn finish(mut self) -> Vec<String> {
+        self.finished = true;
+        if !self.existed {
+            return vec![];
+        }
+
+        let (dst, staged) = (&self.mv.dst, &self.staged);
+        let mut warnings = vec![];
+        if self.mv.backup {
+            let backup = backup_path(dst);
+            let res = remove_any(&backup).and_then(|()| fs::rename(staged, &backup));
+            if let Err(e) = res {
+                warnings.push(format!(
+                    "Couldn't keep the previous '{}' as '{}', it's at '{}': {e}",
+                    dst.display(),
+                    backup.display(),
+                    staged.display()
+                ));
+            }
+        } else if let Err(e) = remove_any(staged) {
+            warnings.push(format!(
+                "Couldn't remove the previous '{}' from '{}': {e}",
+                dst.display(),
+                staged.display()
+            ));
+        }
+        warnings
+    }
+}
+
+impl Drop for Replacement<'_> {
+    fn drop(&mut self) {
+        // Past swap_back() failing, staged is what used to be at dst, and is left for the user.
+        if self.finished || self.swapped {
+            return;
+        }
+        let (src, staged) = (&self.mv.src, &self.staged);
+        if fs::rename(staged, src).is_err() && stage_by_copying(staged, src).is_err() {
+            let _ = remove_any(staged);
+        }
+    }
+}
+
+// Does each move, all or nothing: everything is staged next to its destination first, then moved
+// into place, and if one of those fails, the ones already moved are put back. An existing dst is
+// only replaced if overwrite. On failure, each src is moved back, so it can be tried again.
+// Returns warnings about anything left behind.
+pub fn move_all_into_place(moves: &[Move], overwrite: bool) -> Result<Vec<String>, SaveErr> {
+    if !overwrite && moves.iter().any(|x| fs::symlink_metadata(&x.dst).is_ok()) {
+        return Err(SaveErr::AppAlreadyExists);
+    }
+
+    let mut replacements = vec![];
+    for mv in moves {
+        replacements.push(Replacement::stage(mv).map_err(SaveErr::Other)?);
+    }
+
+    for i in 0..replacements.len() {
+        if let Err(e) = replacements[i].swap() {
+            let mut problems = vec![];
+            for done in replacements[..i].iter_mut().rev() {
+                problems.extend(done.swap_back().err());
+            }
+            if problems.is_empty() {
+                return Err(e);
+            }
+            let e = match e {
+                SaveErr::AppAlreadyExists => {
+                    format!(
+                        "'{}' was created meanwhile",
+                        replacements[i].mv.dst.display()
+                    )
+                }
+                SaveErr::Other(e) => e,
+            };
+            return Err(SaveErr::Other(format!("{e}. {}", problems.join(". "))));
+        }
+    }
+
+    Ok(replacements
+        .into_iter()
+        .flat_map(Replacement::finish)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempdir::TempDir;
+
+    // A bundle-like directory holding a file with contents.
+    fn make_bundle(path: &Path, contents: &str) {
+        fs::create_dir_all(path.join("Contents")).unwrap();
+        fs::write(path.join("Contents/file"), contents).unwrap();
+    }
+
+    fn bundle_contents(path: &Path) -> String {
+        fs::read_to_string(path.join("Contents/file")).unwrap()
+    }
+
+    // Everything in dir, to check nothing was left staged.
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|x| x.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn moves(src: &Path, dst: &Path, backup: bool) -> Vec<Move> {
+        vec![Move {
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+            backup,
+        }]
+    }
+
+    #[test]
+    fn replacing_keeps_the_old_bundle_as_backup() {
+        let (tmp, dest) = (
+            TempDir::new("echidna-test").unwrap(),
+            TempDir::new("echidna-test").unwrap(),
+        );
+        let (src, dst) = (tmp.path().join("Vim.app"), dest.path().join("Vim.app"));
+        make_bundle(&src, "new");
+        make_bundle(&dst, "old");
+
+        let warnings = move_all_into_place(&moves(&src, &dst, true), true).unwrap();
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_eq!(bundle_contents(&dst), "new");
+        assert_eq!(bundle_contents(&backup_path(&dst)), "old");
+        assert_eq!(names(dest.path()), ["Vim.app", "Vim.app.bak"]);
+        assert!(!src.exists());
+
+        // Without a backup, the old one is just removed, and an older backup left alone.
+        make_bundle(&src, "newer");
+        move_all_into_place(&moves(&src, &dst, false), true).unwrap();
+        assert_eq!(bundle_contents(&dst), "newer");
+        assert_eq!(bundle_contents(&backup_path(&dst)), "old");
+        assert_eq!(names(dest.path()), ["Vim.app", "Vim.app.bak"]);
+    }
+
+    #[test]
+    fn moving_to_a_new_destination() {
+        let (tmp, dest) = (
+            TempDir::new("echidna-test").unwrap(),
+            TempDir::new("echidna-test").unwrap(),
+        );
+        let (src, dst) = (tmp.path().join("Vim.app"), dest.path().join("Vim.app"));
+        make_bundle(&src, "new");
+
+        let warnings = move_all_into_place(&moves(&src, &dst, true), false).unwrap();
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_eq!(bundle_contents(&dst), "new");
+        assert_eq!(names(dest.path()), ["Vim.app"]);
+        assert!(!src.exists());
+    }
+
+    #[test]
+    fn existing_destination_needs_overwrite() {
+        let (tmp, dest) = (
+            TempDir::new("echidna-test").unwrap(),
+            TempDir::new("echidna-test").unwrap(),
+        );
+        let (src, dst) = (tmp.path().join("Vim.app"), dest.path().join("Vim.app"));
+        make_bundle(&src, "new");
+        make_bundle(&dst, "old");
+
+        let res = move_all_into_place(&moves(&src, &dst, false), false);
+        assert!(matches!(res, Err(SaveErr::AppAlreadyExists)));
+        assert_eq!(bundle_contents(&dst), "old");
+        assert_eq!(bundle_contents(&src), "new");
+        assert_eq!(names(dest.path()), ["Vim.app"]);
+    }
+
+    #[test]
+    fn failing_to_stage_changes_nothing() {
+        let (tmp, dest) = (
+            TempDir::new("echidna-test").unwrap(),
+            TempDir::new("echidna-test").unwrap(),
+        );
+        let (src, dst) = (tmp.path().join("Vim.app"), dest.path().join("Vim.app"));
+        let src_file = tmp.path().join("echidna-Vim.desktop");
+        make_bundle(&src, "new");
+        make_bundle(&dst, "old");
+        fs::write(&src_file, "entry").unwrap();
+
+        let moves = [
+            Move {
+                src: src.clone(),
+                dst: dst.clone(),
+                backup: false,
+            },
+            // Its directory doesn't exist.
+            Move {
+                src: src_file.clone(),
+                dst: dest.path().join("missing/echidna-Vim.desktop"),
+                backup: false,
+            },
+        ];
+        assert!(move_all_into_place(&moves, true).is_err());
+        assert_eq!(bundle_contents(&dst), "old");
+        assert_eq!(names(dest.path()), ["Vim.app"]);
+        // Back where they were, to try again.
+        assert_eq!(bundle_contents(&src), "new");
+        assert_eq!(fs::read_to_string(&src_file).unwrap(), "entry");
+    }
+
+    #[test]
+    fn swapped_moves_can_be_put_back() {
+        let (tmp, dest) = (
+            TempDir::new("echidna-test").unwrap(),
+            TempDir::new("echidna-test").unwrap(),
+        );
+        let moves = [
+            Move {
+                src: tmp.path().join("Vim"),
+                dst: dest.path().join("Vim"),
+                backup: false,
+            },
+            Move {
+                src: tmp.path().join("echidna-Vim.desktop"),
+                dst: dest.path().join("echidna-Vim.desktop"),
+                backup: false,
+            },
+        ];
+        make_bundle(&moves[0].src, "new");
+        make_bundle(&moves[0].dst, "old");
+        fs::write(&moves[1].src, "entry").unwrap();
+
+        {
+            let mut replacements: Vec<_> = moves
+                .iter()
+                .map(|x| Replacement::stage(x).unwrap())
+                .collect();
+            for x in &mut replacements {
+                x.swap().unwrap();
+            }
+            assert_eq!(bundle_contents(&moves[0].dst), "new");
+            assert!(moves[1].dst.exists());
+
+            for x in replacements.iter_mut().rev() {
+                x.swap_back().unwrap();
+            }
+        }
+
+        assert_eq!(bundle_contents(&moves[0].dst), "old");
+        assert_eq!(names(dest.path()), ["Vim"]);
+        assert_eq!(bundle_contents(&moves[0].src), "new");
+        assert_eq!(fs::read_to_string(&moves[1].src).unwrap(), "entry");
+    }
+
+    #[test]
+    fn swap_exchanges_paths() {
+        let dir = TempDir::new("echidna-test").unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        make_bundle(&a, "a");
+        fs::write(&b, "b").unwrap();
+
+        swap(&a, &b).unwrap();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "b");
+        assert_eq!(bundle_contents(&b), "a");
+        assert_eq!(names(dir.path()), ["a", "b"]);
+
+        // The fallback, for filesystems without an atomic exchange.
+        replace_by_renaming(&a, &b, &dir.path().join("tmp")).unwrap();
+        assert_eq!(bundle_contents(&a), "a");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "b");
+        assert_eq!(names(dir.path()), ["a", "b"]);
+
+        // And the atomic exchange itself, where the temporary directory's filesystem has one.
+        match exchange(&a, &b) {
+            Err(e) if exchange_unsupported(&e) => (),
+            res => {
+                res.unwrap();
+                assert_eq!(fs::read_to_string(&a).unwrap(), "b");
+                assert_eq!(bundle_contents(&b), "a");
+            }
+        }
+    }
+
+    #[test]
+    fn staging_by_copying_keeps_modes_and_symlinks() {
+        let (tmp, dest) = (
+            TempDir::new("echidna-test").unwrap(),
+            TempDir::new("echidna-test").unwrap(),
+        );
+        let src = tmp.path().join("Vim");
+        make_bundle(&src, "new");
+        let bin = src.join("Contents/Vim");
+        fs::write(&bin, "#!/bin/sh").unwrap();
+        fs::set_permissions(&bin, fs::Permissions::from_mode(0o755)).unwrap();
+        symlink("Vim", src.join("Contents/link")).unwrap();
+
+        let staged = staging_path(&dest.path().join("Vim"));
+        assert!(file_name(&staged).starts_with(".Vim.echidna-"));
+        stage_by_copying(&src, &staged).unwrap();
+
+        assert!(!src.exists());
+        assert_eq!(bundle_contents(&staged), "new");
+        let mode = fs::metadata(staged.join("Contents/Vim"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert_eq!(
+            fs::read_link(staged.join("Contents/link")).unwrap(),
+            Path::new("Vim")
+        );
+    }
+
+    fn file_name(path: &Path) -> String {
+        path.file_name().unwrap().to_string_lossy().into_owned()
+    }
+}
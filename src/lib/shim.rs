@@ -0,0 +1,720 @@
+use crate::bailf;
+use crate::config::{Config, GroupBy, TerminalApp};
+use crate::desktop;
+use crate::generate::{self, DocTypeGroup, DocTypes, Rank, Role, Target};
+use crate::icon::{self, HICOLOR_SIZES, SHIM_APP_ICON};
+use crate::icon_composer::ShimIcon;
+use crate::info_plist::{self, GENERATOR_VERSION_KEY};
+use crate::misc::write_all_atomic;
+use crate::type_decl::TypeDecl;
+use crate::{defaults, registry, term, xdg};
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use plist::Dictionary;
+use serde::Serialize;
+
+// Reading back a shim that's already been generated, so it can be inspected or regenerated with
+// changes. Only what the generator writes is understood, anything else in the bundle is ignored.
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Shim {
+    // The .app bundle, or for Linux, the desktop entry.
+    pub path: PathBuf,
+    pub target: Target,
+    pub name: String,
+    pub config: Config,
+    pub doc_types: DocTypes,
+    // The user's own types, not those declared for unknown extensions.
+    pub type_decls: Vec<TypeDecl>,
+    // macOS only.
+    pub bundle_id: Option<String>,
+    // CFBundleVersion, macOS only.
+    pub version: Option<String>,
+    // Echidna's version when the shim was generated, if it recorded it.
+    pub generator_version: Option<String>,
+    // None for the default icon.
+    pub icon: Option<PathBuf>,
+}
+
+// Changes for edit() to make to an existing shim, anything None is left as it is.
+#[derive(Debug, Clone, Default)]
+pub struct ShimEdit {
+    pub command: Option<String>,
+    pub group_open_by: Option<GroupBy>,
+    pub terminal: Option<TerminalApp>,
+    // macOS only.
+    pub bundle_id: Option<String>,
+    pub icon: Option<ShimIcon>,
+}
+
+impl ShimEdit {
+    pub fn is_empty(&self) -> bool {
+        self.command.is_none()
+            && self.group_open_by.is_none()
+            && self.terminal.is_none()
+            && self.bundle_id.is_none()
+            && self.icon.is_none()
+    }
+}
+
+// Embedded in echidna-shim, so the version of a shim's binary can be told without running it.
+pub const VERSION_MARKER: &str =
+    concat!("\0echidna-shim-version:", env!("CARGO_PKG_VERSION"), "\0");
+
+// The version from VERSION_MARKER in a shim binary, if it has one (older ones don't).
+pub fn embedded_version(binary: &[u8]) -> Option<String> {
+    let prefix = VERSION_MARKER.split(':').next()?.as_bytes();
+    let prefix = [prefix, b":"].concat();
+
+    // The prefix alone may be in there too, e.g. as this function's needle.
+    let mut start = 0;
+    while let Some(i) = binary[start..]
+        .windows(prefix.len())
+        .position(|x| x == prefix)
+    {
+        let version_start = start + i + prefix.len();
+        let rest = &binary[version_start..binary.len().min(version_start + 64)];
+        if let Some(len) = rest.iter().position(|x| *x == 0) {
+            let version = &rest[..len];
+            let valid = |c: &u8| c.is_ascii_alphanumeric() || matches!(c, b'.' | b'-' | b'+');
+            if !version.is_empty() && version.iter().all(valid) {
+                return Some(String::from_utf8_lossy(version).into_owned());
+            }
+        }
+        start = version_start;
+    }
+    None
+}
+
+// MIME types of the declarations generated for unknown extensions, see TypeDecl::for_ext().
+const EXT_MIME_PREFIX: &str = "application/x-echidna-";
+
+impl Shim {
+    // path is a .app bundle, or an installed Linux shim's desktop entry or data directory.
+    pub fn load(path: &Path) -> Result<Shim, String> {
+        if path.join("Contents").join("Info.plist").is_file() {
+            load_macos(path)
+        } else if path.extension() == Some(OsStr::new("desktop")) || path.is_dir() {
+            load_linux(path)
+        } else {
+            bailf!("'{}' isn't a shim", path.display())
+        }
+    }
+
+    // The shim's copy of echidna-shim.
+    pub fn binary_path(&self) -> Result<PathBuf, String> {
+        Ok(match self.target {
+            Target::MacOs => {
+                let executable = info_plist::read_bundle(&self.path)
+                    .and_then(|x| string(&x, "CFBundleExecutable"))
+                    .unwrap_or_else(|| self.name.clone());
+                self.path.join("Contents/MacOS").join(executable)
+            }
+            Target::Linux => {
+                let name = linux_name(&self.path)?;
+                desktop::data_dir(name.as_ref())?.join(name)
+            }
+        })
+    }
+
+    // What the icon was made from, to make it again with a different label, tint or badge
+    // instead of drawing over the one already drawn.
+    pub fn icon_source(&self) -> Result<ShimIcon, String> {
+        let Some(recorded) = &self.config.icon else {
+            if self.icon.is_some() {
+                bailf!(
+                    "'{}' doesn't record what its icon was made from, and it may already have a \
+                    label, tint or badge drawn on it, pass the icon to make it from",
+                    self.path.display()
+                );
+            }
+            return Ok(ShimIcon::default());
+        };
+        for path in recorded.path.iter().chain(&recorded.badge) {
+            if !path.is_file() {
+                bailf!(
+                    "'{}'s icon was made from '{}', which is gone, pass the icon to make it from",
+                    self.path.display(),
+                    path.display()
+                );
+            }
+        }
+        Ok(recorded.clone())
+    }
+
+    // Where the shim's config.json is.
+    pub fn config_path(&self) -> Result<PathBuf, String> {
+        Ok(match self.target {
+            Target::MacOs => self.path.join("Contents/Resources/config.json"),
+            Target::Linux => {
+                desktop::data_dir(linux_name(&self.path)?.as_ref())?.join("config.json")
+            }
+        })
+    }
+}
+
+// Shims in the usual places: apps in /Applications and ~/Applications with a config.json, and
+// Linux shims' desktop entries in $XDG_DATA_HOME/applications.
+pub fn find_all() -> Vec<PathBuf> {
+    let mut app_dirs = vec![PathBuf::from("/Applications")];
+    app_dirs.extend(xdg::home_dir().map(|x| x.join("Applications")));
+
+    let mut found = vec![];
+    for dir in app_dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension() == Some(OsStr::new("app"))
+                && path.join("Contents/Resources/config.json").is_file()
+            {
+                found.push(path);
+            }
+        }
+    }
+
+    if let Ok(applications) = desktop::applications_dir() {
+        for entry in fs::read_dir(applications).into_iter().flatten().flatten() {
+            let path = entry.path();
+            let config = linux_name(&path)
+                .and_then(|x| desktop::data_dir(x.as_ref()))
+                .map(|x| x.join("config.json"));
+            if path.extension() == Some(OsStr::new("desktop")) && config.is_ok_and(|x| x.is_file())
+            {
+                found.push(path);
+            }
+        }
+    }
+
+    found.sort();
+    found
+}
+
+// Patches an existing shim's files in place, keeping its binary, bundle identifier, icon and
+// everything else edit doesn't change. Everything is validated and rendered before anything is
+// written, and the files are replaced together, or not at all. Returns warnings.
+pub fn edit(path: &Path, edit: &ShimEdit) -> Result<Vec<String>, String> {
+    if edit.is_empty() {
+        bailf!("Nothing to change");
+    }
+
+    let shim = Shim::load(path)?;
+    let mut warnings = vec![];
+
+    let mut config = shim.config.clone();
+    if let Some(command) = &edit.command {
+        if command.trim().is_empty() {
+            bailf!("Command must not be empty");
+        }
+        config.command.clone_from(command);
+    }
+    if let Some(group_open_by) = edit.group_open_by {
+        config.group_open_by = group_open_by;
+    }
+    if let Some(terminal) = &edit.terminal {
+        match terminal {
+            TerminalApp::Supported(name) if !term::is_supported_for(shim.target, name) => bailf!(
+                "Terminal {name} is not supported for {} (supported terminals: {})",
+                shim.target,
+                term::supported_terminals_string_for(shim.target)
+            ),
+            TerminalApp::Generic(name) if name.trim().is_empty() => {
+                bailf!("Generic terminal must not be empty")
+            }
+            _ => config.terminal = terminal.clone(),
+        }
+    }
+
+    if let Some(icon) = &edit.icon {
+        config.icon = icon.recorded();
+    }
+
+    let mut files = vec![(shim.config_path()?, config.to_json()?.into_bytes())];
+    match shim.target {
+        Target::MacOs => edit_macos(&shim, edit, &config, &mut files, &mut warnings)?,
+        Target::Linux => edit_linux(&shim, edit, &mut files, &mut warnings)?,
+    }
+
+    write_all_atomic(&files)?;
+
+    if let Err(e) = Shim::load(path).and_then(|x| registry::record_shim(&x)) {
+        warnings.push(format!("Couldn't record the shim: {e}"));
+    }
+
+    #[cfg(target_os = "macos")]
+    if let Err(e) = crate::launch_services::register(&shim.path) {
+        warnings.push(e);
+    }
+
+    Ok(warnings)
+}
+
+fn edit_macos(
+    shim: &Shim,
+    edit: &ShimEdit,
+    config: &Config,
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+    warnings: &mut Vec<String>,
+) -> Result<(), String> {
+    let Some(mut info_plist) = info_plist::read_bundle(&shim.path) else {
+        bailf!("Couldn't read '{}'s Info.plist", shim.path.display());
+    };
+    let resources = shim.path.join("Contents").join("Resources");
+
+    // Unless it was replaced with extra keys.
+    let usage_key = "NSAppleEventsUsageDescription";
+    let usage = info_plist::apple_events_usage(&shim.name, shim.config.terminal.name());
+    if string(&info_plist, usage_key) == Some(usage) {
+        let usage = info_plist::apple_events_usage(&shim.name, config.terminal.name());
+        info_plist.insert(usage_key.into(), usage.into());
+    }
+
+    if let Some(id) = &edit.bundle_id {
+        generate::validate_bundle_id(id)?;
+        if let Some(old) = &shim.bundle_id {
+            if old != id && defaults::is_recorded(old).unwrap_or(false) {
+                warnings.push(format!(
+                    "Default handlers are recorded for '{old}', restore them with \
+                    `echidna-cli restore-defaults {old}`"
+                ));
+            }
+        }
+        info_plist.insert("CFBundleIdentifier".into(), id.clone().into());
+    }
+
+    let version = generate::bump_bundle_version(shim.version.as_deref());
+    info_plist.insert("CFBundleVersion".into(), version.into());
+
+    if let Some(shim_icon) = &edit.icon {
+        let (icns, warning) = icon::icns_for(shim_icon)?;
+        warnings.extend(warning);
+        let icon_file = string(&info_plist, "CFBundleIconFile").unwrap_or("AppIcon.icns".into());
+        files.push((resources.join(icon_file), icns));
+    }
+
+    let contents = shim.path.join("Contents").join("Info.plist");
+    files.push((contents, info_plist::to_xml(&info_plist)?));
+    Ok(())
+}
+
+fn edit_linux(
+    shim: &Shim,
+    edit: &ShimEdit,
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+    warnings: &mut Vec<String>,
+) -> Result<(), String> {
+    if edit.bundle_id.is_some() {
+        bailf!("Bundle identifiers are only for macOS shims");
+    }
+    let name = linux_name(&shim.path)?;
+
+    if let Some(command) = &edit.command {
+        let entry = fs::read_to_string(&shim.path)
+            .map_err(|e| format!("Error reading '{}': {e}", shim.path.display()))?;
+        let entry = desktop::set_entry_key(&entry, "Comment", &desktop::entry_comment(command));
+        files.push((shim.path.clone(), entry.into_bytes()));
+    }
+
+    if let Some(shim_icon) = &edit.icon {
+        let (source, warning) = shim_icon.load()?;
+        warnings.extend(warning);
+        let icons_dir = desktop::icons_dir()?;
+        for (size, png) in icon::hicolor_pngs(&source)? {
+            files.push((
+                icons_dir.join(desktop::hicolor_icon_path(name.as_ref(), size)),
+                png,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn string(dict: &Dictionary, key: &str) -> Option<String> {
+    dict.get(key)
+        .and_then(|x| x.as_string())
+        .map(|x| x.to_owned())
+}
+
+fn strings(dict: &Dictionary, key: &str) -> Vec<String> {
+    dict.get(key)
+        .and_then(|x| x.as_array())
+        .map(|x| {
+            x.iter()
+                .filter_map(|x| x.as_string())
+                .map(|x| x.to_owned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn dicts<'a>(dict: &'a Dictionary, key: &str) -> impl Iterator<Item = &'a Dictionary> {
+    dict.get(key)
+        .and_then(|x| x.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|x| x.as_dictionary())
+}
+
+fn type_decl(dict: &Dictionary, resources: &Path) -> Option<TypeDecl> {
+    let tags = dict
+        .get("UTTypeTagSpecification")
+        .and_then(|x| x.as_dictionary());
+    let tag = |key| tags.and_then(|x| string(x, key));
+    let extensions = tags.map(|x| strings(x, "public.filename-extension"));
+
+    Some(TypeDecl {
+        identifier: string(dict, "UTTypeIdentifier")?,
+        mime_type: tag("public.mime-type").unwrap_or_default(),
+        description: string(dict, "UTTypeDescription").unwrap_or_default(),
+        conforms_to: strings(dict, "UTTypeConformsTo"),
+        extensions: extensions.unwrap_or_default(),
+        icon: string(dict, "UTTypeIconFile").map(|x| resources.join(x)),
+    })
+}
+
+// Rebuilds the groups from CFBundleDocumentTypes, rejoining those InfoPlist::build() split in two.
+// imported are the identifiers of the user's own types, whose entry isn't a group.
+fn doc_types(info_plist: &Dictionary, resources: &Path, imported: &[String]) -> DocTypes {
+    let mut groups: Vec<DocTypeGroup> = vec![];
+    for dict in dicts(info_plist, "CFBundleDocumentTypes") {
+        let utis = strings(dict, "LSItemContentTypes");
+        let exts = strings(dict, "CFBundleTypeExtensions");
+        let name = string(dict, "CFBundleTypeName");
+        if name.is_none() && exts.is_empty() && utis.iter().all(|x| imported.contains(x)) {
+            continue;
+        }
+
+        let group = DocTypeGroup {
+            name,
+            utis,
+            exts,
+            mime_types: vec![],
+            role: string(dict, "CFBundleTypeRole")
+                .and_then(|x| Role::from_str(&x, true).ok())
+                .unwrap_or_default(),
+            rank: string(dict, "LSHandlerRank").and_then(|x| Rank::from_str(&x, true).ok()),
+            icon: string(dict, "CFBundleTypeIconFile").map(|x| resources.join(x)),
+        };
+
+        match groups.last_mut() {
+            Some(last)
+                if last.exts.is_empty()
+                    && group.utis.is_empty()
+                    && (&last.name, last.role, last.rank, &last.icon)
+                        == (&group.name, group.role, group.rank, &group.icon) =>
+            {
+                last.exts = group.exts;
+            }
+            _ => groups.push(group),
+        }
+    }
+    DocTypes(groups)
+}
+
+fn load_macos(path: &Path) -> Result<Shim, String> {
+    let Some(info_plist) = info_plist::read_bundle(path) else {
+        bailf!("Couldn't read '{}'s Info.plist", path.display());
+    };
+    let resources = path.join("Contents").join("Resources");
+    let config = Config::load_from(&resources.join("config.json"))?;
+
+    let type_decls: Vec<TypeDecl> = dicts(&info_plist, "UTImportedTypeDeclarations")
+        .filter_map(|x| type_decl(x, &resources))
+        .collect();
+    let imported: Vec<String> = type_decls.iter().map(|x| x.identifier.clone()).collect();
+    let doc_types = doc_types(&info_plist, &resources, &imported);
+
+    let name = string(&info_plist, "CFBundleName")
+        .or_else(|| path.file_stem().map(|x| x.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+
+    let icon_file = string(&info_plist, "CFBundleIconFile").unwrap_or("AppIcon.icns".to_owned());
+    let icon = resources.join(icon_file);
+    let icon = match fs::read(&icon) {
+        Ok(x) if x != SHIM_APP_ICON => Some(icon),
+        _ => None,
+    };
+
+    Ok(Shim {
+        path: path.to_owned(),
+        target: Target::MacOs,
+        name,
+        config,
+        doc_types,
+        type_decls,
+        bundle_id: string(&info_plist, "CFBundleIdentifier"),
+        version: string(&info_plist, "CFBundleVersion"),
+        generator_version: string(&info_plist, GENERATOR_VERSION_KEY)
+            .or_else(|| string(&info_plist, "CFBundleShortVersionString")),
+        icon,
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// The shim's name, from its desktop entry's or data directory's path.
+pub fn linux_name(path: &Path) -> Result<String, String> {
+    let name = if path.extension() == Some(OsStr::new("desktop")) {
+        path.file_stem()
+            .and_then(|x| x.to_str())
+            .and_then(|x| x.strip_prefix("echidna-"))
+    } else {
+        path.file_name().and_then(|x| x.to_str())
+    };
+    match name {
+        Some(x) if !x.is_empty() => Ok(x.to_owned()),
+        _ => bailf!("'{}' isn't an Echidna shim", path.display()),
+    }
+}
+
+fn load_linux(path: &Path) -> Result<Shim, String> {
+    let name = linux_name(path)?;
+    let desktop_file = desktop::applications_dir()?.join(desktop::desktop_file_name(name.as_ref()));
+    let data_dir = desktop::data_dir(name.as_ref())?;
+
+    // The rest of the shim is found by its name, so only an installed one can be read.
+    let expected = if path.is_dir() {
+        &data_dir
+    } else {
+        &desktop_file
+    };
+    let canonical = |x: &Path| fs::canonicalize(x).unwrap_or(x.to_owned());
+    if canonical(path) != canonical(expected) {
+        bailf!(
+            "'{}' isn't an installed shim, expected '{}'",
+            path.display(),
+            expected.display()
+        );
+    }
+
+    let contents = fs::read_to_string(&desktop_file)
+        .map_err(|e| format!("Error reading '{}': {e}", desktop_file.display()))?;
+    let entry = desktop::parse_entry(&contents);
+    let config = Config::load_from(&data_dir.join("config.json"))?;
+
+    let package = desktop::mime_dir()?
+        .join("packages")
+        .join(desktop::mime_package_file_name(name.as_ref()));
+    let decls = fs::read_to_string(package)
+        .map(|x| desktop::parse_mime_package(&x))
+        .unwrap_or_default();
+
+    // Extensions were mapped to MIME types, only those declared by the shim can be mapped back.
+    let mut exts = vec![];
+    let mut type_decls = vec![];
+    for decl in decls {
+        if decl.mime_type.starts_with(EXT_MIME_PREFIX) {
+            exts.extend(decl.extensions);
+        } else {
+            type_decls.push(decl);
+        }
+    }
+
+    let declared = |mime: &str| {
+        mime.starts_with(EXT_MIME_PREFIX) || type_decls.iter().any(|x| x.mime_type == mime)
+    };
+    let mime_types: Vec<String> = entry
+        .get("MimeType")
+        .map(|x| x.as_str())
+        .unwrap_or_default()
+        .split(';')
+        .filter(|x| !x.is_empty() && !declared(x))
+        .map(|x| x.to_owned())
+        .collect();
+
+    let mut groups = vec![];
+    if !mime_types.is_empty() || !exts.is_empty() {
+        groups.push(DocTypeGroup {
+            exts,
+            mime_types,
+            ..Default::default()
+        });
+    }
+
+    let largest = *HICOLOR_SIZES.last().unwrap();
+    let icon = desktop::icons_dir()?.join(desktop::hicolor_icon_path(name.as_ref(), largest));
+    let icon = match fs::read(&icon) {
+        Ok(x) if icon::default_hicolor_png(largest).as_ref() != Ok(&x) => Some(icon),
+        _ => None,
+    };
+
+    Ok(Shim {
+        path: desktop_file,
+        target: Target::Linux,
+        name: entry.get("Name").cloned().unwrap_or(name),
+        config,
+        doc_types: DocTypes(groups),
+        type_decls,
+        bundle_id: None,
+        version: None,
+        generator_version: entry.get("X-Echidna-Version").cloned(),
+        icon,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipe::Recipe;
+    use crate::xdg::TestHome;
+    use std::collections::BTreeMap;
+
+    // Generates a shim named Vim under the test home, returning its path.
+    fn gen_shim(home: &TestHome, target: Target) -> PathBuf {
+        let shim_bin = home.path().join("echidna-shim");
+        fs::write(&shim_bin, "shim").unwrap();
+        let recipe_path = home.path().join("Vim.toml");
+        fs::write(
+            &recipe_path,
+            format!("target = \"{target}\"\ncommand = \"vim\"\ndocs_exts = [\"txt\"]\n"),
+        )
+        .unwrap();
+
+        let dest = home.path().join("Applications");
+        fs::create_dir_all(&dest).unwrap();
+
+        let mut gen = Recipe::load(&recipe_path)
+            .unwrap()
+            .gen(&dest, &shim_bin)
+            .unwrap();
+        let path = gen.final_bundle_path().to_owned();
+        gen.save(false).unwrap();
+        path
+    }
+
+    // Every file under dir, by path.
+    fn read_tree(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+        let mut files = BTreeMap::new();
+        let mut dirs = vec![dir.to_owned()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.insert(path.clone(), fs::read(&path).unwrap());
+                }
+            }
+        }
+        files
+    }
+
+    // The files that differ between before and after.
+    fn changed(
+        before: &BTreeMap<PathBuf, Vec<u8>>,
+        after: &BTreeMap<PathBuf, Vec<u8>>,
+    ) -> Vec<PathBuf> {
+        let mut paths: Vec<_> = before.keys().chain(after.keys()).cloned().collect();
+        paths.sort();
+        paths.dedup();
+        paths
+            .into_iter()
+            .filter(|x| before.get(x) != after.get(x))
+            .collect()
+    }
+
+    #[test]
+    fn edit_macos_bumps_bundle_version() {
+        let home = TestHome::new();
+        let path = gen_shim(&home, Target::MacOs);
+        let shim = Shim::load(&path).unwrap();
+        assert_eq!(shim.version.as_deref(), Some("1"));
+
+        let changes = ShimEdit {
+            command: Some("nvim".to_owned()),
+            bundle_id: Some("com.example.nvim".to_owned()),
+            ..Default::default()
+        };
+        edit(&path, &changes).unwrap();
+        let shim = Shim::load(&path).unwrap();
+        assert_eq!(shim.version.as_deref(), Some("2"));
+        assert_eq!(shim.bundle_id.as_deref(), Some("com.example.nvim"));
+        assert_eq!(shim.config.command, "nvim");
+
+        let changes = ShimEdit {
+            group_open_by: Some(GroupBy::None),
+            ..Default::default()
+        };
+        edit(&path, &changes).unwrap();
+        assert_eq!(Shim::load(&path).unwrap().version.as_deref(), Some("3"));
+    }
+
+    #[test]
+    fn edit_rejects_invalid_bundle_id_before_writing() {
+        let home = TestHome::new();
+        let path = gen_shim(&home, Target::MacOs);
+        let before = read_tree(&path);
+
+        for id in ["", "com.example.vim_2", "com..example"] {
+            let changes = ShimEdit {
+                command: Some("nvim".to_owned()),
+                bundle_id: Some(id.to_owned()),
+                ..Default::default()
+            };
+            assert!(edit(&path, &changes).is_err(), "{id}");
+            assert_eq!(changed(&before, &read_tree(&path)), Vec::<PathBuf>::new());
+        }
+        assert!(edit(&path, &ShimEdit::default()).is_err());
+    }
+
+    #[test]
+    fn edit_linux_changes_only_comment_and_icons() {
+        let home = TestHome::new();
+        let path = gen_shim(&home, Target::Linux);
+        let data_home = xdg::data_home().unwrap();
+        let before = read_tree(&data_home);
+        let entry_before = fs::read_to_string(&path).unwrap();
+
+        let id_change = ShimEdit {
+            bundle_id: Some("com.example.vim".to_owned()),
+            ..Default::default()
+        };
+        assert!(edit(&path, &id_change).is_err());
+        assert_eq!(read_tree(&data_home), before);
+
+        let changes = ShimEdit {
+            command: Some("nvim".to_owned()),
+            icon: Some(ShimIcon {
+                tint: "red".parse().ok(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        edit(&path, &changes).unwrap();
+        let after = read_tree(&data_home);
+
+        let config_path = Shim::load(&path).unwrap().config_path().unwrap();
+        let icons_dir = desktop::icons_dir().unwrap();
+        let mut expected: Vec<_> = HICOLOR_SIZES
+            .iter()
+            .map(|x| icons_dir.join(desktop::hicolor_icon_path(OsStr::new("Vim"), *x)))
+            .chain([path.clone(), config_path])
+            .collect();
+        expected.sort();
+        assert_eq!(changed(&before, &after), expected);
+
+        // In the entry, only the comment.
+        let entry_after = fs::read_to_string(&path).unwrap();
+        let lines_changed: Vec<_> = entry_before
+            .lines()
+            .zip(entry_after.lines())
+            .filter(|(a, b)| a != b)
+            .collect();
+        assert_eq!(
+            lines_changed,
+            [(
+                "Comment=Open files with vim",
+                "Comment=Open files with nvim"
+            )]
+        );
+        assert_eq!(entry_before.lines().count(), entry_after.lines().count());
+    }
+}
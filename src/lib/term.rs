@@ -1,64 +1,129 @@
-use crate::bail;
 use crate::config::{Config, TerminalApp};
+use crate::generate::Target;
+use crate::{bail, bailf};
 
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::io::Write;
 use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
 use std::process::{Command, Stdio};
 
 use indexmap::{indexmap, IndexMap};
 use lazy_static::lazy_static;
 
-type RunInNewWindow = fn(config: &Config, bash: &OsStr) -> Result<(), String>;
+// One result per script (in order), or an error if the whole run failed, so none could be opened.
+pub type RunResults = Result<Vec<Result<(), String>>, String>;
+
+// Runs each script in its own new window.
+type RunInNewWindows = fn(config: &Config, scripts: &[OsString]) -> RunResults;
 
 lazy_static! {
-    // TERMINALS and TERM_ORDER must have exactly the same keys!!!
-    static ref TERMINALS: IndexMap<String, RunInNewWindow> = indexmap! {
-        "Terminal.app".into() => terminal_dot_app::run_in_new_window as RunInNewWindow,
-        "iTerm2".into() => iterm::run_in_new_window as RunInNewWindow,
+    // The first of each is the default.
+    static ref MACOS_TERMINALS: IndexMap<String, RunInNewWindows> = indexmap! {
+        "Terminal.app".into() => terminal_dot_app::run_in_new_windows as RunInNewWindows,
+        "iTerm2".into() => iterm::run_in_new_windows as RunInNewWindows,
+    };
+
+    static ref LINUX_TERMINALS: IndexMap<String, RunInNewWindows> = indexmap! {
+        "x-terminal-emulator".into() => linux::x_terminal_emulator as RunInNewWindows,
+        "gnome-terminal".into() => linux::gnome_terminal as RunInNewWindows,
+        "konsole".into() => linux::konsole as RunInNewWindows,
+        "xfce4-terminal".into() => linux::xfce4_terminal as RunInNewWindows,
+        "kitty".into() => linux::kitty as RunInNewWindows,
+        "alacritty".into() => linux::alacritty as RunInNewWindows,
+        "xterm".into() => linux::xterm as RunInNewWindows,
     };
 }
 
-pub fn run_in_new_window(config: &Config, bash: &OsStr) -> Result<(), String> {
-    match &config.terminal {
-        TerminalApp::Supported(name) => match TERMINALS.get(name) {
-            Some(fun) => fun(config, bash),
-            None => Err(format!(
-                "Terminal {} is not supported",
-                &config.terminal.name()
-            )),
+fn terminals(target: Target) -> &'static IndexMap<String, RunInNewWindows> {
+    match target {
+        Target::MacOs => &MACOS_TERMINALS,
+        Target::Linux => &LINUX_TERMINALS,
+    }
+}
+
+// Environment variable that selects the dry-run backend, overriding the config's dry_run. Its
+// value is the path to append records to, "-" for stdout.
+pub const DRY_RUN_KEY: &str = "ECH_SHIM_DRY_RUN";
+
+// Environment variables for the shim's log: its level (off unless set), and the file or directory
+// to write it to.
+pub const LOG_LEVEL_KEY: &str = "ECH_SHIM_LOG_LEVEL";
+pub const LOG_PATH_KEY: &str = "ECH_SHIM_LOG_PATH";
+
+// Opens one new window per script, all with a single invocation of the terminal backend. A
+// result is returned for each script, in the same order, unless the whole run failed.
+pub fn run_in_new_windows(config: &Config, scripts: &[OsString]) -> RunResults {
+    if scripts.is_empty() {
+        return Ok(vec![]);
+    }
+
+    if let Some(dst) = dry_run_dst(config) {
+        return dry_run::run_in_new_windows(config, scripts, &dst);
+    }
+
+    match (&config.terminal, Target::host()) {
+        (TerminalApp::Supported(name), target) => match terminals(target).get(name) {
+            Some(fun) => fun(config, scripts),
+            None => bailf!("Terminal {} is not supported", &config.terminal.name()),
         },
-        TerminalApp::Generic(_) => generic::run_in_new_window(config, bash),
+        (TerminalApp::Generic(_), Target::MacOs) => generic::run_in_new_windows(config, scripts),
+        (TerminalApp::Generic(_), Target::Linux) => linux::generic(config, scripts),
+    }
+}
+
+fn dry_run_dst(config: &Config) -> Option<PathBuf> {
+    match std::env::var_os(DRY_RUN_KEY) {
+        Some(x) if !x.is_empty() => Some(PathBuf::from(x)),
+        _ => config.dry_run.clone(),
     }
 }
 
+// The plain versions are for the host, the _for versions for generating shims for a target.
+
 pub fn default_terminal() -> &'static str {
-    TERMINALS.keys().next().unwrap().as_str()
+    default_terminal_for(Target::host())
+}
+
+pub fn default_terminal_for(target: Target) -> &'static str {
+    terminals(target).keys().next().unwrap().as_str()
 }
 
 pub fn supported_terminals() -> impl IntoIterator<Item = &'static str> {
-    TERMINALS.keys().map(|x| x.as_str())
+    supported_terminals_for(Target::host())
+}
+
+pub fn supported_terminals_for(target: Target) -> impl IntoIterator<Item = &'static str> {
+    terminals(target).keys().map(|x| x.as_str())
 }
 
 pub fn supported_terminals_string() -> String {
-    itertools::join(supported_terminals(), ", ")
+    supported_terminals_string_for(Target::host())
+}
+
+pub fn supported_terminals_string_for(target: Target) -> String {
+    itertools::join(supported_terminals_for(target), ", ")
 }
 
 pub fn is_supported(terminal: &str) -> bool {
-    TERMINALS.contains_key(terminal)
+    is_supported_for(Target::host(), terminal)
 }
 
-////////////////////////////////////////////////////////////////////////////////
+pub fn is_supported_for(target: Target, terminal: &str) -> bool {
+    terminals(target).contains_key(terminal)
+}
 
-type JxaResult = Result<(), String>;
+////////////////////////////////////////////////////////////////////////////////
 
-fn run_jxa(jxa: &OsStr, term: &OsStr, arg: &OsStr) -> JxaResult {
+// Runs jxa with the arguments, returning its stdout.
+fn run_jxa(jxa: &OsStr, args: &[&OsStr]) -> Result<String, String> {
     let cmd = "osascript";
-    let args = [OsStr::new("-lJavaScript"), OsStr::new("-"), term, arg];
 
     let mut child = Command::new::<&OsStr>(cmd.as_ref())
+        .args([OsStr::new("-lJavaScript"), OsStr::new("-")])
         .args(args)
         .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
         .map_err(|e| "Run error: ".to_owned() + e.to_string().as_str() + "\n")?;
@@ -82,7 +147,35 @@ fn run_jxa(jxa: &OsStr, term: &OsStr, arg: &OsStr) -> JxaResult {
         bail!(msg);
     }
 
-    Ok(())
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Runs a script that handles a batch of windows: argv[0] is term, and each script is its own
+// argument after that. The script must return a JSON array with an entry per script, null on
+// success or an error message.
+fn run_jxa_batch(jxa: &OsStr, term: &OsStr, scripts: &[OsString]) -> RunResults {
+    let mut args = vec![term];
+    args.extend(scripts.iter().map(|x| x.as_os_str()));
+
+    let stdout = run_jxa(jxa, &args)?;
+    let results: Vec<Option<String>> = serde_json::from_str(stdout.trim())
+        .map_err(|e| format!("Couldn't parse osascript output '{}': {e}", stdout.trim()))?;
+
+    if results.len() != scripts.len() {
+        bailf!(
+            "Expected {} results from osascript, got {}",
+            scripts.len(),
+            results.len()
+        );
+    }
+
+    Ok(results
+        .into_iter()
+        .map(|x| match x {
+            Some(e) => Err(e),
+            None => Ok(()),
+        })
+        .collect())
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -90,64 +183,83 @@ fn run_jxa(jxa: &OsStr, term: &OsStr, arg: &OsStr) -> JxaResult {
 // MacOS's built-in terminal
 mod terminal_dot_app {
     use crate::config::Config;
-    use std::ffi::OsStr;
+    use std::ffi::{OsStr, OsString};
 
     const JXA_RUN: &str = r#"
         function run(argv) {
-            if (argv.length !== 2) {
-                console.log("Expected exactly 2 arguments");
-                return;
+            if (argv.length < 2) {
+                console.log("Expected at least 2 arguments");
+                return "[]";
             }
 
             let app = Application("Terminal");
             if (!app.running()) {
                 app.activate();
             }
-            app.doScript(argv[1]);
+
+            let results = [];
+            for (let i = 1; i < argv.length; i++) {
+                try {
+                    app.doScript(argv[i]);
+                    results.push(null);
+                } catch (e) {
+                    results.push(e.toString());
+                }
+            }
+            return JSON.stringify(results);
         }
     "#;
 
-    pub fn run_in_new_window(_: &Config, script: &OsStr) -> Result<(), String> {
-        super::run_jxa(OsStr::new(JXA_RUN), OsStr::new(""), script)
+    pub fn run_in_new_windows(_: &Config, scripts: &[OsString]) -> super::RunResults {
+        super::run_jxa_batch(OsStr::new(JXA_RUN), OsStr::new(""), scripts)
     }
 }
 
 // iTerm2
 mod iterm {
     use crate::config::Config;
-    use std::ffi::OsStr;
+    use std::ffi::{OsStr, OsString};
 
     const JXA_RUN: &str = r#"
         function run(argv) {
-            if (argv.length !== 2) {
-                console.log("Expected exactly 2 arguments");
-                return;
+            if (argv.length < 2) {
+                console.log("Expected at least 2 arguments");
+                return "[]";
             }
 
             let app = Application("iTerm");
             if (!app.running()) {
                 app.activate();
             }
-            let window = app.createWindowWithDefaultProfile({});
-            window.currentSession().write({"text": argv[1]});
 
+            let results = [];
+            for (let i = 1; i < argv.length; i++) {
+                try {
+                    let window = app.createWindowWithDefaultProfile({});
+                    window.currentSession().write({"text": argv[i]});
+                    results.push(null);
+                } catch (e) {
+                    results.push(e.toString());
+                }
+            }
+            return JSON.stringify(results);
         }
     "#;
 
-    pub fn run_in_new_window(_: &Config, script: &OsStr) -> Result<(), String> {
-        super::run_jxa(OsStr::new(JXA_RUN), OsStr::new(""), script)
+    pub fn run_in_new_windows(_: &Config, scripts: &[OsString]) -> super::RunResults {
+        super::run_jxa_batch(OsStr::new(JXA_RUN), OsStr::new(""), scripts)
     }
 }
 
 mod generic {
     use crate::config::Config;
-    use std::ffi::OsStr;
+    use std::ffi::{OsStr, OsString};
 
     const JXA_RUN: &str = r#"
     function run(argv) {
-        if (argv.length !== 2) {
-            console.log("Expected exactly 2 arguments");
-            return;
+        if (argv.length < 2) {
+            console.log("Expected at least 2 arguments");
+            return "[]";
         }
 
         let app = Application(argv[0]);
@@ -155,22 +267,226 @@ mod generic {
         app.activate();
 
         let events = Application("System Events");
-        if (was_running) {
-            events.keystroke("n", {"using": "command down"});
+        let results = [];
+        for (let i = 1; i < argv.length; i++) {
+            try {
+                // A freshly launched terminal opens its own first window.
+                if (was_running || i > 1) {
+                    events.keystroke("n", {"using": "command down"});
+                }
+                delay(0.25);
+                events.keystroke(argv[i]);
+                results.push(null);
+            } catch (e) {
+                results.push(e.toString());
+            }
         }
-        delay(0.25);
-        events.keystroke(argv[1]);
+        return JSON.stringify(results);
     }
     "#;
 
-    pub fn run_in_new_window(config: &Config, script: &OsStr) -> Result<(), String> {
+    pub fn run_in_new_windows(config: &Config, scripts: &[OsString]) -> super::RunResults {
         // Assuming OsStr(ing) is backwards-compatible with ascii...
-        let mut script = script.to_owned();
-        script.push("\n");
-        super::run_jxa(
+        let scripts: Vec<_> = scripts
+            .iter()
+            .map(|x| {
+                let mut script = x.to_owned();
+                script.push("\n");
+                script
+            })
+            .collect();
+        super::run_jxa_batch(
             OsStr::new(JXA_RUN),
             OsStr::new(config.terminal.name()),
-            &script,
+            &scripts,
         )
     }
 }
+
+// Linux terminal emulators, which take the command to run as arguments. There's no batching, but
+// each window is just a process spawn.
+mod linux {
+    use super::RunResults;
+    use crate::bailf;
+    use crate::config::Config;
+    use std::ffi::OsString;
+    use std::io::ErrorKind;
+    use std::process::{Command, Stdio};
+
+    // Like Terminal.app, leave a shell behind once the command exits.
+    const KEEP_OPEN: &str = "; exec \"${SHELL:-bash}\"";
+
+    // prefix is the terminal and the arguments that precede the command to run. A terminal that
+    // isn't installed fails the whole run.
+    fn spawn_each(prefix: &[&str], scripts: &[OsString]) -> RunResults {
+        let mut results = vec![];
+        for script in scripts {
+            let mut script = script.clone();
+            script.push(KEEP_OPEN);
+
+            let res = Command::new(prefix[0])
+                .args(&prefix[1..])
+                .args(["bash", "-c"])
+                .arg(script)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn();
+            match res {
+                Ok(_) => results.push(Ok(())),
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    bailf!("Error running {}: {e}", prefix[0])
+                }
+                Err(e) => results.push(Err(format!("Error running {}: {e}", prefix[0]))),
+            }
+        }
+        Ok(results)
+    }
+
+    macro_rules! terminal {
+        ($name:ident, $($prefix:expr),+) => {
+            pub fn $name(_: &Config, scripts: &[OsString]) -> RunResults {
+                spawn_each(&[$($prefix),+], scripts)
+            }
+        };
+    }
+
+    terminal!(x_terminal_emulator, "x-terminal-emulator", "-e");
+    terminal!(gnome_terminal, "gnome-terminal", "--");
+    terminal!(konsole, "konsole", "-e");
+    terminal!(xfce4_terminal, "xfce4-terminal", "-x");
+    terminal!(kitty, "kitty");
+    terminal!(alacritty, "alacritty", "-e");
+    terminal!(xterm, "xterm", "-e");
+
+    // Most terminals accept -e followed by the command.
+    pub fn generic(config: &Config, scripts: &[OsString]) -> RunResults {
+        spawn_each(&[config.terminal.name(), "-e"], scripts)
+    }
+}
+
+// Doesn't open anything, just records each invocation (the terminal and whether it's generic, the
+// script, cwd and the environment the shim reads) as a line of JSON, for tests and debugging.
+mod dry_run {
+    use super::{RunResults, DRY_RUN_KEY, LOG_LEVEL_KEY, LOG_PATH_KEY};
+    use crate::config::{Config, TerminalApp};
+    use std::collections::BTreeMap;
+    use std::ffi::OsString;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::path::Path;
+
+    pub fn run_in_new_windows(config: &Config, scripts: &[OsString], dst: &Path) -> RunResults {
+        let cwd = std::env::current_dir()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        // PATH finds the terminal, HOME is where logs go by default.
+        let keys = ["PATH", "HOME", DRY_RUN_KEY, LOG_LEVEL_KEY, LOG_PATH_KEY];
+        let env: BTreeMap<_, _> = keys
+            .into_iter()
+            .filter_map(|k| Some((k, std::env::var_os(k)?.to_string_lossy().into_owned())))
+            .collect();
+
+        Ok(scripts
+            .iter()
+            .map(|script| {
+                let mut line = serde_json::json!({
+                    "backend": config.terminal.name(),
+                    "generic": matches!(config.terminal, TerminalApp::Generic(_)),
+                    "script": script.to_string_lossy(),
+                    "cwd": cwd,
+                    "env": env,
+                })
+                .to_string();
+                line.push('\n');
+                write_record(dst, line.as_bytes())
+            })
+            .collect())
+    }
+
+    fn write_record(dst: &Path, line: &[u8]) -> Result<(), String> {
+        if dst == Path::new("-") {
+            return std::io::stdout()
+                .lock()
+                .write_all(line)
+                .map_err(|e| format!("Error writing dry run record to stdout: {e}"));
+        }
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dst)
+            .and_then(|mut file| file.write_all(line))
+            .map_err(|e| format!("Error writing dry run record to '{}': {e}", dst.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GroupBy;
+    use crate::open;
+    use std::fs;
+    use std::path::Path;
+
+    // Opens paths through the dry-run backend, returning the results and the recorded windows'
+    // scripts.
+    fn dry_run(dir: &Path, group_open_by: GroupBy, paths: &[PathBuf]) -> (usize, Vec<String>) {
+        let dst = dir.join(format!("{group_open_by:?}.jsonl"));
+        let config = Config {
+            command: "vim -p".to_owned(),
+            group_open_by,
+            terminal: TerminalApp::Supported(default_terminal().to_owned()),
+            dry_run: Some(dst.clone()),
+            icon: None,
+        };
+        let scripts = open::scripts(&config, paths);
+        let results = run_in_new_windows(&config, &scripts).unwrap();
+        assert!(results.iter().all(Result::is_ok), "{results:?}");
+
+        let records = fs::read_to_string(&dst).unwrap_or_default();
+        let windows = records
+            .lines()
+            .map(|x| {
+                let record: serde_json::Value = serde_json::from_str(x).unwrap();
+                assert_eq!(record["backend"], default_terminal());
+                record["script"].as_str().unwrap().to_owned()
+            })
+            .collect();
+        (results.len(), windows)
+    }
+
+    #[test]
+    fn group_by_none_opens_a_window_per_file() {
+        let dir = tempdir::TempDir::new("echidna-test").unwrap();
+        let paths = [PathBuf::from("/docs/a.txt"), PathBuf::from("/docs/b c.txt")];
+
+        let (results, windows) = dry_run(dir.path(), GroupBy::None, &paths);
+        assert_eq!(results, 2);
+        assert_eq!(
+            windows,
+            [
+                "cd /docs; vim -p /docs/a.txt",
+                "cd /docs; vim -p $'/docs/b c.txt'"
+            ]
+        );
+    }
+
+    #[test]
+    fn group_by_all_opens_one_window() {
+        let dir = tempdir::TempDir::new("echidna-test").unwrap();
+        let paths = [PathBuf::from("/docs/a.txt"), PathBuf::from("/docs/b c.txt")];
+
+        let (results, windows) = dry_run(dir.path(), GroupBy::All, &paths);
+        assert_eq!(results, 1);
+        assert_eq!(windows, ["cd /docs; vim -p /docs/a.txt $'/docs/b c.txt'"]);
+    }
+
+    #[test]
+    fn no_files_open_no_windows() {
+        let dir = tempdir::TempDir::new("echidna-test").unwrap();
+        for group_open_by in [GroupBy::None, GroupBy::All] {
+            assert_eq!(dry_run(dir.path(), group_open_by, &[]), (0, vec![]));
+        }
+    }
+}
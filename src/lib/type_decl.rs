@@ -0,0 +1,268 @@
+use crate::bailf;
+use crate::generate::DocTypes;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+// File types a shim declares itself: either for extensions that don't have a registered type, or
+// the user's own (e.g. in-house formats). On Linux they become a shared-mime-info package, on
+// macOS UTI export (or for the user's own, import) declarations in the Info.plist.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypeDecl {
+    pub identifier: String, // UTI
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_conforms_to")]
+    pub conforms_to: Vec<String>, // UTIs
+    pub extensions: Vec<String>,
+    // Any image icon::load_image() accepts. macOS only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<PathBuf>,
+}
+
+fn default_conforms_to() -> Vec<String> {
+    vec!["public.data".to_owned()]
+}
+
+fn is_uti_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '.'
+}
+
+// Checks a Uniform Type Identifier: reverse-DNS, with only letters, digits, '-' and '.'.
+pub fn validate_uti(uti: &str) -> Result<(), String> {
+    if uti.is_empty() {
+        bailf!("Type identifier must not be empty");
+    }
+    if let Some(c) = uti.chars().find(|c| !is_uti_char(*c)) {
+        bailf!(
+            "Type identifier '{uti}' contains '{c}', only letters, digits, '-' and '.' are allowed"
+        );
+    }
+    if uti.split('.').any(|x| x.is_empty()) {
+        bailf!("Type identifier '{uti}' has an empty component");
+    }
+    Ok(())
+}
+
+impl TypeDecl {
+    pub fn for_ext(ext: &str) -> TypeDecl {
+        // UTIs are restricted to alphanumerics, '-' and '.', and a '.' would read as a
+        // separate component. MIME subtypes are more permissive, but may as well match.
+        let sanitized: String = ext
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+
+        TypeDecl {
+            identifier: format!("local.echidna.ext.{sanitized}"),
+            mime_type: format!("application/x-echidna-{sanitized}"),
+            description: format!(".{ext} file"),
+            conforms_to: default_conforms_to(),
+            extensions: vec![ext.to_owned()],
+            icon: None,
+        }
+    }
+
+    // Validates a user's declaration and fills in what was left out. A relative icon path is
+    // relative to base_dir.
+    pub fn normalized(mut self, base_dir: Option<&Path>) -> Result<TypeDecl, String> {
+        self.identifier = self.identifier.trim().to_owned();
+        if self.identifier.is_empty() {
+            bailf!("Type identifier must not be empty");
+        }
+        if !self.identifier.chars().all(is_uti_char) {
+            bailf!(
+                "Type identifier '{}' may only contain letters, digits, '-' and '.'",
+                self.identifier
+            );
+        }
+
+        self.extensions = self
+            .extensions
+            .iter()
+            .map(|x| x.trim().trim_start_matches('.').to_owned())
+            .filter(|x| !x.is_empty())
+            .collect();
+        if self.extensions.is_empty() {
+            bailf!(
+                "Type '{}' must have at least one extension",
+                self.identifier
+            );
+        }
+
+        self.conforms_to = self
+            .conforms_to
+            .iter()
+            .map(|x| x.trim().to_owned())
+            .filter(|x| !x.is_empty())
+            .collect();
+        if self.conforms_to.is_empty() {
+            self.conforms_to = default_conforms_to();
+        }
+
+        if self.description.trim().is_empty() {
+            self.description.clone_from(&self.identifier);
+        }
+        if self.mime_type.trim().is_empty() {
+            self.mime_type = format!("application/x-{}", self.identifier);
+        }
+
+        if let (Some(icon), Some(base_dir)) = (&self.icon, base_dir) {
+            self.icon = Some(base_dir.join(icon));
+        }
+
+        Ok(self)
+    }
+
+    // The icon's name in the bundle's Resources.
+    pub fn icon_file_name(&self) -> String {
+        format!("{}.icns", self.identifier)
+    }
+}
+
+// Reads a JSON array of declarations, e.g.
+// [{"identifier": "com.example.tfvars", "extensions": ["tfvars"], "conforms_to": ["public.text"]}]
+pub fn load_decls(path: &Path) -> Result<Vec<TypeDecl>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Error reading type declarations '{}': {e}", path.display()))?;
+    let decls: Vec<TypeDecl> = serde_json::from_str(&contents)
+        .map_err(|e| format!("Error parsing type declarations '{}': {e}", path.display()))?;
+
+    decls
+        .into_iter()
+        .map(|x| x.normalized(path.parent()))
+        .collect()
+}
+
+// Declarations for each of the extensions in doc_type that is_known rejects.
+pub fn unknown_ext_decls(doc_type: &DocTypes, is_known: impl Fn(&str) -> bool) -> Vec<TypeDecl> {
+    doc_type
+        .all_exts()
+        .iter()
+        .filter(|ext| *ext != "*" && !is_known(ext))
+        .map(|ext| TypeDecl::for_ext(ext))
+        .collect()
+}
+
+pub fn xml_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::desktop;
+    use crate::generate::DocTypeGroup;
+
+    fn decl(identifier: &str, extensions: &[&str]) -> TypeDecl {
+        TypeDecl {
+            identifier: identifier.to_owned(),
+            mime_type: String::new(),
+            description: String::new(),
+            conforms_to: vec![],
+            extensions: extensions.iter().map(|x| (*x).to_owned()).collect(),
+            icon: None,
+        }
+    }
+
+    #[test]
+    fn xml_escape_escapes_markup() {
+        assert_eq!(
+            xml_escape(r#"<a href="x">Tom & 'Jerry'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &apos;Jerry&apos;&lt;/a&gt;"
+        );
+        assert_eq!(xml_escape("plain.txt"), "plain.txt");
+    }
+
+    #[test]
+    fn mime_package_escapes_user_supplied_strings() {
+        let decl = TypeDecl {
+            mime_type: "application/x-a&b".to_owned(),
+            description: "Tom & <Jerry>".to_owned(),
+            ..decl("com.example.a-b", &["a&b", "<c>"])
+        };
+        let xml = desktop::render_mime_package(std::slice::from_ref(&decl));
+        assert!(xml.contains("type=\"application/x-a&amp;b\""), "{xml}");
+        assert!(
+            xml.contains("<comment>Tom &amp; &lt;Jerry&gt;</comment>"),
+            "{xml}"
+        );
+        assert!(xml.contains("pattern=\"*.a&amp;b\""), "{xml}");
+        assert!(xml.contains("pattern=\"*.&lt;c&gt;\""), "{xml}");
+
+        let [read] = desktop::parse_mime_package(&xml).try_into().unwrap();
+        assert_eq!(read.mime_type, decl.mime_type);
+        assert_eq!(read.description, decl.description);
+        assert_eq!(read.extensions, decl.extensions);
+    }
+
+    #[test]
+    fn normalized_fills_in_defaults() {
+        let decl = decl(" com.example.tfvars ", &[".tfvars", " ", "tfvars.json"])
+            .normalized(Some(Path::new("/base")))
+            .unwrap();
+        assert_eq!(decl.identifier, "com.example.tfvars");
+        assert_eq!(decl.extensions, ["tfvars", "tfvars.json"]);
+        assert_eq!(decl.conforms_to, ["public.data"]);
+        assert_eq!(decl.description, "com.example.tfvars");
+        assert_eq!(decl.mime_type, "application/x-com.example.tfvars");
+
+        let decl = TypeDecl {
+            icon: Some(PathBuf::from("icons/tf.png")),
+            ..decl
+        }
+        .normalized(Some(Path::new("/base")))
+        .unwrap();
+        assert_eq!(decl.icon, Some(PathBuf::from("/base/icons/tf.png")));
+    }
+
+    #[test]
+    fn normalized_rejects_invalid_declarations() {
+        for (identifier, extensions) in [
+            ("", &["txt"][..]),
+            ("com.example.a&b", &["txt"]),
+            ("com.example.<a>", &["txt"]),
+            ("com.example.tfvars", &[]),
+            ("com.example.tfvars", &[" ", "."]),
+        ] {
+            assert!(
+                decl(identifier, extensions).normalized(None).is_err(),
+                "{identifier} {extensions:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn for_ext_makes_a_valid_uti() {
+        let decl = TypeDecl::for_ext("foo.yaml&x");
+        assert_eq!(decl.identifier, "local.echidna.ext.foo-yaml-x");
+        assert_eq!(validate_uti(&decl.identifier), Ok(()));
+        assert_eq!(decl.mime_type, "application/x-echidna-foo-yaml-x");
+        assert_eq!(decl.extensions, ["foo.yaml&x"]);
+    }
+
+    #[test]
+    fn unknown_ext_decls_skips_known_extensions_and_any() {
+        let doc_type = DocTypes(vec![DocTypeGroup {
+            exts: vec!["txt".to_owned(), "bzl".to_owned(), "*".to_owned()],
+            ..Default::default()
+        }]);
+        let decls = unknown_ext_decls(&doc_type, |ext| ext == "txt");
+        assert_eq!(decls, [TypeDecl::for_ext("bzl")]);
+    }
+}
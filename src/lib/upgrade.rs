@@ -0,0 +1,172 @@
+use crate::misc::write_atomic;
+use crate::replace;
+use crate::shim::{self, Shim};
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Bringing existing shims up to date: their copy of echidna-shim, and their config.json, which may
+// have been written by hand. Everything else is left as it is.
+
+pub struct Upgrade {
+    pub path: PathBuf,
+    // Of the binary being replaced, and its replacement, None if it's too old to say.
+    pub from_version: Option<String>,
+    pub to_version: Option<String>,
+    // The shim's binary is newer than the replacement. It's only replaced if forced.
+    pub newer: bool,
+    pub binary: bool,
+    pub config: bool,
+    pub dry_run: bool,
+}
+
+impl Upgrade {
+    pub fn is_up_to_date(&self) -> bool {
+        !self.binary && !self.config && !self.newer
+    }
+
+    // Left as it is rather than downgraded.
+    pub fn is_skipped(&self) -> bool {
+        self.newer && !self.binary
+    }
+}
+
+impl fmt::Display for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.path.display())?;
+        if self.is_up_to_date() {
+            return write!(f, "up to date");
+        }
+
+        let version = |x: &Option<String>| x.clone().unwrap_or("unknown version".to_owned());
+        let (from, to) = (version(&self.from_version), version(&self.to_version));
+        if self.is_skipped() {
+            return write!(
+                f,
+                "shim {from} is newer than {to}, left as it is (pass --force to downgrade it)"
+            );
+        }
+
+        let mut changes = vec![];
+        if self.binary {
+            if self.newer {
+                changes.push(format!("shim {from} -> {to} (a downgrade)"));
+            } else if self.from_version.is_some() && self.from_version == self.to_version {
+                changes.push(format!("shim {to} rebuilt"));
+            } else {
+                changes.push(format!("shim {from} -> {to}"));
+            }
+        }
+        if self.config {
+            changes.push("config.json rewritten".to_owned());
+        }
+        if self.dry_run {
+            write!(f, "would upgrade, ")?;
+        }
+        write!(f, "{}", changes.join(", "))
+    }
+}
+
+// Orders versions like 1.2.10 and 1.3.0-beta.1 by their numbers, then a pre-release before its
+// release. Build metadata (after '+') is ignored. None if either isn't a version.
+pub fn compare_versions(a: &str, b: &str) -> Option<Ordering> {
+    let parse = |x: &str| {
+        let x = x.split('+').next().unwrap_or_default();
+        let (release, pre) = match x.split_once('-') {
+            Some((release, pre)) => (release, Some(pre.to_owned())),
+            None => (x, None),
+        };
+        let numbers: Option<Vec<u64>> = release.split('.').map(|x| x.parse().ok()).collect();
+        Some((numbers?, pre))
+    };
+    let ((a, a_pre), (b, b_pre)) = (parse(a)?, parse(b)?);
+
+    let len = a.len().max(b.len());
+    let padded = |x: Vec<u64>| x.into_iter().chain(std::iter::repeat(0)).take(len);
+    let numbers = padded(a).cmp(padded(b));
+    Some(numbers.then(match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => a.cmp(&b),
+    }))
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("Error reading '{}': {e}", path.display()))
+}
+
+// Replaces the shim's binary with shim_bin if they differ, and rewrites its config as the generator
+// would if it isn't already. A shim newer than shim_bin (or with a version when shim_bin has
+// none) is left as it is, config included, unless forced. With dry_run, only reports what would
+// change.
+pub fn upgrade(
+    path: &Path,
+    shim_bin: &Path,
+    force: bool,
+    dry_run: bool,
+) -> Result<Upgrade, String> {
+    let shim = Shim::load(path)?;
+
+    let binary_path = shim.binary_path()?;
+    let old_binary = read(&binary_path)?;
+    let new_binary = read(shim_bin)?;
+    let from_version = shim::embedded_version(&old_binary);
+    let to_version = shim::embedded_version(&new_binary);
+    let newer = match (&from_version, &to_version) {
+        (Some(from), Some(to)) => compare_versions(from, to) == Some(Ordering::Greater),
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
+
+    let config_path = shim.config_path()?;
+    let new_config = shim.config.to_json()?;
+    let (binary, config) = if newer && !force {
+        (false, false)
+    } else {
+        (
+            old_binary != new_binary,
+            String::from_utf8_lossy(&read(&config_path)?).trim() != new_config,
+        )
+    };
+
+    if !dry_run {
+        if binary {
+            replace::copy_into_place(shim_bin, &binary_path)?;
+        }
+        if config {
+            write_atomic(&config_path, new_config.as_bytes())?;
+        }
+    }
+
+    Ok(Upgrade {
+        path: path.to_owned(),
+        from_version,
+        to_version,
+        newer,
+        binary,
+        config,
+        dry_run,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_versions_orders_numbers_then_pre_releases() {
+        let cmp = compare_versions;
+        assert_eq!(cmp("0.2.0", "0.2.0"), Some(Ordering::Equal));
+        assert_eq!(cmp("0.2.0", "0.2"), Some(Ordering::Equal));
+        assert_eq!(cmp("0.10.0", "0.9.9"), Some(Ordering::Greater));
+        assert_eq!(cmp("1.2.3", "1.2.10"), Some(Ordering::Less));
+        assert_eq!(cmp("1.0.0-beta.1", "1.0.0"), Some(Ordering::Less));
+        assert_eq!(cmp("1.0.0-beta.2", "1.0.0-beta.1"), Some(Ordering::Greater));
+        assert_eq!(cmp("1.0.0+abc", "1.0.0+def"), Some(Ordering::Equal));
+        assert_eq!(cmp("1.x", "1.0"), None);
+        assert_eq!(cmp("", "1.0"), None);
+    }
+}
@@ -0,0 +1,97 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+// XDG Base Directory lookups, for the Linux target. Per the spec, relative paths in the
+// environment variables are invalid and ignored.
+
+#[cfg(test)]
+thread_local! {
+    // Tests run in parallel threads, so can't change the environment. Instead each may stand in
+    // its own home directory, see TestHome, where the XDG directories have their defaults.
+    static TEST_HOME: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+// The user's home directory.
+pub fn home_dir() -> Option<PathBuf> {
+    #[cfg(test)]
+    if let Some(home) = TEST_HOME.with_borrow(|x| x.clone()) {
+        return Some(home);
+    }
+    home::home_dir()
+}
+
+fn absolute_from_env(key: &str) -> Option<PathBuf> {
+    #[cfg(test)]
+    if TEST_HOME.with_borrow(|x| x.is_some()) {
+        return None;
+    }
+    std::env::var_os(key)
+        .map(PathBuf::from)
+        .filter(|x| x.is_absolute())
+}
+
+fn home_relative(key: &str, default: &str) -> Result<PathBuf, String> {
+    if let Some(path) = absolute_from_env(key) {
+        return Ok(path);
+    }
+
+    let mut path = home_dir().ok_or_else(|| format!("${key} isn't set and no $HOME"))?;
+    path.push(default);
+    Ok(path)
+}
+
+// $XDG_DATA_HOME, default ~/.local/share
+pub fn data_home() -> Result<PathBuf, String> {
+    home_relative("XDG_DATA_HOME", ".local/share")
+}
+
+// $XDG_CONFIG_HOME, default ~/.config
+pub fn config_home() -> Result<PathBuf, String> {
+    home_relative("XDG_CONFIG_HOME", ".config")
+}
+
+// $XDG_STATE_HOME, default ~/.local/state
+pub fn state_home() -> Result<PathBuf, String> {
+    home_relative("XDG_STATE_HOME", ".local/state")
+}
+
+// $XDG_DATA_DIRS, default /usr/local/share:/usr/share. Doesn't include data_home().
+pub fn data_dirs() -> Vec<PathBuf> {
+    let dirs = std::env::var_os("XDG_DATA_DIRS")
+        .filter(|x| !x.is_empty())
+        .unwrap_or_else(|| OsString::from("/usr/local/share:/usr/share"));
+
+    std::env::split_paths(&dirs)
+        .filter(|x| x.is_absolute())
+        .collect()
+}
+
+// Makes home the current thread's home directory, e.g. to share a TestHome with another thread.
+#[cfg(test)]
+pub(crate) fn use_test_home(home: &std::path::Path) {
+    TEST_HOME.set(Some(home.to_owned()));
+}
+
+// A temporary home directory for the current test's thread, until it's dropped.
+#[cfg(test)]
+pub(crate) struct TestHome(tempdir::TempDir);
+
+#[cfg(test)]
+impl TestHome {
+    pub fn new() -> TestHome {
+        let dir = tempdir::TempDir::new("echidna-test").unwrap();
+        use_test_home(dir.path());
+        TestHome(dir)
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        self.0.path()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestHome {
+    fn drop(&mut self) {
+        TEST_HOME.set(None);
+    }
+}
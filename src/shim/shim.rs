@@ -1,22 +1,18 @@
 use echidna_lib::bail;
-use echidna_lib::config::{Config, GroupBy, TerminalApp};
-use echidna_lib::term;
+use echidna_lib::config::{Config, TerminalApp};
+use echidna_lib::{open, term};
 
-use std::ffi::{OsStr, OsString};
-use std::os::unix::ffi::OsStringExt;
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 
 use core::str::FromStr;
 
-use cacao::appkit::{Alert, App, AppDelegate};
-use log::{error, info};
-use shell_quote::Bash;
+use clap::Parser;
+use log::error;
 use std::env::VarError;
-use url::Url;
 
 fn init_log() {
-    const LEVEL_KEY: &str = "ECH_SHIM_LOG_LEVEL";
-    const LEVEL_PATH: &str = "ECH_SHIM_LOG_PATH";
+    use term::{LOG_LEVEL_KEY as LEVEL_KEY, LOG_PATH_KEY as LEVEL_PATH};
 
     // Default filename if path given is a directory, default directory is $HOME, or no $HOME, /
     const DEFAULT_FILE: &str = "ech_shim_log.txt";
@@ -59,154 +55,185 @@ fn init_log() {
     }
 }
 
+// Shows an error to the user, however suits the platform.
+#[cfg(target_os = "macos")]
 fn modal<T: AsRef<str>, M: AsRef<str>>(title: T, msg: M) {
     let (title, msg) = (title.as_ref(), msg.as_ref());
     error!("modal {title}: {msg}");
-    Alert::new(title, msg).show();
+    cacao::appkit::Alert::new(title, msg).show();
 }
 
-// Extend an OsString with anything that implements AsRef<OsStr>.
-macro_rules! os_extend {
-    ($os_string:ident, $($vals:expr),*) => {{
-        $os_string.extend([$($vals.as_ref()),+]);
-    }};
-}
-
-// Concatenate anything that implements AsRef<OsStr> into an OsString.
-macro_rules! os_cat {
-    ($($vals:expr),*) => {{
-        let mut os_string = OsString::new();
-        os_extend!(os_string, $($vals),+);
-        os_string
-    }};
-}
-
-fn bash_quote<S: AsRef<OsStr>>(string: S) -> OsString {
-    let string = string.as_ref();
-    OsString::from_vec(Bash::quote(string))
+// There's no app to show a modal from, so stderr for the command line, and a notification for the
+// desktop (if there's a notification daemon).
+#[cfg(not(target_os = "macos"))]
+fn modal<T: AsRef<str>, M: AsRef<str>>(title: T, msg: M) {
+    let (title, msg) = (title.as_ref(), msg.as_ref());
+    error!("modal {title}: {msg}");
+    eprintln!("{title}: {msg}");
+    let _ = std::process::Command::new("notify-send")
+        .args(["--app-name=Echidna", title, msg])
+        .status();
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-struct EchidnaShimDelegate {
-    config: Config,
-}
+// Returns false if there was a known error that means the remaining windows (for
+// GropuBy::None) won't have worked either, so there's no point reporting them.
+fn report_error(config: &Config, e: &str) -> bool {
+    if e.contains("osascript is not allowed to send keystrokes") {
+        modal("Permissions Needed", "Accessibility permissions are needed for generic terminals. Enable them in System Setting -> Privacy & Security -> Accessibility.");
+        return false;
+    }
 
-impl EchidnaShimDelegate {
-    fn new(config: Config) -> Self {
-        Self { config }
+    if e.contains("Application can't be found") {
+        if let TerminalApp::Generic(name) = &config.terminal {
+            modal(
+                "Generic Terminal Not Found",
+                format!("Couldn't find generic terminal '{name}'"),
+            );
+            return false;
+        }
     }
 
-    // Returns false if there was a known error that means future runs
-    // (for GropuBy::None) won't work.
-    fn run_term(&self, bash: &OsStr) -> bool {
-        let Err(e) = term::run_in_new_window(&self.config, bash) else {
-            return true;
-        };
+    modal("Error", e);
+    true
+}
 
-        if e.contains("osascript is not allowed to send keystrokes") {
-            modal("Permissions Needed", "Accessibility permissions are needed for generic terminals. Enable them in System Setting -> Privacy & Security -> Accessibility.");
+// Opens the paths in the terminal, reporting each distinct failure once, e.g. just the one if none
+// could be opened. Returns whether they all opened.
+fn open_paths(config: &Config, paths: &[PathBuf]) -> bool {
+    let scripts = open::scripts(config, paths);
+    let results = match term::run_in_new_windows(config, &scripts) {
+        Ok(x) => x,
+        Err(e) => {
+            report_error(config, &e);
             return false;
         }
+    };
 
-        if e.contains("Application can't be found") {
-            if let TerminalApp::Generic(name) = &self.config.terminal {
-                modal(
-                    "Generic Terminal Not Found",
-                    format!("Couldn't find generic terminal '{name}'"),
-                );
-                return false;
-            }
+    let mut reported = vec![];
+    for e in results.into_iter().filter_map(Result::err) {
+        if reported.contains(&e) {
+            continue;
         }
-
-        modal("Error", &e);
-        true
+        if !report_error(config, &e) {
+            return false;
+        }
+        reported.push(e);
     }
+    reported.is_empty()
 }
 
-impl AppDelegate for EchidnaShimDelegate {
-    fn open_urls(&self, urls: Vec<Url>) {
-        info!("Got urls {urls:?}");
-
-        let paths: Vec<_> = urls
-            .into_iter()
-            .filter_map(|url| {
-                if url.scheme() != "file" {
-                    modal(
-                        "Error",
-                        format!(
-                            "Only 'file' schemes are supported, '{url}''s scheme is {}",
-                            url.scheme()
-                        ),
-                    );
-                    return None;
-                }
-
-                let path: PathBuf = match url.to_file_path() {
-                    Ok(x) => x,
-                    Err(_) => {
-                        modal("Error", format!("'{url}' has no path"));
-                        return None;
+////////////////////////////////////////////////////////////////////////////////
+
+// Launched by LaunchServices, files arrive as open URL events.
+#[cfg(target_os = "macos")]
+mod appkit {
+    use super::{modal, open_paths};
+    use echidna_lib::config::Config;
+    use echidna_lib::open;
+
+    use cacao::appkit::{App, AppDelegate};
+    use log::info;
+    use url::Url;
+
+    struct EchidnaShimDelegate {
+        config: Config,
+    }
+
+    impl AppDelegate for EchidnaShimDelegate {
+        fn open_urls(&self, urls: Vec<Url>) {
+            info!("Got urls {urls:?}");
+
+            let paths: Vec<_> = urls
+                .iter()
+                .filter_map(|url| match open::path_from_url(url) {
+                    Ok(x) => Some(x),
+                    Err(e) => {
+                        modal("Error", e);
+                        None
                     }
-                };
-                Some(path)
-            })
-            .collect();
+                })
+                .collect();
 
-        if paths.is_empty() {
+            open_paths(&self.config, &paths);
+
+            // In Swift I would quit by getting a reference to the shared NSApplication,
+            // but I don't see a way to do it with cacao. This doesn't seem to do
+            // any harm.
             std::process::exit(0);
         }
+    }
 
-        let mut cmd = OsString::new();
-        if let Some(parent) = paths[0].parent() {
-            let parent = bash_quote(parent);
-            cmd = os_cat!("cd ", &parent, "; ");
-        }
+    pub fn run(config: Config) {
+        App::new("com.lockerman.EchidnaShim", EchidnaShimDelegate { config }).run();
+    }
+}
 
-        match self.config.group_open_by {
-            GroupBy::All => {
-                // os_extend!(cmd, &self.config.command);
-                cmd.push(&self.config.command);
-                for path in paths {
-                    let path = bash_quote(path);
-                    os_extend!(cmd, " ", &path);
-                }
-                self.run_term(&cmd);
-            }
-            GroupBy::None => {
-                for path in paths {
-                    let path = bash_quote(path);
-                    let cmd2 = os_cat!(&cmd, &self.config.command, " ", &path);
-                    if !self.run_term(&cmd2) {
-                        break;
-                    }
-                }
-            }
+////////////////////////////////////////////////////////////////////////////////
+
+// Read by echidna-cli upgrade.
+#[used]
+static VERSION_MARKER: &str = echidna_lib::shim::VERSION_MARKER;
+
+// Launched from a desktop entry or the command line, files are arguments.
+#[derive(Parser, Debug)]
+#[command(version, about = "Opens files in a terminal, as configured by echidna")]
+struct Args {
+    /// Config to use instead of the shim's own
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Files to open, as paths or file:// URIs
+    files: Vec<OsString>,
+}
+
+fn load_config(path: Option<&Path>) -> Result<Config, String> {
+    let res = match path {
+        Some(path) => Config::load_from(path),
+        None => Config::load(),
+    };
+
+    res.inspect_err(|msg| modal("Error loading config", msg))
+}
+
+fn run_args(args: Args) -> Result<(), String> {
+    let config = load_config(args.config.as_deref())?;
+
+    let mut paths = vec![];
+    for file in &args.files {
+        match open::path_from_arg(file) {
+            Ok(x) => paths.push(x),
+            Err(e) => modal("Error", e),
         }
+    }
 
-        // In Swift I would quit by getting a reference to the shared NSApplication,
-        // but I don't see a way to do it with cacao. This doesn't seem to do
-        // any harm.
-        std::process::exit(0);
+    if !open_paths(&config, &paths) {
+        bail!("Not all files could be opened");
     }
+    Ok(())
 }
 
-fn main() -> Result<(), String> {
+fn run() -> Result<(), String> {
     init_log();
 
-    let config = match Config::load() {
-        Ok(x) => x,
-        Err(msg) => {
-            modal("Error loading config", &msg);
-            bail!(msg);
-        }
-    };
+    // Older macOS versions pass a process serial number to apps launched from Finder.
+    let args: Vec<OsString> = std::env::args_os()
+        .filter(|x| !x.to_string_lossy().starts_with("-psn_"))
+        .collect();
 
-    App::new(
-        "com.lockerman.EchidnaShim",
-        EchidnaShimDelegate::new(config),
-    )
-    .run();
+    #[cfg(target_os = "macos")]
+    if args.len() <= 1 {
+        appkit::run(load_config(None)?);
+        return Ok(());
+    }
 
-    Ok(())
+    run_args(Args::parse_from(args))
+}
+
+fn main() {
+    // Errors have already been shown with modal().
+    if run().is_err() {
+        std::process::exit(1);
+    }
 }
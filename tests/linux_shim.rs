@@ -0,0 +1,87 @@
+// Generates a Linux shim into temporary XDG directories with echidna-cli, then runs it with the
+// dry-run backend and checks what it would have opened.
+
+use echidna_lib::open::bash_quote;
+use echidna_lib::term::DRY_RUN_KEY;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use serde_json::Value;
+use tempdir::TempDir;
+
+fn run(command: &mut Command) {
+    let output = command.output().unwrap();
+    assert!(
+        output.status.success(),
+        "{command:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn generated_linux_shim_runs() {
+    let tmp = TempDir::new("echidna-test").unwrap();
+    let data_home = tmp.path().join("data");
+    let state_home = tmp.path().join("state");
+    let xdg = |command: &mut Command| {
+        command
+            .env("XDG_DATA_HOME", &data_home)
+            .env("XDG_STATE_HOME", &state_home)
+            .env_remove(DRY_RUN_KEY);
+    };
+
+    let mut generate = Command::new(env!("CARGO_BIN_EXE_echidna-cli"));
+    generate
+        .args([
+            "--target",
+            "linux",
+            "--group-open-by",
+            "none",
+            "--shim-path",
+        ])
+        .arg(env!("CARGO_BIN_EXE_echidna-shim"))
+        .args(["vim -p", "Vi"]);
+    xdg(&mut generate);
+    run(&mut generate);
+
+    assert!(data_home.join("applications/echidna-Vi.desktop").is_file());
+    let shim = data_home.join("echidna/Vi/Vi");
+    assert!(shim.is_file());
+
+    let docs = tmp.path().join("my docs");
+    fs::create_dir(&docs).unwrap();
+    let files = [docs.join("a.txt"), docs.join("b c.txt")];
+    let records = tmp.path().join("records.jsonl");
+
+    let mut open = Command::new(&shim);
+    xdg(&mut open);
+    open.args(&files)
+        .env(DRY_RUN_KEY, &records)
+        .env("ECHIDNA_TEST_UNRELATED", "1")
+        .current_dir(tmp.path());
+    run(&mut open);
+
+    let lines: Vec<Value> = fs::read_to_string(&records)
+        .unwrap()
+        .lines()
+        .map(|x| serde_json::from_str(x).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2, "one window per file: {lines:?}");
+
+    let quoted = |x: &Path| bash_quote(x).to_string_lossy().into_owned();
+    for (record, file) in lines.iter().zip(&files) {
+        assert_eq!(record["backend"], "x-terminal-emulator");
+        assert_eq!(record["generic"], false);
+        assert_eq!(
+            record["script"],
+            format!("cd {}; vim -p {}", quoted(&docs), quoted(file))
+        );
+        assert_eq!(record["cwd"], tmp.path().to_string_lossy().as_ref());
+
+        let env = record["env"].as_object().unwrap();
+        assert_eq!(env[DRY_RUN_KEY], records.to_string_lossy().as_ref());
+        assert!(!env.contains_key("ECHIDNA_TEST_UNRELATED"));
+    }
+}